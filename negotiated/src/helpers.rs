@@ -0,0 +1,192 @@
+use handlebars::{handlebars_helper, Context, Handlebars, Helper, HelperResult, JsonValue, Output, RenderContext};
+
+/// Registers the helper library on a handlebars registry:
+///
+///  - `url`: `{{url "host_group"}}` is `/host_groups`, `{{url "host_group" id}}` is `/host_group/<id>`, `{{url "host_group" id "edit"}}` is `/host_group/<id>/edit`
+///  - `date`: formats a timestamp (RFC 3339, `YYYY-MM-DD HH:MM:SS` or unix seconds), `{{date created format="%d/%m/%Y"}}`
+///  - `json` and `yaml`: pretty print a value. Strings holding JSON are parsed first, so variable values print structured
+///  - `pluralize`: `{{pluralize hosts "host"}}` is `host` or `hosts` depending on the count (or array length), with an optional explicit plural
+///  - `tree`: turns a flat list of items with `id` and `parent` fields into nested nodes with a `children` array, for recursive partials
+///  - `csrf_field`: hidden form input holding the CSRF token of the request
+pub fn register_helpers(handlebars: &mut Handlebars) {
+    handlebars.register_helper("url", Box::new(url_helper));
+    handlebars.register_helper("date", Box::new(date_helper));
+    handlebars.register_helper("json", Box::new(json_helper));
+    handlebars.register_helper("yaml", Box::new(yaml_helper));
+    handlebars.register_helper("pluralize", Box::new(pluralize_helper));
+    handlebars.register_helper("tree", Box::new(tree_helper));
+    handlebars.register_helper("csrf_field", Box::new(csrf_field));
+}
+
+fn scalar_string(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(value) => value.clone(),
+        value => value.to_string()
+    }
+}
+
+fn structured(value: &JsonValue) -> JsonValue {
+    match value {
+        JsonValue::String(text) => serde_json::from_str(text).unwrap_or_else(|_| value.clone()),
+        value => value.clone()
+    }
+}
+
+handlebars_helper!(url_helper: |resource: str, *args| {
+    match (args.get(1), args.get(2)) {
+        (None, _) | (Some(JsonValue::Null), _) => format!("/{}s", resource),
+        (Some(id), None) => format!("/{}/{}", resource, scalar_string(id)),
+        (Some(id), Some(action)) => format!("/{}/{}/{}", resource, scalar_string(id), scalar_string(action))
+    }
+});
+
+pub fn format_date(value: &JsonValue, format: &str) -> String {
+    let parsed = match value {
+        JsonValue::Number(seconds) => seconds.as_i64().and_then(|seconds| chrono::DateTime::from_timestamp(seconds, 0).map(|date| date.naive_utc())),
+        JsonValue::String(text) => chrono::DateTime::parse_from_rfc3339(text)
+            .map(|date| date.naive_utc())
+            .or_else(|_| chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f"))
+            .ok(),
+        _ => None
+    };
+    match parsed {
+        Some(date) => date.format(format).to_string(),
+        None if value.is_null() => String::new(),
+        None => scalar_string(value)
+    }
+}
+handlebars_helper!(date_helper: |value: Json, {format: str = "%Y-%m-%d %H:%M:%S"}| format_date(value, format));
+
+handlebars_helper!(json_helper: |value: Json| serde_json::to_string_pretty(&structured(value)).unwrap_or_default());
+
+handlebars_helper!(yaml_helper: |value: Json| serde_yaml::to_string(&structured(value)).unwrap_or_default());
+
+handlebars_helper!(pluralize_helper: |count: Json, singular: str, *args| {
+    let count = match count {
+        JsonValue::Array(items) => items.len() as f64,
+        JsonValue::Object(items) => items.len() as f64,
+        count => count.as_f64().unwrap_or(0.0)
+    };
+    match (count == 1.0, args.get(2).and_then(|plural| plural.as_str())) {
+        (true, _) => singular.to_string(),
+        (false, Some(plural)) => plural.to_string(),
+        (false, None) => format!("{}s", singular)
+    }
+});
+
+/// Nests `items` under their parent. Items whose parent is not in the list become roots.
+///
+/// Items in a parent cycle are unreachable from any root, and are left out.
+pub fn build_tree(items: &[JsonValue], id: &str, parent: &str) -> JsonValue {
+    fn nest(items: &[JsonValue], id: &str, parent: &str, item: &JsonValue) -> JsonValue {
+        let item_id = item.get(id).cloned().unwrap_or_default();
+        let children = items
+            .iter()
+            .filter(|child| child.get(parent) == Some(&item_id))
+            .map(|child| nest(items, id, parent, child))
+            .collect();
+        let mut node = item.clone();
+        if let JsonValue::Object(fields) = &mut node {
+            fields.insert("children".to_string(), JsonValue::Array(children));
+        }
+        node
+    }
+    let ids = items.iter().filter_map(|item| item.get(id)).collect::<Vec<&JsonValue>>();
+    JsonValue::Array(items
+        .iter()
+        .filter(|item| match item.get(parent) {
+            None | Some(JsonValue::Null) => true,
+            Some(parent_id) => !ids.contains(&parent_id)
+        })
+        .map(|root| nest(items, id, parent, root))
+        .collect())
+}
+handlebars_helper!(tree_helper: |items: array, {id: str = "id", parent: str = "parent"}| build_tree(items, id, parent));
+
+fn csrf_field(_: &Helper, _: &Handlebars, ctx: &Context, _: &mut RenderContext, out: &mut dyn Output) -> HelperResult {
+    let token = ctx.data().get(crate::CSRF_FIELD).and_then(|token| token.as_str()).unwrap_or_default();
+    out.write(&format!(r#"<input type="hidden" name="{}" value="{}">"#, crate::CSRF_FIELD, handlebars::html_escape(token)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn render(template: &str, data: JsonValue) -> String {
+        let mut handlebars = Handlebars::new();
+        register_helpers(&mut handlebars);
+        handlebars.render_template(template, &data).unwrap()
+    }
+
+    #[test]
+    fn url_builds_resource_paths() {
+        assert_eq!(render(r#"{{url "host_group"}}"#, json!({})), "/host_groups");
+        assert_eq!(render(r#"{{url "host_group" id}}"#, json!({ "id": 3 })), "/host_group/3");
+        assert_eq!(render(r#"{{url "host_group" id "edit"}}"#, json!({ "id": "3" })), "/host_group/3/edit");
+        assert_eq!(render(r#"{{url "host_group" id}}"#, json!({ "id": null })), "/host_groups");
+        assert_eq!(render(r#"{{url "host_group" id "edit"}}"#, json!({})), "/host_groups");
+    }
+
+    #[test]
+    fn date_formats_timestamps() {
+        assert_eq!(render("{{date created}}", json!({ "created": "2024-03-01T12:30:00+01:00" })), "2024-03-01 11:30:00");
+        assert_eq!(render("{{date created}}", json!({ "created": "2024-03-01 12:30:00.250" })), "2024-03-01 12:30:00");
+        assert_eq!(render(r#"{{date created format="%d/%m/%Y"}}"#, json!({ "created": 86400 })), "02/01/1970");
+        assert_eq!(render("{{date created}}", json!({ "created": null })), "");
+        assert_eq!(render("{{date created}}", json!({ "created": "yesterday" })), "yesterday");
+    }
+
+    #[test]
+    fn json_and_yaml_print_structured_values() {
+        assert_eq!(render("{{{json value}}}", json!({ "value": { "port": 80 } })), "{\n  \"port\": 80\n}");
+        assert_eq!(render("{{{json value}}}", json!({ "value": "[1,2]" })), "[\n  1,\n  2\n]");
+        assert_eq!(render("{{{json value}}}", json!({ "value": "plain" })), "\"plain\"");
+        assert_eq!(render("{{json value}}", json!({ "value": "<b>" })), "&quot;&lt;b&gt;&quot;");
+        assert_eq!(render("{{yaml value}}", json!({ "value": { "port": 80 } })), "port: 80\n");
+        assert_eq!(render("{{yaml value}}", json!({ "value": "{\"ports\": [80]}" })), "ports:\n- 80\n");
+    }
+
+    #[test]
+    fn pluralize_counts_numbers_and_collections() {
+        assert_eq!(render(r#"{{pluralize count "host"}}"#, json!({ "count": 0 })), "hosts");
+        assert_eq!(render(r#"{{pluralize count "host"}}"#, json!({ "count": 1 })), "host");
+        assert_eq!(render(r#"{{pluralize count "host"}}"#, json!({ "count": 2 })), "hosts");
+        assert_eq!(render(r#"{{pluralize hosts "host"}}"#, json!({ "hosts": [{ "id": 1 }] })), "host");
+        assert_eq!(render(r#"{{pluralize hosts "host"}}"#, json!({ "hosts": [] })), "hosts");
+        assert_eq!(render(r#"{{pluralize facts "fact"}}"#, json!({ "facts": { "os": "linux" } })), "fact");
+        assert_eq!(render(r#"{{pluralize count "child" "children"}}"#, json!({ "count": 0 })), "children");
+        assert_eq!(render(r#"{{pluralize count "child" "children"}}"#, json!({ "count": 1 })), "child");
+        assert_eq!(render(r#"{{pluralize count "host"}}"#, json!({ "count": "many" })), "hosts");
+    }
+
+    #[test]
+    fn tree_nests_items_under_their_parent() {
+        let items = json!({ "items": [
+            { "id": 1, "parent": null },
+            { "id": 2, "parent": 1 },
+            { "id": 3, "parent": 2 },
+            { "id": 4, "parent": 99 },
+            { "id": 5, "parent": 6 },
+            { "id": 6, "parent": 5 },
+            { "id": 7, "parent": 7 },
+        ] });
+        let template = "{{#each (tree items)}}{{> node}}{{/each}}";
+        let mut handlebars = Handlebars::new();
+        register_helpers(&mut handlebars);
+        handlebars.register_partial("node", "({{id}}{{#each children}} {{> node}}{{/each}})").unwrap();
+        // orphans become roots, items in a cycle are left out
+        assert_eq!(handlebars.render_template(template, &items).unwrap(), "(1 (2 (3)))(4)");
+        let renamed = json!({ "items": [{ "key": "a" }, { "key": "b", "up": "a" }] });
+        assert_eq!(render(r#"{{#each (tree items id="key" parent="up")}}{{key}}:{{#each children}}{{key}}{{/each}}{{/each}}"#, renamed), "a:b");
+        assert_eq!(render("{{#each (tree items)}}{{else}}empty{{/each}}", json!({ "items": [] })), "empty");
+    }
+
+    #[test]
+    fn csrf_field_holds_the_escaped_token() {
+        assert_eq!(render("{{csrf_field}}", json!({ crate::CSRF_FIELD: "a\"b" })), r#"<input type="hidden" name="csrf_token" value="a&quot;b">"#);
+        assert_eq!(render("{{#each items}}{{csrf_field}}{{/each}}", json!({ "items": [1], crate::CSRF_FIELD: "t" })), r#"<input type="hidden" name="csrf_token" value="t">"#);
+        assert_eq!(render("{{csrf_field}}", json!({})), r#"<input type="hidden" name="csrf_token" value="">"#);
+    }
+}
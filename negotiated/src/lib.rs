@@ -1,9 +1,126 @@
-use handlebars::Handlebars;
-use actix_web::{HttpRequest, HttpResponse, http::header::{ContentType, self}, body::BoxBody, error::BlockingError};
+use std::sync::Arc;
+use handlebars::{Handlebars, RenderError};
+use actix_web::{HttpRequest, HttpResponse, http::{StatusCode, header::{ContentType, HeaderValue, self}}, body::BoxBody, error::BlockingError, cookie::Cookie};
 use serde::{Deserialize, Serialize};
 
+pub mod helpers;
+
+/// Template rendered when neither the action template nor the error template of a response is registered
+pub const DEBUG_TEMPLATE: &str = "debug";
+/// Cookie carrying a flash message from a redirecting response to the next rendered page
+pub const FLASH_COOKIE: &str = "flash";
+/// Cookie holding the CSRF token, checked against the form field of the same name on form submissions
+pub const CSRF_COOKIE: &str = "csrf_token";
+/// Form field, and template data key, holding the CSRF token
+pub const CSRF_FIELD: &str = "csrf_token";
+
+/// Generates a random CSRF token
+pub fn csrf_token() -> String {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    (0..32).map(|_| format!("{:x}", rng.gen_range(0..16u8))).collect()
+}
+
+/// Number of template source lines shown around the offending line of a template error
+const ERROR_EXCERPT_CONTEXT: usize = 3;
+
 pub trait HandlebarsFactory: Send + Sync + 'static {
-    fn handlebars(&self) -> &Handlebars;
+    /// Current template registry. Returned as an `Arc` so the registry can be swapped while a response renders
+    fn handlebars(&self) -> Arc<Handlebars<'static>>;
+    /// Whether template errors are rendered as a detailed HTML page
+    fn dev_mode(&self) -> bool {
+        self.handlebars().dev_mode()
+    }
+    /// Source of a template, used to show an excerpt of the offending template on errors
+    fn template_source(&self, _name: &str) -> Option<String> {
+        None
+    }
+    /// Application settings exposed to templates as `config`
+    fn template_config(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+    /// Authenticated user of the request, exposed to templates as `user`
+    fn template_user(&self, _req: &HttpRequest) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+}
+
+fn template_excerpt(source: &str, line_no: Option<usize>) -> String {
+    match line_no {
+        Some(line_no) => source
+            .lines()
+            .enumerate()
+            .skip(line_no.saturating_sub(ERROR_EXCERPT_CONTEXT + 1))
+            .take(2 * ERROR_EXCERPT_CONTEXT + 1)
+            .map(|(i, line)| format!("<span{}>{:>5} | {}</span>\n",
+                if i + 1 == line_no { " class=\"error-line\"" } else { "" },
+                i + 1,
+                handlebars::html_escape(line)))
+            .collect::<String>(),
+        None => handlebars::html_escape(source)
+    }
+}
+
+/// Renders a template error as an HTML page containing excerpts of the templates involved.
+///
+/// `sources` holds the name and source of the templates to excerpt. Errors raised inside a partial
+/// block are reported by handlebars against the partial name, so the rendered template is usually
+/// included alongside the template named in the error.
+pub fn template_error_page(err: &RenderError, sources: &[(String, String)]) -> String {
+    let template_name = err.template_name.clone().unwrap_or_else(|| "-".to_string());
+    let excerpts = if sources.is_empty() {
+        "<p>Template source unavailable</p>".to_string()
+    } else {
+        sources
+            .iter()
+            .map(|(name, source)| format!("<h2>{}</h2>\n<pre>{}</pre>\n", handlebars::html_escape(name), template_excerpt(source, err.line_no)))
+            .collect::<String>()
+    };
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Template error: {template_name}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+pre {{ background: #f4f4f4; padding: 1em; }}
+.error-line {{ background: #fcc; font-weight: bold; }}
+</style>
+</head>
+<body>
+<h1>Error rendering template</h1>
+<dl>
+<dt>Template</dt><dd>{template_name}</dd>
+<dt>Line/Col</dt><dd>{line}/{column}</dd>
+<dt>Error</dt><dd>{desc}</dd>
+</dl>
+{excerpts}</body>
+</html>
+"#,
+        template_name = handlebars::html_escape(&template_name),
+        line = err.line_no.unwrap_or(0),
+        column = err.column_no.unwrap_or(0),
+        desc = handlebars::html_escape(&err.desc),
+        excerpts = excerpts)
+}
+
+/// Whether an `If-Match` or `If-None-Match` header value, `*` or a list of entity tags, matches `etag`.
+///
+/// `If-None-Match` uses the weak comparison, ignoring the `W/` prefix, `If-Match` the strong one
+pub fn etag_matches(header: &str, etag: &str, weak: bool) -> bool {
+    header.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate == etag || (weak && candidate.strip_prefix("W/") == Some(etag.trim_start_matches("W/")))
+    })
+}
+
+/// Whether a `GET` or `HEAD` request carries an `If-None-Match` header matching `etag`
+fn not_modified(req: &HttpRequest, etag: &str) -> bool {
+    matches!(*req.method(), actix_web::http::Method::GET | actix_web::http::Method::HEAD) && req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|if_none_match| if_none_match.to_str().ok())
+        .map(|if_none_match| etag_matches(if_none_match, etag, true))
+        .unwrap_or(false)
 }
 
 #[derive(Debug,Clone, Serialize, Deserialize)]
@@ -25,14 +142,107 @@ where HF: HandlebarsFactory
     pub payload: Option<Box<dyn erased_serde::Serialize + Send>>,
     pub error: Option<ResponderError>,
     #[serde(skip_serializing)]
-    pub handlebars_factory: Option<HF>
+    pub handlebars_factory: Option<HF>,
+    #[serde(skip_serializing)]
+    pub template: Option<String>,
+    #[serde(skip_serializing)]
+    pub error_template: Option<String>,
+    #[serde(skip_serializing)]
+    pub redirect: Option<String>,
+    #[serde(skip_serializing)]
+    pub flash: Option<String>,
+    #[serde(skip_serializing)]
+    pub context: Option<serde_json::Value>,
+    #[serde(skip_serializing)]
+    pub status_code: Option<StatusCode>,
+    #[serde(skip_serializing)]
+    pub etag: Option<String>,
 }
 
 impl<HF> Default for Responder<HF>
 where HF: HandlebarsFactory
 {
     fn default() -> Self {
-        Responder { status: ResponderStatus::Success, payload: None, error: None, handlebars_factory: None }
+        Responder { status: ResponderStatus::Success, payload: None, error: None, handlebars_factory: None, template: None, error_template: None, redirect: None, flash: None, context: None, status_code: None, etag: None }
+    }
+}
+
+impl<HF> Responder<HF>
+where HF: HandlebarsFactory
+{
+    /// Sets the template used to render a successful response (e.g. `host_group/show`)
+    pub fn with_template(mut self, template: &str) -> Self {
+        self.template = Some(template.to_string());
+        self
+    }
+    /// Error response with the `403 Forbidden` status, for requests denied by an authorization check
+    pub fn forbidden(err: anyhow::Error) -> Self {
+        Responder::<HF>::from(err).with_status_code(StatusCode::FORBIDDEN)
+    }
+    /// Sets the HTTP status of JSON and rendered HTML responses, which otherwise is `200 OK`
+    pub fn with_status_code(mut self, status_code: StatusCode) -> Self {
+        self.status_code = Some(status_code);
+        self
+    }
+    /// Sets the template used to render an error response (e.g. `host_group/error`)
+    pub fn with_error_template(mut self, template: &str) -> Self {
+        self.error_template = Some(template.to_string());
+        self
+    }
+    /// Redirects successful HTML responses to `location`, showing `flash` on the next rendered page.
+    ///
+    /// `{field}` placeholders in `location` are replaced with the matching payload field (e.g. `/host_group/{id}`)
+    pub fn with_redirect(mut self, location: &str, flash: &str) -> Self {
+        self.redirect = Some(location.to_string());
+        self.flash = Some(flash.to_string());
+        self
+    }
+    /// Sets the entity tag of a single resource response, sent in the `ETag` header, weak on rendered HTML pages.
+    ///
+    /// A `GET` whose `If-None-Match` header matches gets `304 Not Modified` instead of the payload
+    pub fn with_etag(mut self, etag: &str) -> Self {
+        self.etag = Some(etag.to_string());
+        self
+    }
+    /// Sets additional data, exposed to templates as `context`
+    pub fn with_context(mut self, context: serde_json::Value) -> Self {
+        self.context = Some(context);
+        self
+    }
+    /// Data handed to templates: the serialized response plus `flash`, `context`, `csrf_token`, `config` and `user`
+    pub fn template_data(&self, flash: Option<&str>, csrf_token: &str, config: serde_json::Value, user: serde_json::Value) -> serde_json::Result<serde_json::Value> {
+        let mut data = serde_json::to_value(self)?;
+        if let serde_json::Value::Object(map) = &mut data {
+            map.insert("flash".to_string(), flash.map(|flash| serde_json::Value::String(flash.to_string())).unwrap_or_default());
+            map.insert("context".to_string(), self.context.clone().unwrap_or_default());
+            map.insert(CSRF_FIELD.to_string(), serde_json::Value::String(csrf_token.to_string()));
+            map.insert("config".to_string(), config);
+            map.insert("user".to_string(), user);
+        }
+        Ok(data)
+    }
+    fn redirect_location(&self, location: &str) -> String {
+        match self.payload.as_ref().and_then(|payload| serde_json::to_value(payload).ok()) {
+            Some(serde_json::Value::Object(fields)) => fields.iter().fold(location.to_string(), |location, (key, value)| {
+                let value = match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    value => value.to_string()
+                };
+                location.replace(&format!("{{{}}}", key), &value)
+            }),
+            _ => location.to_string()
+        }
+    }
+    /// Name of the template to render, given the set of registered templates
+    pub fn template_name(&self, handlebars: &Handlebars) -> String {
+        match self.status {
+            ResponderStatus::Success => &self.template,
+            ResponderStatus::Error => &self.error_template,
+        }
+            .as_ref()
+            .filter(|template| handlebars.has_template(template))
+            .cloned()
+            .unwrap_or_else(|| DEBUG_TEMPLATE.to_string())
     }
 }
 
@@ -45,19 +255,85 @@ where HF: HandlebarsFactory
             None => "text/html",
             Some(accept) => accept.to_str().unwrap_or("text/html")
         };
+        let status_code = self.status_code.unwrap_or(StatusCode::OK);
+        let etag = match (&self.status, &self.etag) {
+            (ResponderStatus::Success, Some(etag)) => Some(etag.clone()),
+            _ => None
+        };
         if accept.contains("application/json") {
-            HttpResponse::Ok()
+            let mut response = HttpResponse::build(status_code);
+            if let Some(etag) = etag {
+                if not_modified(req, &etag) {
+                    return HttpResponse::NotModified().insert_header((header::ETAG, etag)).insert_header((header::VARY, "Accept")).finish();
+                }
+                response.insert_header((header::ETAG, etag)).insert_header((header::VARY, "Accept"));
+            }
+            response
                 .content_type(ContentType::json())
                 .json(self)
         } else {
-            let handlebars = &req.app_data::<actix_web::web::Data<HF>>().unwrap().handlebars();
-            match handlebars.render(
-                &format!("{}/{}", req.match_pattern().unwrap().split_off(1), req.method().as_str().to_lowercase()).replace("//", "/"),
-                &self) {
+            if let (ResponderStatus::Success, Some(location)) = (&self.status, &self.redirect) {
+                let mut response = HttpResponse::SeeOther()
+                    .insert_header((header::LOCATION, self.redirect_location(location)))
+                    .finish();
+                if let Some(Ok(cookie)) = self.flash.as_ref().map(|flash| HeaderValue::from_str(&Cookie::build(FLASH_COOKIE, flash.as_str()).path("/").finish().encoded().to_string())) {
+                    response.headers_mut().append(header::SET_COOKIE, cookie);
+                }
+                return response;
+            }
+            let flash = req.cookie(FLASH_COOKIE);
+            let csrf_cookie = req.cookie(CSRF_COOKIE);
+            // pages embed the user and the CSRF token besides the entity, so their tag is weak, and a pending flash message or a missing CSRF cookie always renders
+            let etag = etag.map(|etag| format!("W/{}", etag.trim_start_matches("W/")));
+            if let Some(etag) = &etag {
+                if flash.is_none() && csrf_cookie.is_some() && not_modified(req, etag) {
+                    return HttpResponse::NotModified().insert_header((header::ETAG, etag.as_str())).insert_header((header::VARY, "Accept")).finish();
+                }
+            }
+            let handlebars_factory = req.app_data::<actix_web::web::Data<HF>>().unwrap();
+            let handlebars = handlebars_factory.handlebars();
+            let template_name = self.template_name(&handlebars);
+            let csrf_token = csrf_cookie.as_ref().map(|cookie| cookie.value().to_string()).unwrap_or_else(csrf_token);
+            let data = match self.template_data(flash.as_ref().map(|cookie| cookie.value()), &csrf_token, handlebars_factory.template_config(), handlebars_factory.template_user(req)) {
+                Ok(data) => data,
+                Err(err) => return HttpResponse::InternalServerError()
+                    .content_type(ContentType::plaintext())
+                    .body(format!("Error serializing response: {}", err))
+            };
+            match handlebars.render(&template_name, &data) {
                     Ok(body) => {
-                        HttpResponse::Ok()
+                        let mut response = HttpResponse::build(status_code);
+                        if let Some(etag) = etag {
+                            response.insert_header((header::ETAG, etag)).insert_header((header::VARY, "Accept"));
+                        }
+                        let mut response = response
+                            .content_type(ContentType::html())
+                            .body(body);
+                        if let Some(mut cookie) = flash {
+                            cookie.set_path("/");
+                            let _ = response.add_removal_cookie(&cookie);
+                        }
+                        if csrf_cookie.is_none() {
+                            let _ = response.add_cookie(&Cookie::build(CSRF_COOKIE, csrf_token)
+                                .path("/")
+                                .http_only(true)
+                                .same_site(actix_web::cookie::SameSite::Strict)
+                                .finish());
+                        }
+                        response
+                    },
+                    Err(err) if handlebars_factory.dev_mode() => {
+                        let mut names = err.template_name.iter().cloned().collect::<Vec<String>>();
+                        if !names.contains(&template_name) {
+                            names.push(template_name);
+                        }
+                        let sources = names
+                            .into_iter()
+                            .filter_map(|name| handlebars_factory.template_source(&name).map(|source| (name, source)))
+                            .collect::<Vec<(String, String)>>();
+                        HttpResponse::InternalServerError()
                             .content_type(ContentType::html())
-                            .body(body)
+                            .body(template_error_page(&err, &sources))
                     },
                     Err(err) => {
                         HttpResponse::ServiceUnavailable()
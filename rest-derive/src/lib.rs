@@ -7,38 +7,162 @@ use syn::{parse_macro_input, DeriveInput, AttrStyle::Inner};
 fn extract_diesel_table_name(attr: &Attribute) -> Option<proc_macro2::TokenStream> {
     if let Inner(_bang) = attr.style { return None; }
     if attr.path.segments.is_empty() { return None; }
-    if attr.path.segments[0].ident.to_string() != "diesel".to_string() { return None; }
+    if attr.path.segments[0].ident != "diesel" { return None; }
     let mut token_iterator = attr.tokens.clone().into_iter();
     match token_iterator.next() {
-        Some(token) => match token {
-            proc_macro2::TokenTree::Group(group) => {
-                if let proc_macro2::Delimiter::Parenthesis = group.delimiter() {} else { return None; }
-                let mut stream_iterator = group.stream().into_iter();
-                match stream_iterator.next() {
-                    None => return None,
-                    Some(token) => match token {
-                        proc_macro2::TokenTree::Ident(ident) => {
-                            if ident.to_string() != "table_name".to_string() { return None; }
-                        },
-                        _ => return None
-                    }
-                };
-                match stream_iterator.next() {
-                    None => return None,
-                    Some(token) => match token {
-                        proc_macro2::TokenTree::Punct(punct) => {
-                            if punct.as_char() != '=' { return None; }
-                        }
-                        _ => return None
-                    }
-                };
-                let mut result = proc_macro2::TokenStream::new();
-                result.extend(stream_iterator);
-                Some(result)
-            },
-            _ => return None
+        Some(proc_macro2::TokenTree::Group(group)) => {
+            if let proc_macro2::Delimiter::Parenthesis = group.delimiter() {} else { return None; }
+            let mut stream_iterator = group.stream().into_iter();
+            match stream_iterator.next()? {
+                proc_macro2::TokenTree::Ident(ident) => {
+                    if ident != "table_name" { return None; }
+                },
+                _ => return None
+            };
+            match stream_iterator.next()? {
+                proc_macro2::TokenTree::Punct(punct) => {
+                    if punct.as_char() != '=' { return None; }
+                }
+                _ => return None
+            };
+            let mut result = proc_macro2::TokenStream::new();
+            result.extend(stream_iterator);
+            Some(result)
+        },
+        _ => None
+    }
+}
+
+fn snake_case(ident: &str) -> String {
+    ident.chars().enumerate().fold(String::new(), |mut result, (i, c)| {
+        if c.is_uppercase() && i > 0 { result.push('_'); }
+        result.extend(c.to_lowercase());
+        result
+    })
+}
+
+fn bool_attribute(attribute_hash: &HashMap<String, proc_macro2::TokenStream>, name: &str) -> bool {
+    match attribute_hash.get(name).and_then(|value| value.clone().into_iter().next()) {
+        None => false,
+        Some(proc_macro2::TokenTree::Ident(ident)) => ident == "true",
+        _ => panic!("Unexpected value for {} attribute", name),
+    }
+}
+
+fn resource_name(ident: &syn::Ident, attribute_hash: &HashMap<String, proc_macro2::TokenStream>) -> String {
+    match attribute_hash.get("resource") {
+        Some(resource) => resource.to_string().trim_matches('"').to_string(),
+        None => snake_case(&ident.to_string())
+    }
+}
+
+fn responder_redirect(ident: &syn::Ident, attribute_hash: &HashMap<String, proc_macro2::TokenStream>, action: &str) -> proc_macro2::TokenStream {
+    let resource = resource_name(ident, attribute_hash);
+    let location = match action {
+        "delete" => format!("/{}s", resource),
+        _ => format!("/{}/{{id}}", resource)
+    };
+    let mut flash = resource.replace('_', " ");
+    flash.replace_range(..1, &flash[..1].to_uppercase());
+    let flash = format!("{} {}", flash, match action {
+        "create" => "created",
+        "update" => "updated",
+        _ => "deleted"
+    });
+    quote! { .with_redirect(#location, #flash) }
+}
+
+fn responder_templates(ident: &syn::Ident, attribute_hash: &HashMap<String, proc_macro2::TokenStream>, action: &str) -> proc_macro2::TokenStream {
+    let resource = resource_name(ident, attribute_hash);
+    let template = match attribute_hash.get(&format!("template_{}", action)) {
+        Some(template) => template.clone(),
+        None => { let template = format!("{}/{}", resource, action); quote!{ #template } }
+    };
+    let error_template = match attribute_hash.get("template_error") {
+        Some(template) => template.clone(),
+        None => { let template = format!("{}/error", resource); quote!{ #template } }
+    };
+    quote! { .with_template(#template).with_error_template(#error_template) }
+}
+
+/// Call to the `RestAuthorize` hook, returning `403 Forbidden` on failure, or nothing when `authorize` is not set
+fn authorize_call(ident: &syn::Ident, attribute_hash: &HashMap<String, proc_macro2::TokenStream>, app_data: &proc_macro2::TokenStream, connection: &proc_macro2::TokenStream, hook: &str, arguments: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    if !bool_attribute(attribute_hash, "authorize") {
+        return quote! {};
+    }
+    let new_ident = syn::Ident::new(&format!("New{}", ident), ident.span());
+    let hook = syn::Ident::new(hook, ident.span());
+    quote! {
+        if let Err(err) = <#ident as rest::RestAuthorize<#ident, #new_ident, #app_data, #connection>>::#hook(&app_data, &mut db, principal.as_ref(), #arguments) {
+            return negotiated::Responder::<#app_data>::forbidden(err);
         }
-        None => return None
+    }
+}
+
+/// Call to the `RestAuthorize` hook from a `RestOperation`, failing with `OperationError::Forbidden` unless the hook gave another `OperationError`, or nothing when `authorize` is not set
+fn authorize_operation(ident: &syn::Ident, attribute_hash: &HashMap<String, proc_macro2::TokenStream>, app_data: &proc_macro2::TokenStream, connection: &proc_macro2::TokenStream, hook: &str, arguments: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    if !bool_attribute(attribute_hash, "authorize") {
+        return quote! {};
+    }
+    let new_ident = syn::Ident::new(&format!("New{}", ident), ident.span());
+    let hook = syn::Ident::new(hook, ident.span());
+    quote! {
+        <#ident as rest::RestAuthorize<#ident, #new_ident, #app_data, #connection>>::#hook(app_data, db, principal, #arguments).map_err(rest::OperationError::Forbidden)?;
+    }
+}
+
+/// Call to the `RestValidate` hook from a `RestOperation`, failing with `OperationError::Failed`, or nothing when `validate` is not set
+fn validate_operation(ident: &syn::Ident, attribute_hash: &HashMap<String, proc_macro2::TokenStream>, connection: &proc_macro2::TokenStream, hook: &str, arguments: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    if !bool_attribute(attribute_hash, "validate") {
+        return quote! {};
+    }
+    let new_ident = syn::Ident::new(&format!("New{}", ident), ident.span());
+    let hook = syn::Ident::new(hook, ident.span());
+    quote! {
+        <#ident as rest::RestValidate<#ident, #new_ident, #connection>>::#hook(db, #arguments).map_err(rest::OperationError::Failed)?;
+    }
+}
+
+/// `Crud` update or delete of a `RestOperation`, or the result of a creation, followed by the `Audit` hook call when `audit` is set
+fn crud_call(ident: &syn::Ident, attribute_hash: &HashMap<String, proc_macro2::TokenStream>, app_data: &proc_macro2::TokenStream, connection: &proc_macro2::TokenStream, action: &str) -> proc_macro2::TokenStream {
+    let new_ident = syn::Ident::new(&format!("New{}", ident), ident.span());
+    let crud = quote! { <#ident as rest::Crud<#ident, #new_ident, #connection>> };
+    let operation = match action {
+        "create" => quote! { result },
+        "update" => quote! { #crud::db_update(db, &merged_to_update) },
+        _ => quote! { #crud::db_delete(db, filtered_id) },
+    };
+    if !bool_attribute(attribute_hash, "audit") {
+        return operation;
+    }
+    let resource = resource_name(ident, attribute_hash);
+    let (audit_action, fetch_before, before, after) = match action {
+        "create" => (quote! { Create }, quote! {}, quote! { None }, quote! { Some(serde_json::to_value(&result)?) }),
+        "update" => (
+            quote! { Update },
+            quote! { let before = serde_json::to_value(#crud::db_fetch(db, merged_to_update.id)?)?; },
+            quote! { Some(before) },
+            quote! { Some(serde_json::to_value(&result)?) }
+        ),
+        // the deleted entity is returned by `db_delete`
+        _ => (quote! { Delete }, quote! {}, quote! { Some(serde_json::to_value(&result)?) }, quote! { None }),
+    };
+    quote! {
+        (|| -> anyhow::Result<#ident> {
+            #fetch_before
+            let result = #operation?;
+            <#app_data as rest::Audit<#connection>>::audit(app_data, db, rest::AuditEntry {
+                entity: #resource.to_string(),
+                entity_id: result.id,
+                entity_key: None,
+                action: rest::AuditAction::#audit_action,
+                before: #before,
+                after: #after,
+                principal: principal.cloned(),
+                request_id: request_id.0.clone(),
+            })?;
+            Ok(result)
+        })()
     }
 }
 
@@ -56,7 +180,7 @@ fn derive_rest_new(input: proc_macro::TokenStream, _pre: bool, _post: bool, _app
             Some(name) => name
         };
     new_struct.attrs = Vec::new();
-    new_struct.ident = syn::Ident::new( &format!("New{}", new_struct.ident.to_string()), new_struct.ident.span());
+    new_struct.ident = syn::Ident::new( &format!("New{}", new_struct.ident), new_struct.ident.span());
 
     match &mut new_struct.data {
         syn::Data::Struct(data_struct) => {
@@ -67,10 +191,10 @@ fn derive_rest_new(input: proc_macro::TokenStream, _pre: bool, _post: bool, _app
                             .named
                             .pairs()
                             .filter(|p| {
-                                p.value().ident.clone().unwrap_or_else(|| syn::Ident::new("", proc_macro2::Span::call_site())).to_string() != "id".to_string()
+                                p.value().ident.clone().unwrap_or_else(|| syn::Ident::new("", proc_macro2::Span::call_site())) != "id"
                             })
                             .fold(syn::punctuated::Punctuated::<syn::Field, syn::token::Comma>::new(), |mut punctuated, pair| {
-                                let field = pair.value().clone().clone();
+                                let field = (*pair.value()).clone();
                                 punctuated.push(field);
                                 punctuated
                             });
@@ -82,237 +206,252 @@ fn derive_rest_new(input: proc_macro::TokenStream, _pre: bool, _post: bool, _app
     }
     let output = quote!{
         #[derive(Debug, Clone, serde::Deserialize, Insertable)]
-        #[diesel(table_name = #diesel_table_name )]
+        #[diesel(table_name = #diesel_table_name, treat_none_as_default_value = false)]
         #new_struct
     };
     output.into()
 }
 
-fn derive_rest_collection(input: proc_macro::TokenStream, _pre: bool, _post: bool, app_data: &proc_macro2::TokenStream, connection: &proc_macro2::TokenStream) -> proc_macro::TokenStream {
+fn derive_rest_collection(input: proc_macro::TokenStream, _pre: bool, _post: bool, app_data: &proc_macro2::TokenStream, connection: &proc_macro2::TokenStream, attribute_hash: &HashMap<String, proc_macro2::TokenStream>) -> proc_macro::TokenStream {
     let DeriveInput { ident, .. } = parse_macro_input!(input);
+    let templates_list = responder_templates(&ident, attribute_hash, "list");
+    let authorize_list = authorize_call(&ident, attribute_hash, app_data, connection, "authorize_list", quote! {});
     let output = quote! {
         #[async_trait]
         impl RestCollection<rest::RestCollectionGetParameters, #app_data, #connection> for #ident {
-            async fn get(app_data: actix_web::web::Data<#app_data>, actix_web::web::Query(query_parameters): actix_web::web::Query<rest::RestCollectionGetParameters>) -> negotiated::Responder<#app_data> {
-                actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                    let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
+            async fn get(app_data: actix_web::web::Data<#app_data>, principal: rest::RequestPrincipal, actix_web::web::Query(query_parameters): actix_web::web::Query<rest::RestCollectionGetParameters>) -> negotiated::Responder<#app_data> {
+                let principal = principal.map(|principal| principal.into_inner());
+                negotiated::Responder::<#app_data>::from(actix_web::web::block(move || -> negotiated::Responder<#app_data> {
+                    let mut db = match <#app_data as rest::DbFactory<#connection>>::db(&app_data) {
+                        Ok(db) => db,
+                        Err(err) => return err.into()
+                    };
+                    #authorize_list
                     #ident::db_fetch_all(&mut db, query_parameters.q.unwrap_or_default(), None).into()
-                }).await.into()
+                }).await)#templates_list
             }
         }
     };
     output.into()
 }
-fn derive_rest(input: proc_macro::TokenStream, pre: bool, post: bool, app_data: &proc_macro2::TokenStream, connection: &proc_macro2::TokenStream) -> proc_macro::TokenStream {
+fn derive_rest(input: proc_macro::TokenStream, pre: bool, post: bool, app_data: &proc_macro2::TokenStream, connection: &proc_macro2::TokenStream, attribute_hash: &HashMap<String, proc_macro2::TokenStream>) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input);
     let DeriveInput { ident, .. } = input;
-    let new_ident = syn::Ident::new( &format!("New{}", ident.to_string()), ident.span() );
-    let output = match (pre, post) {
-        (true, true) => quote! {
-            #[async_trait]
-            impl Rest<#ident, #new_ident, #app_data, #connection> for #ident
-            where #ident: rest::RestPre<#ident, #new_ident, #app_data> + rest::RestPost<#ident, #new_ident>
-            {
-                async fn post(app_data: actix_web::web::Data<#app_data>, actix_web::web::Json(host_group): actix_web::web::Json<#new_ident>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::RestPost<#ident, #new_ident>>::post_post(
-                            &host_group,
-                            <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_insert(
-                                &mut db,
-                                &match <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_post(&app_data, &host_group) {
-                                    Ok(host_group) => host_group,
-                                    Err(err) => return err.into()
-                                }
-                            ).into()
-                        ).into()
-                    }).await.into()
-                }
-                async fn get(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::RestPost<#ident, #new_ident>>::post_get(
-                            id.clone(),
-                            <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_fetch(
-                                &mut db,
-                                match <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_get(&app_data, id.into_inner().try_into().unwrap()) {
-                                    Ok(id) => id,
-                                    Err(err) => return err.into()
-                                }
-                            ).into()
-                        ).into()
-                    }).await.into()
-                }
-                async fn put(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>, to_update: actix_web::web::Json<#ident>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        let (filtered_id, filtered_to_update) = match <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_put(&app_data, id.clone(), &to_update) {
-                            Ok(tuple) => tuple,
-                            Err(err) => return err.into()
-                        };
-                        let mut merged_to_update = filtered_to_update.clone();
-                        merged_to_update.id = id.clone();
-                        <#ident as rest::RestPost<#ident, #new_ident>>::post_put(
-                            id.clone(),
-                            &to_update,
-                            <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_update(&mut db, &merged_to_update).into()
-                        ).into()
-                    }).await.into()
-                }
-                async fn delete(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::RestPost<#ident, #new_ident>>::post_delete(
-                            id.clone(),
-                            <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_delete(
-                                &mut db,
-                                match <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_delete(&app_data, id.into_inner().try_into().unwrap()) {
-                                    Ok(id) => id,
-                                    Err(err) => return err.into()
-                                }
-                            ).into()
-                        ).into()
-                    }).await.into()
-                }
+    let templates_create = responder_templates(&ident, attribute_hash, "create");
+    let templates_show = responder_templates(&ident, attribute_hash, "show");
+    let templates_update = responder_templates(&ident, attribute_hash, "update");
+    let templates_delete = responder_templates(&ident, attribute_hash, "delete");
+    let redirect_create = responder_redirect(&ident, attribute_hash, "create");
+    let redirect_update = responder_redirect(&ident, attribute_hash, "update");
+    let redirect_delete = responder_redirect(&ident, attribute_hash, "delete");
+    let new_ident = syn::Ident::new( &format!("New{}", ident), ident.span() );
+    let pre_bound = if pre { quote! { + rest::RestPre<#ident, #new_ident, #app_data> } } else { quote! {} };
+    let post_bound = if post { quote! { + rest::RestPost<#ident, #new_ident, #app_data> } } else { quote! {} };
+    let context_bound = if bool_attribute(attribute_hash, "context") { quote! { + rest::RestContext<#ident, #connection> } } else { quote! {} };
+    let authorize_bound = if bool_attribute(attribute_hash, "authorize") { quote! { + rest::RestAuthorize<#ident, #new_ident, #app_data, #connection> } } else { quote! {} };
+    let validate_bound = if bool_attribute(attribute_hash, "validate") { quote! { + rest::RestValidate<#ident, #new_ident, #connection> } } else { quote! {} };
+    let validate_post = validate_operation(&ident, attribute_hash, connection, "validate_post", quote! { &filtered_new_object });
+    let validate_post_batch = validate_operation(&ident, attribute_hash, connection, "validate_post_batch", quote! { &filtered_new_object, batch });
+    let validate_put = validate_operation(&ident, attribute_hash, connection, "validate_put", quote! { filtered_id, &filtered_to_update });
+    let cascade = bool_attribute(attribute_hash, "cascade");
+    let cascade_bound = if cascade { quote! { + rest::RestCascade<#ident, #app_data, #connection> } } else { quote! {} };
+    let fetch_cascaded = if cascade { quote! { let cascaded = <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_fetch(db, filtered_id)?; } } else { quote! {} };
+    let cascade_put = if cascade { quote! {
+        if let Ok(updated) = &result {
+            <#ident as rest::RestCascade<#ident, #app_data, #connection>>::cascade_put(app_data, db, principal, request_id, &cascaded, updated)?;
+        }
+    } } else { quote! {} };
+    let authorize_post = authorize_operation(&ident, attribute_hash, app_data, connection, "authorize_post", quote! { &filtered_new_object });
+    let authorize_get = authorize_call(&ident, attribute_hash, app_data, connection, "authorize_get", quote! { filtered_id });
+    let authorize_put = authorize_operation(&ident, attribute_hash, app_data, connection, "authorize_put", quote! { filtered_id, &filtered_to_update });
+    let authorize_delete = authorize_operation(&ident, attribute_hash, app_data, connection, "authorize_delete", quote! { filtered_id });
+    let crud_create = crud_call(&ident, attribute_hash, app_data, connection, "create");
+    let crud_update = crud_call(&ident, attribute_hash, app_data, connection, "update");
+    let crud_delete = crud_call(&ident, attribute_hash, app_data, connection, "delete");
+    let pre_post = if pre { quote! { <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_post(app_data, principal, &new_object)? } } else { quote! { new_object.clone() } };
+    let pre_get = if pre { quote! {
+        match <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_get(&app_data, principal.as_ref(), id) {
+            Ok(id) => id,
+            Err(err) => return err.into()
+        }
+    } } else { quote! { id } };
+    let pre_put = if pre { quote! { <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_put(app_data, principal, id, &to_update)? } } else { quote! { (id, to_update.clone()) } };
+    let pre_delete = if pre { quote! { <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_delete(app_data, principal, id)? } } else { quote! { id } };
+    let post_post = if post { quote! { <#ident as rest::RestPost<#ident, #new_ident, #app_data>>::post_post(app_data, &new_object, result) } } else { quote! { result } };
+    let post_get = if post { quote! { <#ident as rest::RestPost<#ident, #new_ident, #app_data>>::post_get(&app_data, id, result) } } else { quote! { result } };
+    let post_put = if post { quote! { <#ident as rest::RestPost<#ident, #new_ident, #app_data>>::post_put(app_data, id, &to_update, result) } } else { quote! { result } };
+    let post_delete = if post { quote! { <#ident as rest::RestPost<#ident, #new_ident, #app_data>>::post_delete(app_data, id, result) } } else { quote! { result } };
+    let operation = quote! { <#ident as rest::RestOperation<#ident, #new_ident, #app_data, #connection>> };
+    let show_responder = if bool_attribute(attribute_hash, "context") { quote! {
+        match result {
+            Ok(object) => match <#ident as rest::RestContext<#ident, #connection>>::context(&mut db, &object) {
+                Ok(context) => negotiated::Responder::<#app_data>::from(anyhow::Result::<#ident>::Ok(object)).with_context(context),
+                Err(err) => err.into()
+            },
+            Err(err) => err.into()
+        }
+    } } else { quote! { result.into() } };
+    let output = quote! {
+        #[allow(unused_variables)]
+        impl rest::RestOperation<#ident, #new_ident, #app_data, #connection> for #ident
+        where #ident: Sized #pre_bound #post_bound #authorize_bound #validate_bound #cascade_bound
+        {
+            fn prepare_create(app_data: &actix_web::web::Data<#app_data>, db: &mut #connection, principal: Option<&rest::Principal>, new_object: &#new_ident, batch: &[#new_ident]) -> Result<#new_ident, rest::OperationError> {
+                let filtered_new_object = #pre_post;
+                #authorize_post
+                #validate_post
+                #validate_post_batch
+                Ok(filtered_new_object)
             }
-        },
-        (true, false) => quote! {
-            #[async_trait]
-            impl Rest<#ident, #new_ident, #app_data, #connection> for #ident
-            where #ident: rest::RestPre<#ident, #new_ident, #app_data>
-            {
-                async fn post(app_data: actix_web::web::Data<#app_data>, actix_web::web::Json(host_group): actix_web::web::Json<#new_ident>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_insert(
-                            &mut db,
-                            &match <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_post(&app_data, &host_group) {
-                                Ok(host_group) => host_group,
-                                Err(err) => return err.into()
-                            }
-                        ).into()
-                    }).await.into()
-                }
-                async fn get(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_fetch(
-                            &mut db,
-                            match <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_get(&app_data, id.into_inner().try_into().unwrap()) {
-                                Ok(id) => id,
-                                Err(err) => return err.into()
-                            }
-                        ).into()
-                    }).await.into()
-                }
-                async fn put(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>, to_update: actix_web::web::Json<#ident>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        let (filtered_id, filtered_to_update) = match <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_put(&app_data, id.clone(), &to_update) {
-                            Ok(tuple) => tuple,
-                            Err(err) => return err.into()
-                        };
-                        let mut merged_to_update = filtered_to_update.clone();
-                        merged_to_update.id = id.clone();
-                        <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_update(&mut db, &merged_to_update).into()
-                    }).await.into()
-                }
-                async fn delete(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_delete(
-                            &mut db,
-                            match <#ident as rest::RestPre<#ident, #new_ident, #app_data>>::pre_delete(&app_data, id.into_inner().try_into().unwrap()) {
-                                Ok(id) => id,
-                                Err(err) => return err.into()
-                            }
-                        ).into()
-                    }).await.into()
-                }
+            fn complete_create(app_data: &actix_web::web::Data<#app_data>, db: &mut #connection, principal: Option<&rest::Principal>, request_id: &rest::RequestId, new_object: &#new_ident, result: anyhow::Result<#ident>) -> Result<#ident, rest::OperationError> {
+                let result = #crud_create;
+                let result = #post_post;
+                Ok(result?)
             }
-        },
-        (false, true) => quote! {
-            #[async_trait]
-            impl Rest<#ident, #new_ident, #app_data, #connection> for #ident
-            where #ident: rest::RestPost<#ident, #new_ident>
-            {
-                async fn post(app_data: actix_web::web::Data<#app_data>, actix_web::web::Json(host_group): actix_web::web::Json<#new_ident>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::RestPost<#ident, #new_ident>>::post_post(
-                            &host_group,
-                            <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_insert(&mut db, &host_group).into()
-                        ).into()
-                    }).await.into()
-                }
-                async fn get(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::RestPost<#ident, #new_ident>>::post_get(
-                            id.clone(),
-                            <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_fetch(&mut db, id.into_inner().try_into().unwrap()).into()
-                        ).into()
-                    }).await.into()
-                }
-                async fn put(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>, to_update: actix_web::web::Json<#ident>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        let mut merged_to_update = to_update.clone();
-                        merged_to_update.id = id.clone();
-                        <#ident as rest::RestPost<#ident, #new_ident>>::post_put(
-                            id.clone(),
-                            &to_update,
-                            <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_update(&mut db, &merged_to_update).into()
-                        ).into()
-                    }).await.into()
-                }
-                async fn delete(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::RestPost<#ident, #new_ident>>::post_delete(
-                            id.clone(),
-                            <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_delete(&mut db, id.into_inner().try_into().unwrap()).into()
-                        ).into()
-                    }).await.into()
-                }
+            fn create(app_data: &actix_web::web::Data<#app_data>, db: &mut #connection, principal: Option<&rest::Principal>, request_id: &rest::RequestId, new_object: #new_ident) -> Result<#ident, rest::OperationError> {
+                let filtered_new_object = #operation::prepare_create(app_data, db, principal, &new_object, &[])?;
+                let result = <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_insert(db, &filtered_new_object);
+                #operation::complete_create(app_data, db, principal, request_id, &new_object, result)
             }
-        },
-        (false, false) => quote! {
-            #[async_trait]
-            impl Rest<#ident, #new_ident, #app_data, #connection> for #ident {
-                async fn post(app_data: actix_web::web::Data<#app_data>, actix_web::web::Json(host_group): actix_web::web::Json<#new_ident>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_insert(&mut db, &host_group).into()
-                    }).await.into()
-                }
-                async fn get(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_fetch(&mut db, id.into_inner().try_into().unwrap()).into()
-                    }).await.into()
-                }
-                async fn put(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>, to_update: actix_web::web::Json<#ident>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        let mut merged_to_update = to_update.clone();
-                        merged_to_update.id = id.clone();
-                        <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_update(&mut db, &merged_to_update).into()
-                    }).await.into()
-                }
-                async fn delete(app_data: actix_web::web::Data<#app_data>, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
-                    actix_web::web::block(move || -> negotiated::Responder<#app_data> {
-                        let mut db = <#app_data as rest::DbFactory<#connection>>::db(&app_data);
-                        <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_delete(&mut db, id.into_inner().try_into().unwrap()).into()
-                    }).await.into()
-                }
+            fn update(app_data: &actix_web::web::Data<#app_data>, db: &mut #connection, principal: Option<&rest::Principal>, request_id: &rest::RequestId, id: i32, to_update: #ident) -> Result<#ident, rest::OperationError> {
+                #operation::update_if_match(app_data, db, principal, request_id, &rest::IfMatch(None), id, to_update)
             }
-        },
+            fn update_if_match(app_data: &actix_web::web::Data<#app_data>, db: &mut #connection, principal: Option<&rest::Principal>, request_id: &rest::RequestId, if_match: &rest::IfMatch, id: i32, to_update: #ident) -> Result<#ident, rest::OperationError> {
+                let (filtered_id, filtered_to_update) = #pre_put;
+                #authorize_put
+                rest::check_if_match::<#ident, #new_ident, #connection>(db, filtered_id, if_match)?;
+                #validate_put
+                #fetch_cascaded
+                let mut merged_to_update = filtered_to_update.clone();
+                merged_to_update.id = filtered_id;
+                let result = #crud_update;
+                #cascade_put
+                let result = #post_put;
+                Ok(result?)
+            }
+            fn delete(app_data: &actix_web::web::Data<#app_data>, db: &mut #connection, principal: Option<&rest::Principal>, request_id: &rest::RequestId, id: i32) -> Result<#ident, rest::OperationError> {
+                #operation::delete_if_match(app_data, db, principal, request_id, &rest::IfMatch(None), id)
+            }
+            fn delete_if_match(app_data: &actix_web::web::Data<#app_data>, db: &mut #connection, principal: Option<&rest::Principal>, request_id: &rest::RequestId, if_match: &rest::IfMatch, id: i32) -> Result<#ident, rest::OperationError> {
+                let filtered_id = #pre_delete;
+                #authorize_delete
+                rest::check_if_match::<#ident, #new_ident, #connection>(db, filtered_id, if_match)?;
+                let result = #crud_delete;
+                let result = #post_delete;
+                Ok(result?)
+            }
+        }
+        #[async_trait]
+        impl Rest<#ident, #new_ident, #app_data, #connection> for #ident
+        where #ident: Sized #pre_bound #post_bound #context_bound #authorize_bound #validate_bound #cascade_bound
+        {
+            async fn post(app_data: actix_web::web::Data<#app_data>, principal: rest::RequestPrincipal, request_id: rest::RequestId, rest::JsonOrForm(new_object): rest::JsonOrForm<#new_ident>) -> negotiated::Responder<#app_data> {
+                let principal = principal.map(|principal| principal.into_inner());
+                negotiated::Responder::<#app_data>::from(actix_web::web::block(move || -> negotiated::Responder<#app_data> {
+                    let mut db = match <#app_data as rest::DbFactory<#connection>>::db(&app_data) {
+                        Ok(db) => db,
+                        Err(err) => return err.into()
+                    };
+                    match diesel::connection::Connection::transaction(&mut db, |db| #operation::create(&app_data, db, principal.as_ref(), &request_id, new_object)) {
+                        Ok(result) => {
+                            let etag = rest::etag(&result);
+                            negotiated::Responder::<#app_data>::from(anyhow::Result::<#ident>::Ok(result)).with_etag(&etag)
+                        },
+                        Err(err) => err.into()
+                    }
+                }).await)#templates_create #redirect_create
+            }
+            async fn get(app_data: actix_web::web::Data<#app_data>, principal: rest::RequestPrincipal, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
+                let id = id.into_inner();
+                let principal = principal.map(|principal| principal.into_inner());
+                negotiated::Responder::<#app_data>::from(actix_web::web::block(move || -> negotiated::Responder<#app_data> {
+                    let mut db = match <#app_data as rest::DbFactory<#connection>>::db(&app_data) {
+                        Ok(db) => db,
+                        Err(err) => return err.into()
+                    };
+                    let filtered_id = #pre_get;
+                    #authorize_get
+                    let result = <#ident as rest::Crud<#ident, #new_ident, #connection>>::db_fetch(&mut db, filtered_id);
+                    let result = #post_get;
+                    let etag = result.as_ref().ok().map(rest::etag);
+                    let responder: negotiated::Responder<#app_data> = #show_responder;
+                    match etag {
+                        Some(etag) => responder.with_etag(&etag),
+                        None => responder
+                    }
+                }).await)#templates_show
+            }
+            async fn put(app_data: actix_web::web::Data<#app_data>, principal: rest::RequestPrincipal, request_id: rest::RequestId, if_match: rest::IfMatch, id: actix_web::web::Path<i32>, rest::JsonOrForm(to_update): rest::JsonOrForm<#ident>) -> negotiated::Responder<#app_data> {
+                let id = id.into_inner();
+                let principal = principal.map(|principal| principal.into_inner());
+                negotiated::Responder::<#app_data>::from(actix_web::web::block(move || -> negotiated::Responder<#app_data> {
+                    let mut db = match <#app_data as rest::DbFactory<#connection>>::db(&app_data) {
+                        Ok(db) => db,
+                        Err(err) => return err.into()
+                    };
+                    let result = diesel::connection::Connection::transaction(&mut db, |db| #operation::update_if_match(&app_data, db, principal.as_ref(), &request_id, &if_match, id, to_update));
+                    match result {
+                        Ok(result) => {
+                            let etag = rest::etag(&result);
+                            negotiated::Responder::<#app_data>::from(anyhow::Result::<#ident>::Ok(result)).with_etag(&etag)
+                        },
+                        Err(err) => err.into()
+                    }
+                }).await)#templates_update #redirect_update
+            }
+            async fn delete(app_data: actix_web::web::Data<#app_data>, principal: rest::RequestPrincipal, request_id: rest::RequestId, if_match: rest::IfMatch, id: actix_web::web::Path<i32>) -> negotiated::Responder<#app_data> {
+                let id = id.into_inner();
+                let principal = principal.map(|principal| principal.into_inner());
+                negotiated::Responder::<#app_data>::from(actix_web::web::block(move || -> negotiated::Responder<#app_data> {
+                    let mut db = match <#app_data as rest::DbFactory<#connection>>::db(&app_data) {
+                        Ok(db) => db,
+                        Err(err) => return err.into()
+                    };
+                    let result = diesel::connection::Connection::transaction(&mut db, |db| #operation::delete_if_match(&app_data, db, principal.as_ref(), &request_id, &if_match, id));
+                    match result {
+                        Ok(result) => negotiated::Responder::<#app_data>::from(anyhow::Result::<#ident>::Ok(result)),
+                        Err(err) => err.into()
+                    }
+                }).await)#templates_delete #redirect_delete
+            }
+            async fn bulk_post(app_data: actix_web::web::Data<#app_data>, principal: rest::RequestPrincipal, request_id: rest::RequestId, new_objects: actix_web::web::Json<Vec<#new_ident>>) -> negotiated::Responder<#app_data> {
+                let new_objects = new_objects.into_inner();
+                let principal = principal.map(|principal| principal.into_inner());
+                negotiated::Responder::<#app_data>::from(actix_web::web::block(move || -> negotiated::Responder<#app_data> {
+                    let mut db = match <#app_data as rest::DbFactory<#connection>>::db(&app_data) {
+                        Ok(db) => db,
+                        Err(err) => return err.into()
+                    };
+                    rest::bulk_responder(rest::bulk_create::<#ident, #new_ident, #app_data, #connection>(&app_data, &mut db, principal.as_ref(), &request_id, new_objects))
+                }).await)
+            }
+            async fn bulk_patch(app_data: actix_web::web::Data<#app_data>, principal: rest::RequestPrincipal, request_id: rest::RequestId, to_update: actix_web::web::Json<Vec<rest::BulkUpdate<#ident>>>) -> negotiated::Responder<#app_data> {
+                let to_update = to_update.into_inner();
+                let principal = principal.map(|principal| principal.into_inner());
+                negotiated::Responder::<#app_data>::from(actix_web::web::block(move || -> negotiated::Responder<#app_data> {
+                    let mut db = match <#app_data as rest::DbFactory<#connection>>::db(&app_data) {
+                        Ok(db) => db,
+                        Err(err) => return err.into()
+                    };
+                    rest::bulk_responder(rest::bulk(&mut db, to_update, |db, rest::BulkUpdate { object, etag }| #operation::update_if_match(&app_data, db, principal.as_ref(), &request_id, &rest::IfMatch(etag), object.id, object)))
+                }).await)
+            }
+            async fn bulk_delete(app_data: actix_web::web::Data<#app_data>, principal: rest::RequestPrincipal, request_id: rest::RequestId, rest::BulkIds(ids): rest::BulkIds) -> negotiated::Responder<#app_data> {
+                let principal = principal.map(|principal| principal.into_inner());
+                negotiated::Responder::<#app_data>::from(actix_web::web::block(move || -> negotiated::Responder<#app_data> {
+                    let mut db = match <#app_data as rest::DbFactory<#connection>>::db(&app_data) {
+                        Ok(db) => db,
+                        Err(err) => return err.into()
+                    };
+                    rest::bulk_responder(rest::bulk(&mut db, ids, |db, (id, etag)| #operation::delete_if_match(&app_data, db, principal.as_ref(), &request_id, &rest::IfMatch(etag), id)))
+                }).await)
+            }
+        }
     };
     output.into()
 }
 
-fn hash_attributes(attrs: &Vec<Attribute>, ident: &str) -> HashMap<String, proc_macro2::TokenStream> {
+fn hash_attributes(attrs: &[Attribute], ident: &str) -> HashMap<String, proc_macro2::TokenStream> {
     attrs
         .iter()
         .filter(|attr| attr.path.is_ident(ident) )
@@ -325,7 +464,7 @@ fn hash_attributes(attrs: &Vec<Attribute>, ident: &str) -> HashMap<String, proc_
                         .fold((result, "".to_string()), |(mut result, current_ident): (HashMap<String, Vec::<proc_macro2::TokenTree>>, String), attr_token| {
                     match &attr_token {
                         proc_macro2::TokenTree::Ident(ident) => {
-                            if current_ident.len() == 0 {
+                            if current_ident.is_empty() {
                                 result.insert(ident.to_string(), Vec::new());
                                 return (result, ident.to_string());
                             } else {
@@ -334,7 +473,7 @@ fn hash_attributes(attrs: &Vec<Attribute>, ident: &str) -> HashMap<String, proc_
                         }
                         proc_macro2::TokenTree::Punct( punct ) => {
                             if punct.as_char() == ',' { return (result, "".to_string()); }
-                            if punct.as_char() != '=' || result.get(&current_ident).unwrap().len() > 0 { result.get_mut(&current_ident).unwrap().push(attr_token.clone()); }
+                            if punct.as_char() != '=' || !result.get(&current_ident).unwrap().is_empty() { result.get_mut(&current_ident).unwrap().push(attr_token.clone()); }
                         },
                         other => {
                             result.get_mut(&current_ident).unwrap().push(other.clone());
@@ -361,19 +500,19 @@ pub fn derive_rest_traits(input: proc_macro::TokenStream) -> proc_macro::TokenSt
     } = derive_input;
     let attribute_hash = hash_attributes(&attrs, "rest");
     let attribute_pre = match attribute_hash.get("pre").expect("pre attribute is mandatory in Rest derive").clone().into_iter().next().expect("pre attribute has no value in Rest derive") {
-        proc_macro2::TokenTree::Ident(ident) => { ident.to_string() == "true".to_string() },
+        proc_macro2::TokenTree::Ident(ident) => { ident == "true" },
         _ => panic!("Unexpected value for pre attribute in Rest derive"),
     };
     let attribute_post = match attribute_hash.get("post").expect("post attribute is mandatory in Rest derive").clone().into_iter().next().expect("post attribute has no value in Rest derive") {
-        proc_macro2::TokenTree::Ident(ident) => { ident.to_string() == "true".to_string() },
+        proc_macro2::TokenTree::Ident(ident) => { ident == "true" },
         _ => panic!("Unexpected value for post attribute in Rest derive"),
     };
     let attribute_app_data = attribute_hash.get("app_data").expect("pre attribute is mandatory in Rest derive");
     let attribute_connection = attribute_hash.get("connection").expect("connection attribute is mandatory in Rest derive");
     let mut result = proc_macro::TokenStream::new();
     result.extend(derive_rest_new(input.clone(), attribute_pre, attribute_post, attribute_app_data, attribute_connection));
-    result.extend(derive_rest_collection(input.clone(), attribute_pre, attribute_post, attribute_app_data, attribute_connection));
-    result.extend(derive_rest(input.clone(), attribute_pre, attribute_post, attribute_app_data, attribute_connection));
+    result.extend(derive_rest_collection(input.clone(), attribute_pre, attribute_post, attribute_app_data, attribute_connection, &attribute_hash));
+    result.extend(derive_rest(input.clone(), attribute_pre, attribute_post, attribute_app_data, attribute_connection, &attribute_hash));
     result
 }
 
@@ -388,14 +527,13 @@ pub fn derive_crud(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let attribute_hash = hash_attributes(&attrs, "crud");
     let table_name = attribute_hash.get("table_name").expect("table_name attribute is mandatory in Crud derive");
     let connection = attribute_hash.get("connection").expect("connection attribute is mandatory in Crud derive");
-    let new_ident = proc_macro2::Ident::new( &format!("New{}", ident.to_string()), ident.span().clone() );
-    let default_search_field = proc_macro2::TokenStream::from_iter(vec![proc_macro2::TokenTree::Ident(proc_macro2::Ident::new("name", proc_macro2::Span::call_site()))].into_iter());
+    let new_ident = proc_macro2::Ident::new( &format!("New{}", ident), ident.span() );
+    let default_search_field = proc_macro2::TokenStream::from_iter(vec![proc_macro2::TokenTree::Ident(proc_macro2::Ident::new("name", proc_macro2::Span::call_site()))]);
     let search_field = if attribute_hash.contains_key("search_field") { 
         attribute_hash.get("search_field").unwrap()
     } else {
         &default_search_field
     };
-    println!("{:?}", search_field);
     let output = quote!{
         impl rest::Crud<#ident, #new_ident, #connection> for #ident {
             fn db_insert(db: &mut #connection, to_insert: &#new_ident) -> anyhow::Result<#ident> {
@@ -403,6 +541,18 @@ pub fn derive_crud(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                     .values(to_insert)
                     .get_result::<#ident>(db)?)
             }
+            fn db_insert_all(db: &mut #connection, to_insert: &[#new_ident]) -> anyhow::Result<Vec<#ident>> {
+                // SQLite multi-row inserts cannot return rows: the inserted rows are the last ones, as ids are assigned in sequence
+                let count = diesel::insert_into(#table_name::table)
+                    .values(to_insert)
+                    .execute(db)?;
+                let mut inserted = #table_name::table
+                    .order(crate::#table_name::dsl::id.desc())
+                    .limit(count as i64)
+                    .load::<#ident>(db)?;
+                inserted.reverse();
+                Ok(inserted)
+            }
             fn db_update(db: &mut #connection, to_update: &#ident) -> anyhow::Result<#ident> {
                 Ok(diesel::update(#table_name::table)
                     .filter(crate::#table_name::dsl::id.eq(to_update.id))
@@ -417,7 +567,7 @@ pub fn derive_crud(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
             }
             fn db_fetch(db: &mut #connection, id: i32) -> anyhow::Result<#ident> {
                 Ok(#table_name::table
-                    .filter(crate::schema::host_group::dsl::id.eq(id))
+                    .filter(crate::#table_name::dsl::id.eq(id))
                     .first(db)?)
             }
             fn db_delete(db: &mut #connection, id: i32) -> anyhow::Result<#ident> {
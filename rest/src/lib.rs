@@ -1,45 +1,347 @@
+use std::{future::Future, pin::Pin};
 use async_trait::async_trait;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, body::MessageBody, dev::{Payload, ServiceRequest, ServiceResponse}, error::{ErrorBadRequest, ErrorForbidden, ErrorUnsupportedMediaType}, http::header::{HeaderName, HeaderValue}, middleware::Next, web::Bytes};
 use negotiated::Responder;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
 
+/// Authenticated caller of a request, stored in the request extensions by the application authentication middleware
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Principal {
+    /// User id
+    pub user: i32,
+    /// User name
+    pub name: String,
+    /// API token id, when authenticated with a bearer token rather than a session
+    pub token: Option<i32>,
+}
+/// Principal extractor of the `Rest` handlers. `None` when the application does not authenticate the request
+pub type RequestPrincipal = Option<actix_web::web::ReqData<Principal>>;
+
+/// Header carrying the request id, read from the request when the client sets it and echoed in the response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Identifier of a request, tying together the audit entries written while serving it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestId(pub String);
+impl RequestId {
+    fn of(req: &HttpRequest) -> RequestId {
+        if let Some(request_id) = req.extensions().get::<RequestId>() {
+            return request_id.clone();
+        }
+        let request_id = req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= 128)
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+        RequestId(request_id)
+    }
+}
+impl FromRequest for RequestId {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        std::future::ready(Ok(RequestId::of(req)))
+    }
+}
+
+/// Middleware assigning a `RequestId` to every request, and returning it in the `X-Request-Id` response header
+pub async fn request_id(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let RequestId(request_id) = RequestId::of(req.request());
+    let mut response = next.call(req).await?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    Ok(response)
+}
+
+/// Header carrying the CSRF token of state-changing requests without a form body
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Middleware checking the CSRF token of state-changing requests authenticated with a session cookie rather than an API token,
+/// unless they are JSON, which browsers do not send across sites. The token must match the CSRF cookie set by `negotiated::Responder`,
+/// and comes from the `X-CSRF-Token` header or the CSRF field of a form body. Must run inside the authentication middleware
+pub async fn csrf(mut req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = req.extensions().get::<Principal>().is_some_and(|principal| principal.token.is_none());
+    let safe = matches!(*req.method(), actix_web::http::Method::GET | actix_web::http::Method::HEAD | actix_web::http::Method::OPTIONS);
+    if !session || safe || req.content_type() == "application/json" {
+        return next.call(req).await;
+    }
+    let cookie = req.cookie(negotiated::CSRF_COOKIE).map(|cookie| cookie.value().to_string());
+    let mut token = req.headers()
+        .get(CSRF_HEADER)
+        .and_then(|token| token.to_str().ok())
+        .map(|token| token.to_string());
+    if token.is_none() && req.content_type() == "application/x-www-form-urlencoded" {
+        let bytes = req.extract::<Bytes>().await?;
+        token = serde_urlencoded::from_bytes::<Vec<FormField>>(&bytes)
+            .map_err(ErrorBadRequest)?
+            .into_iter()
+            .find(|(key, _)| key == negotiated::CSRF_FIELD)
+            .map(|(_, token)| token);
+        req.set_payload(Payload::from(bytes));
+    }
+    match (cookie, token) {
+        (Some(cookie), Some(token)) if cookie == token => next.call(req).await,
+        _ => Err(ErrorForbidden("Invalid CSRF token")),
+    }
+}
+
+/// Entity tag of an object: hash of its JSON serialization, so that it changes with any field
+pub fn etag<T: Serialize>(object: &T) -> String {
+    let digest = Sha256::digest(serde_json::to_vec(object).unwrap_or_default());
+    format!("\"{}\"", digest[..16].iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+}
+
+/// `If-Match` header of a request, if any
+pub struct IfMatch(pub Option<String>);
+impl FromRequest for IfMatch {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let if_match = req.headers()
+            .get(actix_web::http::header::IF_MATCH)
+            .map(|if_match| if_match.to_str().map(|if_match| if_match.to_string()).map_err(ErrorBadRequest))
+            .transpose();
+        std::future::ready(if_match.map(IfMatch))
+    }
+}
+
+/// Fails with `OperationError::PreconditionFailed` unless the current entity `id` matches the `If-Match` header, when there is one
+pub fn check_if_match<T, NewT, Connection>(db: &mut Connection, id: i32, if_match: &IfMatch) -> Result<(), OperationError>
+where T: Crud<T, NewT, Connection> + Serialize, Connection: diesel::connection::Connection
+{
+    let Some(header) = &if_match.0 else { return Ok(()) };
+    let current = T::db_fetch(db, id).map_err(|err| OperationError::PreconditionFailed(err.context(format!("If-Match given for missing entity {}", id))))?;
+    if !negotiated::etag_matches(header, &etag(&current), false) {
+        return Err(OperationError::PreconditionFailed(anyhow::anyhow!("Entity {} was modified since it was read: its ETag is {}", id, etag(&current))));
+    }
+    Ok(())
+}
+
+/// Kind of change recorded by the `Audit` hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+        })
+    }
+}
+/// Change made through `Crud`, with the state of the entity before and after it
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    /// Resource name of the entity
+    pub entity: String,
+    pub entity_id: i32,
+    /// Second part of the id of entities identified by a pair, such as the variable of a variable value
+    pub entity_key: Option<i32>,
+    pub action: AuditAction,
+    /// Entity before the change, `None` on creation
+    pub before: Option<serde_json::Value>,
+    /// Entity after the change, `None` on deletion
+    pub after: Option<serde_json::Value>,
+    pub principal: Option<Principal>,
+    pub request_id: String,
+}
+/// Audit hook, enabled with `audit=true` in the `Rest` derive and implemented by the application data.
+///
+/// The generated operations call it right after the `Crud` create, update or delete, in the same transaction, which is rolled back when it fails.
+pub trait Audit<Connection>
+where Connection: diesel::connection::Connection
+{
+    fn audit(&self, db: &mut Connection, entry: AuditEntry) -> anyhow::Result<()>;
+}
+
+/// Source of database connections. The generated handlers answer `503 Service Unavailable` when it fails
 pub trait DbFactory<Connection>
 where Connection: diesel::connection::SimpleConnection {
-    fn db(&self) -> Connection;
+    fn db(&self) -> Result<Connection, Unavailable>;
+}
+/// The database cannot be opened
+#[derive(Debug)]
+pub struct Unavailable(pub anyhow::Error);
+impl std::fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Database unavailable: {}", self.0)
+    }
+}
+impl std::error::Error for Unavailable {}
+impl<D> From<Unavailable> for Responder<D>
+where D: negotiated::HandlebarsFactory
+{
+    fn from(err: Unavailable) -> Self {
+        Responder::<D>::from(anyhow::Error::from(err)).with_status_code(actix_web::http::StatusCode::SERVICE_UNAVAILABLE)
+    }
 }
 
 #[async_trait]
 pub trait RestCollection<QueryParameters, D, Connection>: Sized 
 where D: Sized + Send + negotiated::HandlebarsFactory, Connection: diesel::connection::Connection
 {
-    async fn get(app_data: actix_web::web::Data<D>, query_parameters: actix_web::web::Query<QueryParameters>) -> Responder<D>;
+    async fn get(app_data: actix_web::web::Data<D>, principal: RequestPrincipal, query_parameters: actix_web::web::Query<QueryParameters>) -> Responder<D>;
 }
 #[async_trait]
 pub trait Rest<T, NewT, D, Connection>: Sized 
 where D: Sized + Send + negotiated::HandlebarsFactory, Connection: diesel::connection::Connection
 {
-    async fn post(app_data: actix_web::web::Data<D>, new_object: actix_web::web::Json<NewT>) -> Responder<D>;
-    async fn get(app_data: actix_web::web::Data<D>, id: actix_web::web::Path<i32>) -> Responder<D>;
-    async fn put(app_data: actix_web::web::Data<D>, id: actix_web::web::Path<i32>, to_update: actix_web::web::Json<T>) -> Responder<D>;
-    async fn delete(app_data: actix_web::web::Data<D>, id: actix_web::web::Path<i32>) -> Responder<D>;
+    async fn post(app_data: actix_web::web::Data<D>, principal: RequestPrincipal, request_id: RequestId, new_object: JsonOrForm<NewT>) -> Responder<D>;
+    async fn get(app_data: actix_web::web::Data<D>, principal: RequestPrincipal, id: actix_web::web::Path<i32>) -> Responder<D>;
+    async fn put(app_data: actix_web::web::Data<D>, principal: RequestPrincipal, request_id: RequestId, if_match: IfMatch, id: actix_web::web::Path<i32>, to_update: JsonOrForm<T>) -> Responder<D>;
+    async fn delete(app_data: actix_web::web::Data<D>, principal: RequestPrincipal, request_id: RequestId, if_match: IfMatch, id: actix_web::web::Path<i32>) -> Responder<D>;
+    /// Creates all objects of a JSON array, in one transaction and a single insert
+    async fn bulk_post(app_data: actix_web::web::Data<D>, principal: RequestPrincipal, request_id: RequestId, new_objects: actix_web::web::Json<Vec<NewT>>) -> Responder<D>;
+    /// Updates all objects of a JSON array, identified by their `id`, in one transaction. Objects with an `etag` field must still match it
+    async fn bulk_patch(app_data: actix_web::web::Data<D>, principal: RequestPrincipal, request_id: RequestId, to_update: actix_web::web::Json<Vec<BulkUpdate<T>>>) -> Responder<D>;
+    /// Deletes all objects of the `id` query parameters, in one transaction. Ids given as `id:etag` must still match the entity tag
+    async fn bulk_delete(app_data: actix_web::web::Data<D>, principal: RequestPrincipal, request_id: RequestId, ids: BulkIds) -> Responder<D>;
 }
 pub trait RestPre<T, NewT, D>
 where D: Sized + Send + negotiated::HandlebarsFactory
 {
-    fn pre_post(app_data: &actix_web::web::Data<D>, new_object: &NewT) -> anyhow::Result<NewT>;
-    fn pre_get(app_data: &actix_web::web::Data<D>, id: i32) -> anyhow::Result<i32>;
-    fn pre_put(app_data: &actix_web::web::Data<D>, id: i32, to_update: &T) -> anyhow::Result<(i32, T)>;
-    fn pre_delete(app_data: &actix_web::web::Data<D>, id: i32) -> anyhow::Result<i32>;
+    fn pre_post(app_data: &actix_web::web::Data<D>, principal: Option<&Principal>, new_object: &NewT) -> anyhow::Result<NewT>;
+    fn pre_get(app_data: &actix_web::web::Data<D>, principal: Option<&Principal>, id: i32) -> anyhow::Result<i32>;
+    fn pre_put(app_data: &actix_web::web::Data<D>, principal: Option<&Principal>, id: i32, to_update: &T) -> anyhow::Result<(i32, T)>;
+    fn pre_delete(app_data: &actix_web::web::Data<D>, principal: Option<&Principal>, id: i32) -> anyhow::Result<i32>;
+}
+pub trait RestPost<T, NewT, D>: Sized
+where D: Sized + Send + negotiated::HandlebarsFactory
+{
+    fn post_post(app_data: &actix_web::web::Data<D>, new_object: &NewT, result: anyhow::Result<T>) -> anyhow::Result<T>;
+    fn post_get(app_data: &actix_web::web::Data<D>, id: i32, result: anyhow::Result<T>) -> anyhow::Result<T>;
+    fn post_put(app_data: &actix_web::web::Data<D>, id: i32, to_update: &T, result: anyhow::Result<T>) -> anyhow::Result<T>;
+    fn post_delete(app_data: &actix_web::web::Data<D>, id: i32, result: anyhow::Result<T>) -> anyhow::Result<T>;
+}
+/// Authorization hook, enabled with `authorize=true` in the `Rest` derive.
+///
+/// The generated handlers call it right before the `Crud` operation, after the `RestPre` hook, and answer `403 Forbidden` when it fails.
+/// Checks of the change against the database belong in the `RestValidate` hook instead.
+pub trait RestAuthorize<T, NewT, D, Connection>
+where D: Sized + Send + negotiated::HandlebarsFactory, Connection: diesel::connection::Connection
+{
+    fn authorize_list(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>) -> anyhow::Result<()>;
+    fn authorize_post(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, new_object: &NewT) -> anyhow::Result<()>;
+    fn authorize_get(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, id: i32) -> anyhow::Result<()>;
+    fn authorize_put(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, id: i32, to_update: &T) -> anyhow::Result<()>;
+    fn authorize_delete(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, id: i32) -> anyhow::Result<()>;
+}
+/// Validation hook checking a change against the database, enabled with `validate=true` in the `Rest` derive.
+///
+/// The generated operations call it on the connection of the operation, once the `RestAuthorize` hook and the `If-Match` precondition pass,
+/// right before the `Crud` operation, so that what it checks holds until the transaction commits. Failures are `OperationError::Failed`
+pub trait RestValidate<T, NewT, Connection>
+where Connection: diesel::connection::Connection
+{
+    fn validate_post(db: &mut Connection, new_object: &NewT) -> anyhow::Result<()>;
+    fn validate_put(db: &mut Connection, id: i32, to_update: &T) -> anyhow::Result<()>;
+    /// Checks a creation of a bulk request against the objects created before it in the same request, which the database does not hold yet
+    fn validate_post_batch(db: &mut Connection, new_object: &NewT, batch: &[NewT]) -> anyhow::Result<()>;
+}
+/// Hook making the changes that follow from an update, enabled with `cascade=true` in the `Rest` derive.
+///
+/// The generated operations call it on the connection of the operation, after the `Crud` operation and the `Audit` hook, with the entity before and after the update.
+/// Changes it makes are part of the transaction of the update, and are expected to be audited too
+pub trait RestCascade<T, D, Connection>
+where D: Sized + Send + negotiated::HandlebarsFactory, Connection: diesel::connection::Connection
+{
+    fn cascade_put(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, request_id: &RequestId, before: &T, after: &T) -> anyhow::Result<()>;
+}
+/// Failure of a `RestOperation`
+#[derive(Debug)]
+pub enum OperationError {
+    /// Denied by the `RestAuthorize` hook
+    Forbidden(anyhow::Error),
+    /// The entity does not match the `If-Match` header of the request
+    PreconditionFailed(anyhow::Error),
+    /// The database cannot be opened
+    Unavailable(anyhow::Error),
+    Failed(anyhow::Error),
 }
-pub trait RestPost<T, NewT>: Sized {
-    fn post_post(new_object: &NewT, result: anyhow::Result<T>) -> anyhow::Result<T>;
-    fn post_get(id: i32, result: anyhow::Result<T>) -> anyhow::Result<T>;
-    fn post_put(id: i32, to_update: &T, result: anyhow::Result<T>) -> anyhow::Result<T>;
-    fn post_delete(id: i32, result: anyhow::Result<T>) -> anyhow::Result<T>;
+impl OperationError {
+    /// HTTP status of a response failing with this error
+    pub fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            OperationError::Forbidden(_) => actix_web::http::StatusCode::FORBIDDEN,
+            OperationError::PreconditionFailed(_) => actix_web::http::StatusCode::PRECONDITION_FAILED,
+            OperationError::Unavailable(_) => actix_web::http::StatusCode::SERVICE_UNAVAILABLE,
+            OperationError::Failed(_) => actix_web::http::StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+impl std::fmt::Display for OperationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OperationError::Forbidden(err) | OperationError::PreconditionFailed(err) | OperationError::Unavailable(err) | OperationError::Failed(err) => write!(f, "{}", err),
+        }
+    }
+}
+impl From<anyhow::Error> for OperationError {
+    fn from(err: anyhow::Error) -> Self {
+        OperationError::Failed(err)
+    }
+}
+impl From<Unavailable> for OperationError {
+    fn from(err: Unavailable) -> Self {
+        OperationError::Unavailable(err.into())
+    }
+}
+impl From<diesel::result::Error> for OperationError {
+    fn from(err: diesel::result::Error) -> Self {
+        OperationError::Failed(err.into())
+    }
+}
+impl<D> From<OperationError> for Responder<D>
+where D: negotiated::HandlebarsFactory
+{
+    fn from(err: OperationError) -> Self {
+        let status_code = err.status_code();
+        match err {
+            OperationError::Forbidden(err) | OperationError::PreconditionFailed(err) | OperationError::Unavailable(err) | OperationError::Failed(err) => Responder::<D>::from(err).with_status_code(status_code),
+        }
+    }
+}
+/// Create, update and delete operations run by the `Rest` handlers: `RestPre` hook, `RestAuthorize` hook, `RestValidate` hook, `Crud` operation, `Audit` hook, `RestCascade` hook and `RestPost` hook.
+///
+/// Operations run on the connection they are given and leave transactions to the caller, so that several of them can be made atomic
+pub trait RestOperation<T, NewT, D, Connection>
+where D: Sized + Send + negotiated::HandlebarsFactory, Connection: diesel::connection::Connection
+{
+    /// `RestPre`, `RestAuthorize` and `RestValidate` hooks of a creation, returning the object to insert. `batch` holds the objects prepared before it in the same bulk request
+    fn prepare_create(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, new_object: &NewT, batch: &[NewT]) -> Result<NewT, OperationError>;
+    /// `Audit` and `RestPost` hooks of a creation, given the result of the `Crud` insert
+    fn complete_create(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, request_id: &RequestId, new_object: &NewT, result: anyhow::Result<T>) -> Result<T, OperationError>;
+    fn create(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, request_id: &RequestId, new_object: NewT) -> Result<T, OperationError>;
+    fn update(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, request_id: &RequestId, id: i32, to_update: T) -> Result<T, OperationError>;
+    /// `update` of an entity matching the `If-Match` header, checked once the `RestAuthorize` hook passes so that the precondition reveals nothing to those denied
+    fn update_if_match(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, request_id: &RequestId, if_match: &IfMatch, id: i32, to_update: T) -> Result<T, OperationError>;
+    fn delete(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, request_id: &RequestId, id: i32) -> Result<T, OperationError>;
+    /// `delete` of an entity matching the `If-Match` header, checked once the `RestAuthorize` hook passes
+    fn delete_if_match(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, request_id: &RequestId, if_match: &IfMatch, id: i32) -> Result<T, OperationError>;
+}
+/// Additional template context for HTML responses, enabled with `context=true` in the `Rest` derive
+pub trait RestContext<T, Connection>
+where Connection: diesel::connection::Connection
+{
+    fn context(db: &mut Connection, object: &T) -> anyhow::Result<serde_json::Value>;
 }
 pub trait Crud<T, NewT, Connection>
 where Connection: diesel::connection::Connection
 {
     fn db_insert(db: &mut Connection, to_insert: &NewT) -> anyhow::Result<T>;
+    /// Inserts all of `to_insert` with a single multi-row statement. Must run in a transaction
+    fn db_insert_all(db: &mut Connection, to_insert: &[NewT]) -> anyhow::Result<Vec<T>>;
     fn db_update(db: &mut Connection, to_update: &T) -> anyhow::Result<T>;
     fn db_fetch_all(db: &mut Connection, name_filter: String, limit: Option<(i64, i64)>) -> anyhow::Result<Vec<T>>;
     fn db_fetch(db: &mut Connection, id: i32) -> anyhow::Result<T>;
@@ -49,3 +351,219 @@ where Connection: diesel::connection::Connection
 pub struct RestCollectionGetParameters {
     pub q: Option<String>
 }
+
+/// Outcome of an item of a bulk request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BulkItemStatus {
+    /// Applied, the request was committed
+    Success,
+    /// Valid, but rolled back by the failure of another item
+    RolledBack,
+    Error,
+}
+#[derive(Debug, Serialize)]
+pub struct BulkItemResult<T> {
+    pub status: BulkItemStatus,
+    pub payload: Option<T>,
+    pub error: Option<String>,
+}
+impl<T> BulkItemResult<T> {
+    fn success(payload: T) -> Self {
+        BulkItemResult { status: BulkItemStatus::Success, payload: Some(payload), error: None }
+    }
+    fn error(err: &OperationError) -> Self {
+        BulkItemResult { status: BulkItemStatus::Error, payload: None, error: Some(err.to_string()) }
+    }
+}
+/// Item of a bulk update: the object, and the entity tag it must still match, if any
+#[derive(Deserialize)]
+pub struct BulkUpdate<T> {
+    #[serde(flatten)]
+    pub object: T,
+    pub etag: Option<String>,
+}
+/// Objects of a successful bulk request, or the result of every item along with the first failure
+pub type BulkOutcome<T> = Result<Vec<T>, (Vec<BulkItemResult<T>>, OperationError)>;
+
+/// Ids of a bulk delete, from `id` query parameters, repeated (`?id=1&id=2`) or comma separated (`?id=1,2`),
+/// each with the entity tag it must still match when given as `id:etag`
+pub struct BulkIds(pub Vec<(i32, Option<String>)>);
+impl FromRequest for BulkIds {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let ids = serde_urlencoded::from_str::<Vec<FormField>>(req.query_string())
+            .map_err(ErrorBadRequest)
+            .and_then(|parameters| parameters
+                .iter()
+                .filter(|(key, _)| key == "id")
+                .flat_map(|(_, value)| value.split(','))
+                .map(|item| {
+                    let (id, etag) = match item.split_once(':') {
+                        Some((id, etag)) => (id, Some(quoted_etag(etag.trim()))),
+                        None => (item, None),
+                    };
+                    id.trim().parse::<i32>().map(|id| (id, etag)).map_err(|_| ErrorBadRequest(format!("Invalid id {}", item)))
+                })
+                .collect::<Result<Vec<(i32, Option<String>)>, actix_web::Error>>());
+        std::future::ready(ids.map(BulkIds))
+    }
+}
+
+/// Entity tag of a bulk delete id, which may leave out the quotes
+fn quoted_etag(etag: &str) -> String {
+    if etag.starts_with('"') || etag.starts_with("W/") {
+        etag.to_string()
+    } else {
+        format!("\"{}\"", etag)
+    }
+}
+
+/// Runs `run_item` on every item in one transaction, rolled back when any item fails. Items after a failure are still run, to report their own errors
+pub fn bulk<T, I, Connection>(db: &mut Connection, items: Vec<I>, mut run_item: impl FnMut(&mut Connection, I) -> Result<T, OperationError>) -> BulkOutcome<T>
+where Connection: diesel::connection::Connection
+{
+    let mut results: Vec<BulkItemResult<T>> = Vec::new();
+    let outcome = db.transaction::<(), OperationError, _>(|db| {
+        let mut failure = None;
+        for item in items {
+            match run_item(db, item) {
+                Ok(payload) => results.push(BulkItemResult::success(payload)),
+                Err(err) => {
+                    results.push(BulkItemResult::error(&err));
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    });
+    bulk_outcome(results, outcome)
+}
+
+/// Creates `new_objects` in one transaction with a single multi-row insert, once the `RestPre`, `RestAuthorize` and `RestValidate` hooks pass for all of them,
+/// each validated against the objects before it as well as against the database
+pub fn bulk_create<T, NewT, D, Connection>(app_data: &actix_web::web::Data<D>, db: &mut Connection, principal: Option<&Principal>, request_id: &RequestId, new_objects: Vec<NewT>) -> BulkOutcome<T>
+where T: RestOperation<T, NewT, D, Connection> + Crud<T, NewT, Connection>,
+      D: Sized + Send + negotiated::HandlebarsFactory,
+      Connection: diesel::connection::Connection
+{
+    let mut results: Vec<BulkItemResult<T>> = Vec::new();
+    let outcome = db.transaction::<(), OperationError, _>(|db| {
+        let mut failure = None;
+        let mut prepared = Vec::new();
+        let mut errors = Vec::new();
+        for new_object in &new_objects {
+            match T::prepare_create(app_data, db, principal, new_object, &prepared) {
+                Ok(new_object) => { prepared.push(new_object); errors.push(None); },
+                Err(err) => { errors.push(Some(err.to_string())); failure.get_or_insert(err); }
+            }
+        }
+        if let Some(err) = failure {
+            results.extend(errors.into_iter().map(|error| BulkItemResult { status: if error.is_some() { BulkItemStatus::Error } else { BulkItemStatus::RolledBack }, payload: None, error }));
+            return Err(err);
+        }
+        let inserted = match T::db_insert_all(db, &prepared) {
+            Ok(inserted) => inserted,
+            Err(err) => {
+                let err = OperationError::Failed(err);
+                results.extend(new_objects.iter().map(|_| BulkItemResult::error(&err)));
+                return Err(err);
+            }
+        };
+        for (new_object, inserted) in new_objects.iter().zip(inserted) {
+            match T::complete_create(app_data, db, principal, request_id, new_object, Ok(inserted)) {
+                Ok(created) => results.push(BulkItemResult::success(created)),
+                Err(err) => {
+                    results.push(BulkItemResult::error(&err));
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    });
+    bulk_outcome(results, outcome)
+}
+
+fn bulk_outcome<T>(mut results: Vec<BulkItemResult<T>>, outcome: Result<(), OperationError>) -> BulkOutcome<T> {
+    match outcome {
+        Ok(()) => Ok(results.into_iter().filter_map(|result| result.payload).collect()),
+        Err(err) => {
+            results
+                .iter_mut()
+                .filter(|result| result.status == BulkItemStatus::Success)
+                .for_each(|result| result.status = BulkItemStatus::RolledBack);
+            Err((results, err))
+        }
+    }
+}
+
+/// Response of a bulk request: the objects on success. On failure an error carrying the result of every item,
+/// with the status of the first failure: `403 Forbidden`, `412 Precondition Failed` or `422 Unprocessable Entity`
+pub fn bulk_responder<T, D>(outcome: BulkOutcome<T>) -> Responder<D>
+where T: Serialize + Send + 'static, D: negotiated::HandlebarsFactory
+{
+    match outcome {
+        Ok(objects) => Responder::<D>::from(anyhow::Result::<Vec<T>>::Ok(objects)),
+        Err((results, err)) => {
+            let status_code = err.status_code();
+            let failed = results.iter().filter(|result| result.status == BulkItemStatus::Error).count();
+            let mut responder = Responder::<D>::from(anyhow::anyhow!("{} of {} items failed, nothing was changed: {}", failed, results.len(), err)).with_status_code(status_code);
+            responder.payload = Some(Box::new(results));
+            responder
+        }
+    }
+}
+
+type FormField = (String, String);
+
+/// Request body extractor accepting either JSON or an HTML form (`application/x-www-form-urlencoded`), and refusing other content types with `415 Unsupported Media Type`.
+///
+/// Empty form fields are dropped before deserializing, so that an empty input maps to `None` on optional fields.
+/// Form submissions must carry a CSRF token field matching the CSRF cookie set by `negotiated::Responder`.
+pub struct JsonOrForm<T>(pub T);
+impl<T> JsonOrForm<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+impl<T> std::ops::Deref for JsonOrForm<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+impl<T> FromRequest for JsonOrForm<T>
+where T: DeserializeOwned + 'static
+{
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let is_form = req.content_type() == "application/x-www-form-urlencoded";
+        if !is_form && req.content_type() != "application/json" {
+            let content_type = req.content_type().to_string();
+            return Box::pin(async move { Err(ErrorUnsupportedMediaType(format!("Unsupported content type {}, expected application/json or application/x-www-form-urlencoded", content_type))) });
+        }
+        let csrf_cookie = req.cookie(negotiated::CSRF_COOKIE).map(|cookie| cookie.value().to_string());
+        let bytes = Bytes::from_request(req, payload);
+        Box::pin(async move {
+            let bytes = bytes.await?;
+            if is_form {
+                let (csrf_fields, fields): (Vec<FormField>, Vec<FormField>) = serde_urlencoded::from_bytes::<Vec<FormField>>(&bytes)
+                    .map_err(ErrorBadRequest)?
+                    .into_iter()
+                    .filter(|(_, value)| !value.is_empty())
+                    .partition(|(key, _)| key == negotiated::CSRF_FIELD);
+                match (csrf_cookie, csrf_fields.first()) {
+                    (Some(cookie), Some((_, field))) if &cookie == field => (),
+                    _ => return Err(ErrorForbidden("Invalid CSRF token"))
+                }
+                let encoded = serde_urlencoded::to_string(fields).map_err(ErrorBadRequest)?;
+                Ok(JsonOrForm(serde_urlencoded::from_str(&encoded).map_err(ErrorBadRequest)?))
+            } else {
+                Ok(JsonOrForm(serde_json::from_slice(&bytes).map_err(ErrorBadRequest)?))
+            }
+        })
+    }
+}
@@ -0,0 +1,4 @@
+DROP TABLE session;
+DROP TABLE api_token;
+DROP TABLE user;
+UPDATE db_version SET version = 1;
@@ -0,0 +1,4 @@
+CREATE TABLE user(id INTEGER PRIMARY KEY NOT NULL, name TEXT NOT NULL UNIQUE, password_hash TEXT NOT NULL);
+CREATE TABLE api_token(id INTEGER PRIMARY KEY NOT NULL, user INTEGER NOT NULL REFERENCES user(id), name TEXT NOT NULL, token_hash TEXT NOT NULL UNIQUE, created BIGINT NOT NULL, revoked BIGINT);
+CREATE TABLE session(id INTEGER PRIMARY KEY NOT NULL, user INTEGER NOT NULL REFERENCES user(id), token_hash TEXT NOT NULL UNIQUE, expires BIGINT NOT NULL);
+UPDATE db_version SET version = 2;
@@ -0,0 +1,2 @@
+DROP TABLE role_grant;
+UPDATE db_version SET version = 2;
@@ -0,0 +1,2 @@
+CREATE TABLE role_grant(id INTEGER PRIMARY KEY NOT NULL, user INTEGER NOT NULL REFERENCES user(id), role TEXT NOT NULL CHECK (role IN ('viewer', 'editor', 'admin')), host_group INTEGER REFERENCES host_group(id), UNIQUE(user, role, host_group));
+UPDATE db_version SET version = 3;
@@ -0,0 +1,2 @@
+DROP TABLE audit_log;
+UPDATE db_version SET version = 3;
@@ -0,0 +1,4 @@
+CREATE TABLE audit_log(id INTEGER PRIMARY KEY NOT NULL, entity TEXT NOT NULL, entity_id INTEGER NOT NULL, action TEXT NOT NULL CHECK (action IN ('create', 'update', 'delete')), before TEXT, after TEXT, user INTEGER, principal TEXT, timestamp BIGINT NOT NULL, request_id TEXT NOT NULL);
+CREATE INDEX audit_log_entity ON audit_log(entity, entity_id);
+CREATE INDEX audit_log_timestamp ON audit_log(timestamp);
+UPDATE db_version SET version = 4;
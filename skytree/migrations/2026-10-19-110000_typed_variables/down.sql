@@ -0,0 +1,18 @@
+ALTER TABLE variable DROP COLUMN hash_behaviour;
+CREATE TABLE host_variable_text(variable INTEGER NOT NULL REFERENCES variable(id), host INTEGER NOT NULL REFERENCES host(id), value TEXT NOT NULL, PRIMARY KEY (variable, host));
+INSERT INTO host_variable_text SELECT variable, host, CASE WHEN json_type(value) = 'text' THEN json_extract(value, '$') ELSE value END FROM host_variable;
+DROP TABLE host_variable;
+ALTER TABLE host_variable_text RENAME TO host_variable;
+CREATE TABLE host_group_variable_text(variable INTEGER NOT NULL REFERENCES variable(id), "group" INTEGER NOT NULL REFERENCES "group"(id), value TEXT NOT NULL, PRIMARY KEY (variable, "group"));
+INSERT INTO host_group_variable_text SELECT variable, "group", CASE WHEN json_type(value) = 'text' THEN json_extract(value, '$') ELSE value END FROM host_group_variable;
+DROP TABLE host_group_variable;
+ALTER TABLE host_group_variable_text RENAME TO host_group_variable;
+CREATE TABLE service_variable_text(variable INTEGER NOT NULL REFERENCES variable(id), "service" INTEGER NOT NULL REFERENCES "service"(id), value TEXT NOT NULL, PRIMARY KEY (variable, "service"));
+INSERT INTO service_variable_text SELECT variable, "service", CASE WHEN json_type(value) = 'text' THEN json_extract(value, '$') ELSE value END FROM service_variable;
+DROP TABLE service_variable;
+ALTER TABLE service_variable_text RENAME TO service_variable;
+CREATE TABLE service_instance_variable_text(variable INTEGER NOT NULL REFERENCES variable(id), instance INTEGER NOT NULL REFERENCES service_instance(id), value TEXT NOT NULL, primary key (variable, instance));
+INSERT INTO service_instance_variable_text SELECT variable, instance, CASE WHEN json_type(value) = 'text' THEN json_extract(value, '$') ELSE value END FROM service_instance_variable;
+DROP TABLE service_instance_variable;
+ALTER TABLE service_instance_variable_text RENAME TO service_instance_variable;
+UPDATE db_version SET version = 4;
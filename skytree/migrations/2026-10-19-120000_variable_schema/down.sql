@@ -0,0 +1,2 @@
+ALTER TABLE variable DROP COLUMN value_schema;
+UPDATE db_version SET version = 5;
@@ -0,0 +1,2 @@
+ALTER TABLE variable ADD COLUMN value_schema TEXT CHECK (value_schema IS NULL OR json_valid(value_schema));
+UPDATE db_version SET version = 6;
@@ -0,0 +1,2 @@
+ALTER TABLE variable DROP COLUMN secret;
+UPDATE db_version SET version = 6;
@@ -0,0 +1,2 @@
+ALTER TABLE variable ADD COLUMN secret BOOLEAN NOT NULL DEFAULT 0;
+UPDATE db_version SET version = 7;
@@ -0,0 +1,2 @@
+DROP TABLE host_fact;
+UPDATE db_version SET version = 7;
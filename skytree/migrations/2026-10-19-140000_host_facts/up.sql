@@ -0,0 +1,3 @@
+CREATE TABLE host_fact(host INTEGER NOT NULL REFERENCES host(id), path TEXT NOT NULL, value TEXT NOT NULL CHECK (json_valid(value)), gathered BIGINT NOT NULL, PRIMARY KEY (host, path));
+CREATE INDEX host_fact_path ON host_fact(path);
+UPDATE db_version SET version = 8;
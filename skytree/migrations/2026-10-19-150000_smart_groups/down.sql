@@ -0,0 +1,2 @@
+ALTER TABLE host_group DROP COLUMN rule;
+UPDATE db_version SET version = 8;
@@ -0,0 +1,2 @@
+ALTER TABLE host_group ADD COLUMN rule TEXT;
+UPDATE db_version SET version = 9;
@@ -0,0 +1,2 @@
+DROP TABLE service_endpoint;
+UPDATE db_version SET version = 9;
@@ -0,0 +1,9 @@
+CREATE TABLE service_endpoint(id INTEGER PRIMARY KEY NOT NULL, instance INTEGER NOT NULL REFERENCES service_instance(id), ip TEXT NOT NULL, port INTEGER NOT NULL CHECK (port BETWEEN 1 AND 65535), protocol TEXT NOT NULL DEFAULT 'tcp' CHECK (protocol IN ('tcp', 'udp', 'sctp')), dns_name TEXT);
+CREATE INDEX service_endpoint_instance ON service_endpoint(instance);
+CREATE TRIGGER service_endpoint_unique_insert BEFORE INSERT ON service_endpoint
+WHEN EXISTS (SELECT 1 FROM service_endpoint e INNER JOIN service_instance i ON i.id = e.instance WHERE e.ip = NEW.ip AND e.port = NEW.port AND e.protocol = NEW.protocol AND i.host = (SELECT host FROM service_instance WHERE id = NEW.instance))
+BEGIN SELECT RAISE(ABORT, 'address, port and protocol already claimed on this host'); END;
+CREATE TRIGGER service_endpoint_unique_update BEFORE UPDATE ON service_endpoint
+WHEN EXISTS (SELECT 1 FROM service_endpoint e INNER JOIN service_instance i ON i.id = e.instance WHERE e.id != NEW.id AND e.ip = NEW.ip AND e.port = NEW.port AND e.protocol = NEW.protocol AND i.host = (SELECT host FROM service_instance WHERE id = NEW.instance))
+BEGIN SELECT RAISE(ABORT, 'address, port and protocol already claimed on this host'); END;
+UPDATE db_version SET version = 10;
@@ -0,0 +1,3 @@
+DROP TABLE service_instance_relation;
+DROP TABLE service_relation;
+UPDATE db_version SET version = 10;
@@ -0,0 +1,3 @@
+CREATE TABLE service_relation(id INTEGER PRIMARY KEY NOT NULL, kind TEXT NOT NULL CHECK (kind IN ('depends_on', 'load_balances', 'replicates_to')), source INTEGER NOT NULL REFERENCES "service"(id), target INTEGER NOT NULL REFERENCES "service"(id), CHECK (source != target), UNIQUE (kind, source, target));
+CREATE TABLE service_instance_relation(id INTEGER PRIMARY KEY NOT NULL, kind TEXT NOT NULL CHECK (kind IN ('depends_on', 'load_balances', 'replicates_to')), source INTEGER NOT NULL REFERENCES service_instance(id), target INTEGER NOT NULL REFERENCES service_instance(id), CHECK (source != target), UNIQUE (kind, source, target));
+UPDATE db_version SET version = 11;
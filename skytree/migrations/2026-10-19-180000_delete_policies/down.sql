@@ -0,0 +1,3 @@
+DROP TRIGGER service_in_use;
+DROP TRIGGER host_group_in_use;
+UPDATE db_version SET version = 11;
@@ -0,0 +1,13 @@
+CREATE TRIGGER host_group_in_use BEFORE DELETE ON host_group
+WHEN EXISTS (SELECT 1 FROM host_group WHERE parent = OLD.id)
+  OR EXISTS (SELECT 1 FROM host_group_membership WHERE "group" = OLD.id)
+  OR EXISTS (SELECT 1 FROM host_group_variable WHERE "group" = OLD.id)
+  OR EXISTS (SELECT 1 FROM role_grant WHERE host_group = OLD.id)
+BEGIN SELECT RAISE(ABORT, 'host group is in use, delete it with policy=cascade or policy=reparent'); END;
+CREATE TRIGGER service_in_use BEFORE DELETE ON service
+WHEN EXISTS (SELECT 1 FROM service WHERE parent = OLD.id)
+  OR EXISTS (SELECT 1 FROM service_instance WHERE service = OLD.id)
+  OR EXISTS (SELECT 1 FROM service_variable WHERE service = OLD.id)
+  OR EXISTS (SELECT 1 FROM service_relation WHERE source = OLD.id OR target = OLD.id)
+BEGIN SELECT RAISE(ABORT, 'service is in use, delete it with policy=cascade or policy=reparent'); END;
+UPDATE db_version SET version = 12;
@@ -0,0 +1,15 @@
+CREATE TABLE host_nullable(id INTEGER PRIMARY KEY NOT NULL, name TEXT);
+INSERT INTO host_nullable SELECT id, name FROM host;
+DROP TABLE host;
+ALTER TABLE host_nullable RENAME TO host;
+CREATE TABLE host_group_nullable(id INTEGER PRIMARY KEY NOT NULL, parent INTEGER REFERENCES host_group(id), name TEXT, rule TEXT);
+INSERT INTO host_group_nullable SELECT id, parent, name, rule FROM host_group;
+DROP TABLE host_group;
+ALTER TABLE host_group_nullable RENAME TO host_group;
+CREATE TRIGGER host_group_in_use BEFORE DELETE ON host_group
+WHEN EXISTS (SELECT 1 FROM host_group WHERE parent = OLD.id)
+  OR EXISTS (SELECT 1 FROM host_group_membership WHERE "group" = OLD.id)
+  OR EXISTS (SELECT 1 FROM host_group_variable WHERE "group" = OLD.id)
+  OR EXISTS (SELECT 1 FROM role_grant WHERE host_group = OLD.id)
+BEGIN SELECT RAISE(ABORT, 'host group is in use, delete it with policy=cascade or policy=reparent'); END;
+UPDATE db_version SET version = 12;
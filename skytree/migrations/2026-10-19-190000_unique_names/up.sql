@@ -0,0 +1,22 @@
+UPDATE host_group SET name = 'host_group_' || id WHERE name IS NULL OR trim(name) = '';
+UPDATE host_group SET name = replace(replace(replace(replace(trim(name), '-', '_'), '.', '_'), ':', '_'), ' ', '_');
+UPDATE host_group SET name = '_' || name WHERE substr(name, 1, 1) BETWEEN '0' AND '9';
+UPDATE host_group SET name = name || '_' || id WHERE EXISTS (SELECT 1 FROM host_group other WHERE other.name = host_group.name AND other.id < host_group.id);
+CREATE TABLE host_group_named(id INTEGER PRIMARY KEY NOT NULL, parent INTEGER REFERENCES host_group(id), name TEXT NOT NULL UNIQUE, rule TEXT);
+INSERT INTO host_group_named SELECT id, parent, name, rule FROM host_group;
+DROP TABLE host_group;
+ALTER TABLE host_group_named RENAME TO host_group;
+CREATE TRIGGER host_group_in_use BEFORE DELETE ON host_group
+WHEN EXISTS (SELECT 1 FROM host_group WHERE parent = OLD.id)
+  OR EXISTS (SELECT 1 FROM host_group_membership WHERE "group" = OLD.id)
+  OR EXISTS (SELECT 1 FROM host_group_variable WHERE "group" = OLD.id)
+  OR EXISTS (SELECT 1 FROM role_grant WHERE host_group = OLD.id)
+BEGIN SELECT RAISE(ABORT, 'host group is in use, delete it with policy=cascade or policy=reparent'); END;
+UPDATE host SET name = 'host_' || id WHERE name IS NULL OR trim(name) = '';
+UPDATE host SET name = trim(name);
+UPDATE host SET name = name || '-' || id WHERE EXISTS (SELECT 1 FROM host other WHERE other.name = host.name AND other.id < host.id);
+CREATE TABLE host_named(id INTEGER PRIMARY KEY NOT NULL, name TEXT NOT NULL UNIQUE);
+INSERT INTO host_named SELECT id, name FROM host;
+DROP TABLE host;
+ALTER TABLE host_named RENAME TO host;
+UPDATE db_version SET version = 13;
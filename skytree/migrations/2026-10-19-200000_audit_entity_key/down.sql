@@ -0,0 +1,4 @@
+DROP INDEX audit_log_entity;
+ALTER TABLE audit_log DROP COLUMN entity_key;
+CREATE INDEX audit_log_entity ON audit_log(entity, entity_id);
+UPDATE db_version SET version = 13;
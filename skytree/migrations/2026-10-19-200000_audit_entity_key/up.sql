@@ -0,0 +1,5 @@
+ALTER TABLE audit_log ADD COLUMN entity_key INTEGER;
+UPDATE audit_log SET entity_key = json_extract(coalesce(after, before), '$.variable') WHERE entity IN ('host_group_variable', 'host_variable', 'service_variable', 'service_instance_variable');
+DROP INDEX audit_log_entity;
+CREATE INDEX audit_log_entity ON audit_log(entity, entity_id, entity_key);
+UPDATE db_version SET version = 14;
@@ -0,0 +1,312 @@
+use actix_web::{http::StatusCode, web};
+use anyhow::{anyhow, Context};
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use rest::{AuditEntry, DbFactory, RequestPrincipal};
+use serde::{Deserialize, Serialize, Serializer};
+use crate::{AppData, auth, authorization, history, schema};
+
+/// Entries returned by `GET /audit` when no limit is given
+const DEFAULT_LIMIT: i64 = 100;
+/// `entity` of `GET /audit/diff` comparing the inventory as a whole
+const INVENTORY: &str = "inventory";
+
+/// Change recorded in the audit log
+#[derive(Debug, Clone, Serialize, Queryable)]
+pub struct AuditLog {
+    pub id: i32,
+    pub entity: String,
+    pub entity_id: i32,
+    pub action: String,
+    #[serde(serialize_with = "as_json")]
+    pub before: Option<String>,
+    #[serde(serialize_with = "as_json")]
+    pub after: Option<String>,
+    pub user: Option<i32>,
+    pub principal: Option<String>,
+    pub timestamp: i64,
+    pub request_id: String,
+    /// Second part of the id, such as the variable of a variable value
+    pub entity_key: Option<i32>,
+}
+
+/// Serializes the JSON text stored in the database as structured JSON
+fn as_json<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    value
+        .as_deref()
+        .map(|value| serde_json::from_str::<serde_json::Value>(value).unwrap_or_else(|_| serde_json::Value::String(value.to_string())))
+        .serialize(serializer)
+}
+
+impl rest::Audit<SqliteConnection> for AppData<'static> {
+    fn audit(&self, db: &mut SqliteConnection, entry: AuditEntry) -> anyhow::Result<()> {
+        diesel::insert_into(schema::audit_log::table)
+            .values((
+                schema::audit_log::entity.eq(&entry.entity),
+                schema::audit_log::entity_id.eq(entry.entity_id),
+                schema::audit_log::entity_key.eq(entry.entity_key),
+                schema::audit_log::action.eq(entry.action.to_string()),
+                schema::audit_log::before.eq(entry.before.map(|before| before.to_string())),
+                schema::audit_log::after.eq(entry.after.map(|after| after.to_string())),
+                schema::audit_log::user.eq(entry.principal.as_ref().map(|principal| principal.user)),
+                schema::audit_log::principal.eq(entry.principal.as_ref().map(|principal| principal.name.clone())),
+                schema::audit_log::timestamp.eq(auth::now()),
+                schema::audit_log::request_id.eq(&entry.request_id),
+            ))
+            .execute(db)
+            .context("Unable to write the audit log")?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditQuery {
+    /// Resource name, such as `host_group` or `variable`
+    pub entity: Option<String>,
+    /// Entity id, along with `entity`
+    pub id: Option<i32>,
+    /// Second part of the id, along with `id`, such as the variable of a variable value
+    pub key: Option<i32>,
+    /// Start of the time range, inclusive
+    pub from: Option<String>,
+    /// End of the time range, exclusive
+    pub to: Option<String>,
+    pub request_id: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Parses a time given as unix seconds, RFC 3339 or a `YYYY-MM-DD` date (midnight UTC)
+pub fn parse_time(time: &str) -> anyhow::Result<i64> {
+    if let Ok(seconds) = time.parse::<i64>() {
+        return Ok(seconds);
+    }
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(time) {
+        return Ok(date.timestamp());
+    }
+    chrono::NaiveDate::parse_from_str(time, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc().timestamp())
+        .ok_or_else(|| anyhow!("Invalid time {}, expected unix seconds, RFC 3339 or YYYY-MM-DD", time))
+}
+
+/// Audit log entries matching `query`, most recent first
+pub fn search(db: &mut SqliteConnection, query: &AuditQuery) -> anyhow::Result<Vec<AuditLog>> {
+    let mut statement = schema::audit_log::table.into_boxed();
+    if let Some(entity) = query.entity.as_deref().filter(|entity| !entity.is_empty()) {
+        statement = statement.filter(schema::audit_log::entity.eq(entity.to_string()));
+    }
+    if let Some(id) = query.id {
+        statement = statement.filter(schema::audit_log::entity_id.eq(id));
+    }
+    if let Some(key) = query.key {
+        statement = statement.filter(schema::audit_log::entity_key.eq(key));
+    }
+    if let Some(from) = query.from.as_deref().filter(|from| !from.is_empty()) {
+        statement = statement.filter(schema::audit_log::timestamp.ge(parse_time(from)?));
+    }
+    if let Some(to) = query.to.as_deref().filter(|to| !to.is_empty()) {
+        statement = statement.filter(schema::audit_log::timestamp.lt(parse_time(to)?));
+    }
+    if let Some(request_id) = query.request_id.as_deref().filter(|request_id| !request_id.is_empty()) {
+        statement = statement.filter(schema::audit_log::request_id.eq(request_id.to_string()));
+    }
+    Ok(statement
+        .order(schema::audit_log::id.desc())
+        .limit(query.limit.unwrap_or(DEFAULT_LIMIT))
+        .load::<AuditLog>(db)?)
+}
+
+/// Recorded JSON of an entity, `null` when it did not exist
+fn recorded(value: Option<String>) -> anyhow::Result<serde_json::Value> {
+    Ok(match value {
+        Some(value) => serde_json::from_str(&value)?,
+        None => serde_json::Value::Null,
+    })
+}
+
+/// State of an entity at `as_of`, replayed from the audit log: `null` when it did not exist at that time.
+/// `key` completes the id of entities identified by a pair, such as variable values.
+///
+/// Fails when the entity has no recorded history, as its past state is then unknown
+pub fn state_at(db: &mut SqliteConnection, entity: &str, id: i32, key: Option<i32>, as_of: i64) -> anyhow::Result<serde_json::Value> {
+    let history = schema::audit_log::table
+        .filter(schema::audit_log::entity.eq(entity))
+        .filter(schema::audit_log::entity_id.eq(id))
+        .filter(schema::audit_log::entity_key.is(key));
+    let last_before = history
+        .filter(schema::audit_log::timestamp.le(as_of))
+        .order(schema::audit_log::id.desc())
+        .select(schema::audit_log::after)
+        .first::<Option<String>>(db)
+        .optional()?;
+    if let Some(after) = last_before {
+        return recorded(after);
+    }
+    let first_after = history
+        .order(schema::audit_log::id.asc())
+        .select(schema::audit_log::before)
+        .first::<Option<String>>(db)
+        .optional()?;
+    match first_after {
+        Some(before) => recorded(before),
+        None => Err(anyhow!("No recorded history for {} {}{}", entity, id, key.map(|key| format!("/{}", key)).unwrap_or_default())),
+    }
+}
+
+/// Entity changed between two points in time
+#[derive(Debug, Clone, Serialize)]
+pub struct Difference {
+    pub entity: String,
+    pub entity_id: i32,
+    pub entity_key: Option<i32>,
+    /// State at the start of the range, `null` when the entity did not exist
+    pub before: serde_json::Value,
+    /// State at the end of the range, `null` when the entity did not exist
+    pub after: serde_json::Value,
+    /// Number of changes made in between
+    pub changes: usize,
+}
+
+/// Entities whose state differs between `from` and `to`, replayed from the audit log changes made after `from` up to `to`
+pub fn diff(db: &mut SqliteConnection, entity: Option<&str>, from: i64, to: i64) -> anyhow::Result<Vec<Difference>> {
+    let mut statement = schema::audit_log::table
+        .filter(schema::audit_log::timestamp.gt(from))
+        .filter(schema::audit_log::timestamp.le(to))
+        .into_boxed();
+    if let Some(entity) = entity {
+        statement = statement.filter(schema::audit_log::entity.eq(entity.to_string()));
+    }
+    let mut differences: Vec<Difference> = Vec::new();
+    for change in statement.order(schema::audit_log::id.asc()).load::<AuditLog>(db)? {
+        match differences.iter_mut().find(|difference| difference.entity == change.entity && difference.entity_id == change.entity_id && difference.entity_key == change.entity_key) {
+            Some(difference) => {
+                difference.after = recorded(change.after)?;
+                difference.changes += 1;
+            },
+            None => differences.push(Difference {
+                entity: change.entity,
+                entity_id: change.entity_id,
+                entity_key: change.entity_key,
+                before: recorded(change.before)?,
+                after: recorded(change.after)?,
+                changes: 1,
+            }),
+        }
+    }
+    differences.retain(|difference| difference.before != difference.after);
+    Ok(differences)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AsOfQuery {
+    pub as_of: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffQuery {
+    pub entity: Option<String>,
+    pub from: String,
+    /// Defaults to now
+    pub to: Option<String>,
+}
+
+/// `GET /audit/{entity}/{id}?as_of=`: state of an entity at a point in time
+pub async fn show(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, path: web::Path<(String, i32)>, query: web::Query<AsOfQuery>) -> Responder<AppData<'static>> {
+    let (entity, id) = path.into_inner();
+    show_state(app_data, principal, entity, id, None, query.into_inner()).await
+}
+
+/// `GET /audit/{entity}/{id}/{key}?as_of=`: state of an entity identified by a pair, such as the value of variable `key` on host `id`, at a point in time
+pub async fn show_keyed(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, path: web::Path<(String, i32, i32)>, query: web::Query<AsOfQuery>) -> Responder<AppData<'static>> {
+    let (entity, id, key) = path.into_inner();
+    show_state(app_data, principal, entity, id, Some(key), query.into_inner()).await
+}
+
+async fn show_state(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, entity: String, id: i32, key: Option<i32>, query: AsOfQuery) -> Responder<AppData<'static>> {
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        let as_of = match parse_time(&query.as_of) {
+            Ok(as_of) => as_of,
+            Err(err) => return Responder::<AppData<'static>>::from(err).with_status_code(StatusCode::BAD_REQUEST),
+        };
+        let context = serde_json::json!({ "entity": entity, "id": id, "key": key, "as_of": as_of });
+        match state_at(&mut db, &entity, id, key, as_of) {
+            Ok(state) => Responder::<AppData<'static>>::from(anyhow::Result::<serde_json::Value>::Ok(state)).with_context(context),
+            Err(err) => Responder::<AppData<'static>>::from(err).with_status_code(StatusCode::NOT_FOUND),
+        }
+    }).await)
+        .with_template("audit/show")
+        .with_error_template("audit/error")
+}
+
+/// `GET /audit/diff?from=&to=`: entities changed between two points in time. With `entity=inventory`, groups and hosts whose inventory entry changed
+pub async fn difference(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, web::Query(query): web::Query<DiffQuery>) -> Responder<AppData<'static>> {
+    let principal = principal.map(|principal| principal.into_inner());
+    let template = match query.entity.as_deref() {
+        Some(INVENTORY) => "audit/inventory_diff",
+        _ => "audit/diff",
+    };
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        let range = parse_time(&query.from).and_then(|from| Ok((from, query.to.as_deref().map(parse_time).transpose()?.unwrap_or_else(auth::now))));
+        let (from, to) = match range {
+            Ok(range) => range,
+            Err(err) => return Responder::<AppData<'static>>::from(err).with_status_code(StatusCode::BAD_REQUEST),
+        };
+        let context = serde_json::json!({ "entity": query.entity, "from": from, "to": to });
+        if query.entity.as_deref() == Some(INVENTORY) {
+            return match history::diff(&app_data, from, to) {
+                Ok(changes) => Responder::<AppData<'static>>::from(anyhow::Result::<Vec<history::Change>>::Ok(changes)).with_context(context),
+                Err(err) => err.into(),
+            };
+        }
+        diff(&mut db, query.entity.as_deref().filter(|entity| !entity.is_empty()), from, to)
+            .map(|differences| Responder::<AppData<'static>>::from(anyhow::Result::<Vec<Difference>>::Ok(differences)).with_context(context))
+            .unwrap_or_else(|err| err.into())
+    }).await)
+        .with_template(template)
+        .with_error_template("audit/error")
+}
+
+/// `GET /audit`: audit log, filtered by entity and time range. Readable by any role
+pub async fn list(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, web::Query(query): web::Query<AuditQuery>) -> Responder<AppData<'static>> {
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        let context = serde_json::to_value(&query).unwrap_or_default();
+        match search(&mut db, &query) {
+            Ok(entries) => Responder::<AppData<'static>>::from(anyhow::Result::<Vec<AuditLog>>::Ok(entries)).with_context(context),
+            Err(err) => Responder::<AppData<'static>>::from(err).with_status_code(StatusCode::BAD_REQUEST)
+        }
+    }).await)
+        .with_template("audit/list")
+        .with_error_template("audit/error")
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/audit", web::get().to(list))
+        .route("/audit/diff", web::get().to(difference))
+        .route("/audit/{entity}/{id}", web::get().to(show))
+        .route("/audit/{entity}/{id}/{key}", web::get().to(show_keyed));
+}
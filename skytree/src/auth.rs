@@ -0,0 +1,312 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{body::MessageBody, cookie::{Cookie, SameSite}, dev::{ServiceRequest, ServiceResponse}, http::{header, StatusCode}, middleware::Next, web, HttpMessage, HttpRequest, HttpResponse, Responder as _};
+use anyhow::{anyhow, bail};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::{SaltString, rand_core::OsRng}};
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use rand::Rng;
+use rest::{DbFactory, JsonOrForm, Principal};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::{AppData, schema};
+
+/// Cookie holding the session token of a logged in user
+pub const SESSION_COOKIE: &str = "session";
+/// Session lifetime, in seconds
+pub const SESSION_LIFETIME: i64 = 12 * 60 * 60;
+pub const LOGIN_PATH: &str = "/login";
+pub const LOGOUT_PATH: &str = "/logout";
+
+#[derive(Debug, Clone, Serialize, Queryable)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Queryable)]
+pub struct ApiToken {
+    pub id: i32,
+    pub user: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub created: i64,
+    pub revoked: Option<i64>,
+}
+
+pub(crate) fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs() as i64).unwrap_or_default()
+}
+
+/// Random 256 bit token, hex encoded
+fn random_token() -> String {
+    let mut rng = rand::thread_rng();
+    (0..32).map(|_| format!("{:02x}", rng.gen::<u8>())).collect()
+}
+
+/// Tokens are random, so a plain digest is enough to avoid storing them in the clear
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    Argon2::default()
+        .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+        .map(|hash| hash.to_string())
+        .map_err(|err| anyhow!("Unable to hash password: {}", err))
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+pub fn find_user(db: &mut SqliteConnection, name: &str) -> anyhow::Result<User> {
+    schema::user::table
+        .filter(schema::user::name.eq(name))
+        .first::<User>(db)
+        .optional()?
+        .ok_or_else(|| anyhow!("User {} not found", name))
+}
+
+pub fn create_user(db: &mut SqliteConnection, name: &str, password: &str) -> anyhow::Result<User> {
+    if name.trim().is_empty() {
+        bail!("User name must not be empty");
+    }
+    Ok(diesel::insert_into(schema::user::table)
+        .values((schema::user::name.eq(name), schema::user::password_hash.eq(hash_password(password)?)))
+        .get_result::<User>(db)?)
+}
+
+/// Changes the password of a user, logging out all of their sessions
+pub fn set_password(db: &mut SqliteConnection, name: &str, password: &str) -> anyhow::Result<User> {
+    let user = find_user(db, name)?;
+    let password_hash = hash_password(password)?;
+    db.transaction(|db| {
+        diesel::delete(schema::session::table.filter(schema::session::user.eq(user.id))).execute(db)?;
+        Ok(diesel::update(schema::user::table.find(user.id))
+            .set(schema::user::password_hash.eq(password_hash))
+            .get_result::<User>(db)?)
+    })
+}
+
+/// Deletes a user along with their sessions, API tokens and role grants
+pub fn delete_user(db: &mut SqliteConnection, name: &str) -> anyhow::Result<User> {
+    let user = find_user(db, name)?;
+    db.transaction(|db| {
+        diesel::delete(schema::session::table.filter(schema::session::user.eq(user.id))).execute(db)?;
+        diesel::delete(schema::role_grant::table.filter(schema::role_grant::user.eq(user.id))).execute(db)?;
+        diesel::delete(schema::api_token::table.filter(schema::api_token::user.eq(user.id))).execute(db)?;
+        Ok(diesel::delete(schema::user::table.find(user.id)).get_result::<User>(db)?)
+    })
+}
+
+/// Creates an API token for a user. The token itself is only returned here, the database keeps its hash
+pub fn create_token(db: &mut SqliteConnection, user_name: &str, token_name: &str) -> anyhow::Result<(ApiToken, String)> {
+    let user = find_user(db, user_name)?;
+    let token = random_token();
+    let api_token = diesel::insert_into(schema::api_token::table)
+        .values((
+            schema::api_token::user.eq(user.id),
+            schema::api_token::name.eq(token_name),
+            schema::api_token::token_hash.eq(token_hash(&token)),
+            schema::api_token::created.eq(now()),
+        ))
+        .get_result::<ApiToken>(db)?;
+    Ok((api_token, token))
+}
+
+pub fn revoke_token(db: &mut SqliteConnection, id: i32) -> anyhow::Result<ApiToken> {
+    diesel::update(schema::api_token::table.find(id).filter(schema::api_token::revoked.is_null()))
+        .set(schema::api_token::revoked.eq(now()))
+        .get_result::<ApiToken>(db)
+        .optional()?
+        .ok_or_else(|| anyhow!("Active API token {} not found", id))
+}
+
+/// API tokens, with the name of their user
+pub fn list_tokens(db: &mut SqliteConnection) -> anyhow::Result<Vec<(ApiToken, String)>> {
+    Ok(schema::api_token::table
+        .inner_join(schema::user::table)
+        .select((schema::api_token::all_columns, schema::user::name))
+        .order(schema::api_token::id)
+        .load::<(ApiToken, String)>(db)?)
+}
+
+fn authenticate_token(db: &mut SqliteConnection, token: &str) -> anyhow::Result<Option<Principal>> {
+    Ok(schema::api_token::table
+        .inner_join(schema::user::table)
+        .filter(schema::api_token::token_hash.eq(token_hash(token)))
+        .filter(schema::api_token::revoked.is_null())
+        .select((schema::user::id, schema::user::name, schema::api_token::id))
+        .first::<(i32, String, i32)>(db)
+        .optional()?
+        .map(|(user, name, token)| Principal { user, name, token: Some(token) }))
+}
+
+fn authenticate_session(db: &mut SqliteConnection, token: &str) -> anyhow::Result<Option<Principal>> {
+    Ok(schema::session::table
+        .inner_join(schema::user::table)
+        .filter(schema::session::token_hash.eq(token_hash(token)))
+        .filter(schema::session::expires.gt(now()))
+        .select((schema::user::id, schema::user::name))
+        .first::<(i32, String)>(db)
+        .optional()?
+        .map(|(user, name)| Principal { user, name, token: None }))
+}
+
+/// Checks the credentials of a user and opens a session, returning the session token
+fn create_session(db: &mut SqliteConnection, name: &str, password: &str) -> anyhow::Result<String> {
+    let user = match find_user(db, name) {
+        Ok(user) if verify_password(password, &user.password_hash) => user,
+        _ => bail!("Invalid user name or password")
+    };
+    let token = random_token();
+    diesel::delete(schema::session::table.filter(schema::session::expires.le(now()))).execute(db)?;
+    diesel::insert_into(schema::session::table)
+        .values((
+            schema::session::user.eq(user.id),
+            schema::session::token_hash.eq(token_hash(&token)),
+            schema::session::expires.eq(now() + SESSION_LIFETIME),
+        ))
+        .execute(db)?;
+    Ok(token)
+}
+
+fn delete_session(db: &mut SqliteConnection, token: &str) -> anyhow::Result<()> {
+    diesel::delete(schema::session::table.filter(schema::session::token_hash.eq(token_hash(token)))).execute(db)?;
+    Ok(())
+}
+
+/// Authentication middleware.
+///
+/// Requests are authenticated with an `Authorization: Bearer <token>` API token or a session cookie, and the resulting
+/// `rest::Principal` is stored in the request extensions. Unauthenticated browsers are redirected to the login page,
+/// other clients get `401 Unauthorized`.
+pub async fn authenticate(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if req.path() == LOGIN_PATH {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let bearer = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|authorization| authorization.to_str().ok())
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    let session = req.cookie(SESSION_COOKIE).map(|cookie| cookie.value().to_string());
+    let principal = match (req.app_data::<web::Data<AppData<'static>>>().cloned(), bearer.clone(), session) {
+        (Some(app_data), Some(token), _) => web::block(move || authenticate_token(&mut app_data.db()?, &token)).await?,
+        (Some(app_data), None, Some(token)) => web::block(move || authenticate_session(&mut app_data.db()?, &token)).await?,
+        _ => Ok(None)
+    };
+    match principal {
+        Ok(Some(principal)) => {
+            req.extensions_mut().insert(principal);
+            Ok(next.call(req).await?.map_into_left_body())
+        },
+        Ok(None) => {
+            let is_browser = bearer.is_none() && !req
+                .headers()
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .unwrap_or_default()
+                .contains("application/json");
+            let response = if is_browser {
+                let next_location = req.uri().path_and_query().map(|path| path.as_str()).unwrap_or("/");
+                let query = serde_urlencoded::to_string([("next", next_location)]).unwrap_or_default();
+                HttpResponse::SeeOther()
+                    .insert_header((header::LOCATION, format!("{}?{}", LOGIN_PATH, query)))
+                    .finish()
+            } else {
+                let mut response = Responder::<AppData<'static>>::from(anyhow!("Authentication required")).respond_to(req.request());
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                response.headers_mut().insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+                response
+            };
+            Ok(req.into_response(response).map_into_right_body())
+        },
+        Err(err) if err.is::<rest::Unavailable>() => Err(actix_web::error::ErrorServiceUnavailable(err)),
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err))
+    }
+}
+
+/// Only local paths are accepted as the post-login location, so the login form cannot redirect off-site
+fn local_location(location: Option<String>) -> String {
+    location
+        .filter(|location| location.starts_with('/') && !location.starts_with("//"))
+        .unwrap_or_else(|| "/".to_string())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginParameters {
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    pub username: String,
+    pub password: String,
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutForm {}
+
+pub async fn login_form(web::Query(parameters): web::Query<LoginParameters>) -> Responder<AppData<'static>> {
+    Responder::<AppData<'static>>::default()
+        .with_template("login")
+        .with_context(serde_json::json!({ "next": local_location(parameters.next) }))
+}
+
+pub async fn login(req: HttpRequest, app_data: web::Data<AppData<'static>>, JsonOrForm(form): JsonOrForm<LoginForm>) -> HttpResponse {
+    let location = local_location(form.next);
+    let session = web::block(move || create_session(&mut app_data.db()?, &form.username, &form.password))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|session| session);
+    match session {
+        Ok(token) => HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, location))
+            .cookie(Cookie::build(SESSION_COOKIE, token)
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .max_age(actix_web::cookie::time::Duration::seconds(SESSION_LIFETIME))
+                .finish())
+            .finish(),
+        Err(err) if err.is::<rest::Unavailable>() => Responder::<AppData<'static>>::from(err)
+            .with_status_code(StatusCode::SERVICE_UNAVAILABLE)
+            .with_error_template("login")
+            .with_context(serde_json::json!({ "next": location }))
+            .respond_to(&req),
+        Err(err) => Responder::<AppData<'static>>::from(err)
+            .with_error_template("login")
+            .with_context(serde_json::json!({ "next": location }))
+            .respond_to(&req)
+    }
+}
+
+pub async fn logout(req: HttpRequest, app_data: web::Data<AppData<'static>>, _form: JsonOrForm<LogoutForm>) -> actix_web::Result<HttpResponse> {
+    let mut response = HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, LOGIN_PATH))
+        .finish();
+    if let Some(mut cookie) = req.cookie(SESSION_COOKIE) {
+        let token = cookie.value().to_string();
+        web::block(move || delete_session(&mut app_data.db()?, &token))
+            .await?
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        cookie.set_path("/");
+        response.add_removal_cookie(&cookie)?;
+    }
+    Ok(response)
+}
+
+/// Registers the login and logout routes
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route(LOGIN_PATH, web::get().to(login_form))
+        .route(LOGIN_PATH, web::post().to(login))
+        .route(LOGOUT_PATH, web::post().to(logout));
+}
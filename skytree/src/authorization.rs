@@ -0,0 +1,114 @@
+use std::{fmt, str::FromStr};
+use anyhow::{anyhow, bail};
+use diesel::{prelude::*, SqliteConnection};
+use rest::{Crud, Principal};
+use serde::{Deserialize, Serialize};
+use crate::{schema, skytree::{host::Host, host_group::HostGroup}};
+
+/// Roles, from least to most privileged. Each role includes the rights of the previous ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read the inventory
+    Viewer,
+    /// Create, update and delete inventory entries
+    Editor,
+    /// Grant roles
+    Admin,
+}
+impl FromStr for Role {
+    type Err = anyhow::Error;
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            _ => bail!("Unknown role {}, expected viewer, editor or admin", role)
+        }
+    }
+}
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+/// Where a role is required
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// A global grant is needed
+    Global,
+    /// A grant on any scope will do
+    Anywhere,
+    /// A global grant, or a grant on one of these groups or on one of their ancestors
+    Groups(Vec<i32>),
+}
+impl Scope {
+    /// Scope of an entry placed under `group`. Entries outside the group hierarchy are global
+    pub fn group(group: Option<i32>) -> Scope {
+        match group {
+            Some(group) => Scope::Groups(vec![group]),
+            None => Scope::Global,
+        }
+    }
+    /// Scope of a host: the groups it is a member of. Hosts outside any group are global
+    pub fn host(db: &mut SqliteConnection, host: i32) -> anyhow::Result<Scope> {
+        let groups = Host::groups(db, host)?.into_iter().map(|group| group.id).collect::<Vec<i32>>();
+        Ok(if groups.is_empty() { Scope::Global } else { Scope::Groups(groups) })
+    }
+}
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Global => f.write_str("globally"),
+            Scope::Anywhere => f.write_str("on any scope"),
+            Scope::Groups(groups) => write!(f, "on host group {}", groups.iter().map(|group| group.to_string()).collect::<Vec<String>>().join(" or ")),
+        }
+    }
+}
+
+/// Groups of `groups` and all of their ancestors
+fn lineage(db: &mut SqliteConnection, groups: &[i32]) -> anyhow::Result<Vec<i32>> {
+    let mut lineage = Vec::new();
+    for group in groups {
+        let group = HostGroup::db_fetch(db, *group)?;
+        lineage.extend(HostGroup::ancestors(db, &group)?.into_iter().map(|ancestor| ancestor.id));
+        lineage.push(group.id);
+    }
+    Ok(lineage)
+}
+
+/// Fails unless `principal` holds `role`, or a more privileged role, on `scope`
+pub fn require(db: &mut SqliteConnection, principal: Option<&Principal>, role: Role, scope: Scope) -> anyhow::Result<()> {
+    let principal = principal.ok_or_else(|| anyhow!("Authentication required"))?;
+    let grants = schema::role_grant::table
+        .filter(schema::role_grant::user.eq(principal.user))
+        .select((schema::role_grant::role, schema::role_grant::host_group))
+        .load::<(String, Option<i32>)>(db)?
+        .into_iter()
+        .filter(|(granted, _)| granted.parse::<Role>().map(|granted| granted >= role).unwrap_or(false))
+        .map(|(_, group)| group)
+        .collect::<Vec<Option<i32>>>();
+    let allowed = match &scope {
+        Scope::Anywhere => !grants.is_empty(),
+        _ if grants.contains(&None) => true,
+        Scope::Global => false,
+        Scope::Groups(groups) => {
+            let lineage = lineage(db, groups)?;
+            grants.iter().flatten().any(|group| lineage.contains(group))
+        },
+    };
+    if !allowed {
+        bail!("Permission denied: {} needs the {} role {}", principal.name, role, scope);
+    }
+    Ok(())
+}
+
+/// Read access to the inventory, granted by any role on any scope
+pub fn require_viewer(db: &mut SqliteConnection, principal: Option<&Principal>) -> anyhow::Result<()> {
+    require(db, principal, Role::Viewer, Scope::Anywhere)
+}
@@ -0,0 +1,210 @@
+use actix_web::web;
+use anyhow::anyhow;
+use diesel::{Connection, SqliteConnection};
+use negotiated::Responder;
+use rest::{DbFactory, OperationError, Principal, RequestId, RequestPrincipal, RestOperation};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use crate::{AppData, memberships, variables::{self, Owner}, skytree::{host::{Host, NewHost}, host_group::{HostGroup, NewHostGroup}, role_grant::{NewRoleGrant, RoleGrant}, service::{NewService, Service}, service_endpoint::{NewServiceEndpoint, ServiceEndpoint}, service_instance::{NewServiceInstance, ServiceInstance}, service_instance_relation::{NewServiceInstanceRelation, ServiceInstanceRelation}, service_relation::{NewServiceRelation, ServiceRelation}, variable::{NewVariable, Variable}}};
+
+/// Operation of a changeset: a create, update or delete on a resource named as in its REST path (`host_group`, `host`, ...),
+/// a change of a variable value on an entry, or a change of the explicit groups of a host.
+///
+/// Ids, bodies and memberships may refer to the entity created or updated by an earlier operation of the changeset with `{"$ref": <operation index>}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Create { resource: String, body: Value },
+    Update { resource: String, id: Value, body: Value },
+    Delete { resource: String, id: Value },
+    /// Sets the value of `variable`, by id or name, on entry `id` of `resource`, like `PUT /{resource}/{id}/variables/{variable}`
+    SetVariable { resource: String, id: Value, variable: Value, value: Value },
+    /// Removes the value of `variable`, like `DELETE /{resource}/{id}/variables/{variable}`
+    UnsetVariable { resource: String, id: Value, variable: Value },
+    /// Makes `host` an explicit member of `group`, like `PUT /host/{id}/groups/{group}`
+    AddMember { host: Value, group: Value },
+    /// Removes `host` from the explicit members of `group`, like `DELETE /host/{id}/groups/{group}`
+    RemoveMember { host: Value, group: Value },
+}
+impl Operation {
+    /// The operation with its `$ref`s replaced by the ids they refer to. Variable values are taken as they are
+    fn resolved(self, results: &[OperationResult]) -> anyhow::Result<Operation> {
+        Ok(match self {
+            Operation::Create { resource, body } => Operation::Create { resource, body: resolve(&body, results)? },
+            Operation::Update { resource, id, body } => Operation::Update { resource, id: resolve(&id, results)?, body: resolve(&body, results)? },
+            Operation::Delete { resource, id } => Operation::Delete { resource, id: resolve(&id, results)? },
+            Operation::SetVariable { resource, id, variable, value } => Operation::SetVariable { resource, id: resolve(&id, results)?, variable: resolve(&variable, results)?, value },
+            Operation::UnsetVariable { resource, id, variable } => Operation::UnsetVariable { resource, id: resolve(&id, results)?, variable: resolve(&variable, results)? },
+            Operation::AddMember { host, group } => Operation::AddMember { host: resolve(&host, results)?, group: resolve(&group, results)? },
+            Operation::RemoveMember { host, group } => Operation::RemoveMember { host: resolve(&host, results)?, group: resolve(&group, results)? },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum OperationStatus {
+    /// Applied, the changeset was committed
+    Success,
+    /// Applied, then rolled back by the failure of a later operation
+    RolledBack,
+    /// The failed operation
+    Error,
+    /// Not attempted, after the failed operation
+    Skipped,
+}
+
+/// Outcome of an operation of a changeset
+#[derive(Debug, Serialize)]
+pub struct OperationResult {
+    pub status: OperationStatus,
+    /// Entity created, updated or deleted
+    pub payload: Option<Value>,
+    pub error: Option<String>,
+}
+
+/// Replaces `{"$ref": <index>}` by the id of the entity returned by operation `<index>`
+fn resolve(value: &Value, results: &[OperationResult]) -> anyhow::Result<Value> {
+    Ok(match value {
+        Value::Object(object) if object.len() == 1 && object.contains_key("$ref") => {
+            let index = object["$ref"].as_u64().ok_or_else(|| anyhow!("$ref must be the index of an earlier operation"))? as usize;
+            results
+                .get(index)
+                .and_then(|result| result.payload.as_ref())
+                .and_then(|payload| payload.get("id"))
+                .cloned()
+                .ok_or_else(|| anyhow!("$ref {} does not refer to an earlier operation", index))?
+        },
+        Value::Object(object) => Value::Object(object
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), resolve(value, results)?)))
+            .collect::<anyhow::Result<serde_json::Map<String, Value>>>()?),
+        Value::Array(values) => Value::Array(values.iter().map(|value| resolve(value, results)).collect::<anyhow::Result<Vec<Value>>>()?),
+        value => value.clone(),
+    })
+}
+
+fn id_of(id: &Value) -> anyhow::Result<i32> {
+    id.as_i64()
+        .and_then(|id| i32::try_from(id).ok())
+        .ok_or_else(|| anyhow!("Invalid id {}", id))
+}
+
+/// Variable given by id or name
+fn variable_of(variable: &Value) -> anyhow::Result<String> {
+    match variable {
+        Value::String(name) => Ok(name.clone()),
+        Value::Number(id) => Ok(id.to_string()),
+        variable => Err(anyhow!("Invalid variable {}", variable)),
+    }
+}
+
+fn payload<T: Serialize>(result: T) -> Result<Value, OperationError> {
+    Ok(serde_json::to_value(result).map_err(anyhow::Error::from)?)
+}
+
+/// Create, update or delete operation, already resolved, without its resource
+enum Change {
+    Create { body: Value },
+    Update { id: Value, body: Value },
+    Delete { id: Value },
+}
+
+/// Applies a change to resource `T`
+fn apply<T, NewT>(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, change: Change) -> Result<Value, OperationError>
+where T: RestOperation<T, NewT, AppData<'static>, SqliteConnection> + Serialize + DeserializeOwned,
+      NewT: DeserializeOwned
+{
+    let result = match change {
+        Change::Create { body } => T::create(app_data, db, principal, request_id, serde_json::from_value(body).map_err(anyhow::Error::from)?)?,
+        Change::Update { id, mut body } => {
+            let id = id_of(&id)?;
+            if let Value::Object(object) = &mut body {
+                object.entry("id").or_insert(Value::from(id));
+            }
+            T::update(app_data, db, principal, request_id, id, serde_json::from_value(body).map_err(anyhow::Error::from)?)?
+        },
+        Change::Delete { id } => T::delete(app_data, db, principal, request_id, id_of(&id)?)?,
+    };
+    payload(result)
+}
+
+fn dispatch(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, operation: Operation) -> Result<Value, OperationError> {
+    let (resource, change) = match operation {
+        Operation::Create { resource, body } => (resource, Change::Create { body }),
+        Operation::Update { resource, id, body } => (resource, Change::Update { id, body }),
+        Operation::Delete { resource, id } => (resource, Change::Delete { id }),
+        Operation::SetVariable { resource, id, variable, value } => {
+            let owner = Owner::of(&resource, id_of(&id)?)?;
+            return payload(variables::set(app_data, db, principal, request_id, owner, &variable_of(&variable)?, value)?);
+        },
+        Operation::UnsetVariable { resource, id, variable } => {
+            let owner = Owner::of(&resource, id_of(&id)?)?;
+            return payload(variables::unset(app_data, db, principal, request_id, owner, &variable_of(&variable)?)?);
+        },
+        Operation::AddMember { host, group } => return payload(memberships::join(app_data, db, principal, request_id, id_of(&host)?, id_of(&group)?)?),
+        Operation::RemoveMember { host, group } => return payload(memberships::leave(app_data, db, principal, request_id, id_of(&host)?, id_of(&group)?)?),
+    };
+    match resource.as_str() {
+        "host_group" => apply::<HostGroup, NewHostGroup>(app_data, db, principal, request_id, change),
+        "host" => apply::<Host, NewHost>(app_data, db, principal, request_id, change),
+        "service" => apply::<Service, NewService>(app_data, db, principal, request_id, change),
+        "service_instance" => apply::<ServiceInstance, NewServiceInstance>(app_data, db, principal, request_id, change),
+        "service_endpoint" => apply::<ServiceEndpoint, NewServiceEndpoint>(app_data, db, principal, request_id, change),
+        "service_relation" => apply::<ServiceRelation, NewServiceRelation>(app_data, db, principal, request_id, change),
+        "service_instance_relation" => apply::<ServiceInstanceRelation, NewServiceInstanceRelation>(app_data, db, principal, request_id, change),
+        "variable" => apply::<Variable, NewVariable>(app_data, db, principal, request_id, change),
+        "role_grant" => apply::<RoleGrant, NewRoleGrant>(app_data, db, principal, request_id, change),
+        resource => Err(OperationError::Failed(anyhow!("Unknown resource {}", resource))),
+    }
+}
+
+/// Applies `operations` in order, in a single transaction rolled back on the first failure
+pub fn apply_changeset(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, operations: Vec<Operation>) -> (Vec<OperationResult>, Option<(usize, OperationError)>) {
+    let count = operations.len();
+    let mut results: Vec<OperationResult> = Vec::new();
+    let outcome = db.transaction::<(), OperationError, _>(|db| {
+        for operation in operations {
+            let resolved = operation.resolved(&results)?;
+            let payload = dispatch(app_data, db, principal, request_id, resolved)?;
+            results.push(OperationResult { status: OperationStatus::Success, payload: Some(payload), error: None });
+        }
+        Ok(())
+    });
+    match outcome {
+        Ok(()) => (results, None),
+        Err(err) => {
+            // operations before the failed one all succeeded
+            let index = results.len();
+            results.iter_mut().for_each(|result| result.status = OperationStatus::RolledBack);
+            results.push(OperationResult { status: OperationStatus::Error, payload: None, error: Some(err.to_string()) });
+            results.extend((index + 1..count).map(|_| OperationResult { status: OperationStatus::Skipped, payload: None, error: None }));
+            (results, Some((index, err)))
+        }
+    }
+}
+
+/// `POST /changeset`: applies a JSON array of operations atomically, answering with the result of each operation.
+///
+/// When an operation fails, the response is an error carrying the results in its payload, with `403 Forbidden` if the operation was denied
+pub async fn post(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, web::Json(operations): web::Json<Vec<Operation>>) -> Responder<AppData<'static>> {
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        let (results, failure) = apply_changeset(&app_data, &mut db, principal.as_ref(), &request_id, operations);
+        match failure {
+            None => Responder::<AppData<'static>>::from(anyhow::Result::<Vec<OperationResult>>::Ok(results)),
+            Some((index, err)) => {
+                let mut responder = Responder::<AppData<'static>>::from(anyhow!("Operation {} failed, changeset rolled back: {}", index, err)).with_status_code(err.status_code());
+                responder.payload = Some(Box::new(results));
+                responder
+            }
+        }
+    }).await)
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.route("/changeset", web::post().to(post));
+}
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, fmt, net::SocketAddr, path::PathBuf, str::FromStr};
+use anyhow::{anyhow, bail, Context};
+use clap::Args;
+use serde::Serialize;
+
+pub const DEFAULT_CONFIG_FILE: &str = "/etc/skytree.ini";
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:3000";
+pub const DEFAULT_TEMPLATE_DIR: &str = "templates";
+const SECTION: &str = "skytree";
+const KEYS: [&str; 8] = ["listen", "workers", "database_url", "template_dir", "dev_mode", "secret_key_file", "vault_password_file", "fact_paths"];
+/// Facts retained from the output of the Ansible `setup` module when `fact_paths` is not set
+pub const DEFAULT_FACT_PATHS: &[&str] = &[
+    "ansible_architecture",
+    "ansible_distribution",
+    "ansible_distribution_major_version",
+    "ansible_distribution_release",
+    "ansible_distribution_version",
+    "ansible_os_family",
+    "ansible_kernel",
+    "ansible_fqdn",
+    "ansible_hostname",
+    "ansible_default_ipv4.address",
+    "ansible_default_ipv6.address",
+    "ansible_processor_vcpus",
+    "ansible_memtotal_mb",
+    "ansible_virtualization_role",
+    "ansible_virtualization_type",
+];
+
+/// Address the server listens on: `127.0.0.1:3000`, `[::1]:3000` or `unix:/run/skytree.sock`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+impl FromStr for ListenAddress {
+    type Err = anyhow::Error;
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let address = address.trim();
+        match address.strip_prefix("unix:") {
+            Some("") => bail!("invalid listen address `{}`: missing socket path", address),
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => address
+                .parse::<SocketAddr>()
+                .map(ListenAddress::Tcp)
+                .map_err(|_| anyhow!("invalid listen address `{}`: expected ip:port, [ipv6]:port or unix:/path/to/socket", address))
+        }
+    }
+}
+impl Serialize for ListenAddress {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{}", address),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Command line flags overriding the configuration file. Each flag can also be set through its environment variable.
+#[derive(Args, Debug, Default)]
+pub struct ConfigOverrides {
+    /// Address to listen on, repeatable: ip:port, [ipv6]:port or unix:/path/to/socket
+    #[arg(short='l', long="listen", env="SKYTREE_LISTEN", value_delimiter=',')]
+    pub listen: Vec<String>,
+    /// Number of worker threads. Defaults to the number of CPUs
+    #[arg(short='w', long="workers", env="SKYTREE_WORKERS")]
+    pub workers: Option<String>,
+    /// Inventory database
+    #[arg(long="database-url", env="DATABASE_URL")]
+    pub database_url: Option<String>,
+    /// Directory with template overrides
+    #[arg(long="template-dir", env="SKYTREE_TEMPLATE_DIR")]
+    pub template_dir: Option<String>,
+    /// Reload templates on change and show detailed template errors
+    #[arg(long="dev-mode", env="SKYTREE_DEV_MODE")]
+    pub dev_mode: Option<String>,
+    /// File holding the key secret variable values are encrypted with
+    #[arg(long="secret-key-file", env="SKYTREE_SECRET_KEY_FILE")]
+    pub secret_key_file: Option<String>,
+    /// Ansible vault password file, to output secret variable values as vault ciphertext
+    #[arg(long="vault-password-file", env="SKYTREE_VAULT_PASSWORD_FILE")]
+    pub vault_password_file: Option<String>,
+    /// Comma separated facts retained from the Ansible setup module output, as dotted paths, or `*` for all facts
+    #[arg(long="fact-paths", env="SKYTREE_FACT_PATHS")]
+    pub fact_paths: Option<String>,
+}
+
+/// Settings of the `[skytree]` configuration file section, after applying command line and environment overrides.
+///
+/// Serialized for templates, without the database URL.
+#[derive(Debug, Clone, Serialize)]
+pub struct Config {
+    pub listen: Vec<ListenAddress>,
+    pub workers: Option<usize>,
+    #[serde(skip_serializing)]
+    pub database_url: Option<String>,
+    /// Absolute path, always ending in `/`
+    pub template_dir: String,
+    pub dev_mode: bool,
+    #[serde(skip_serializing)]
+    pub secret_key_file: Option<String>,
+    #[serde(skip_serializing)]
+    pub vault_password_file: Option<String>,
+    /// Dotted paths of the facts retained per host. `*` retains every fact
+    pub fact_paths: Vec<String>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec![DEFAULT_LISTEN.parse().unwrap()],
+            workers: None,
+            database_url: None,
+            template_dir: DEFAULT_TEMPLATE_DIR.to_string(),
+            dev_mode: false,
+            secret_key_file: None,
+            vault_password_file: None,
+            fact_paths: DEFAULT_FACT_PATHS.iter().map(|path| path.to_string()).collect(),
+        }
+    }
+}
+impl Config {
+    /// Loads the configuration file, if any, and applies the overrides.
+    ///
+    /// An explicitly given configuration file must exist, while a missing default configuration file just means defaults apply.
+    pub fn load(config_file: Option<&str>, overrides: &ConfigOverrides) -> anyhow::Result<Config> {
+        let mut settings = match config_file {
+            Some(config_file) => Config::read(config_file)?,
+            None if std::path::Path::new(DEFAULT_CONFIG_FILE).exists() => Config::read(DEFAULT_CONFIG_FILE)?,
+            None => {
+                log::info!("{} not found, using default configuration", DEFAULT_CONFIG_FILE);
+                HashMap::new()
+            }
+        };
+        if !overrides.listen.is_empty() {
+            settings.insert("listen".to_string(), overrides.listen.join(","));
+        }
+        for (key, value) in [("workers", &overrides.workers), ("database_url", &overrides.database_url), ("template_dir", &overrides.template_dir), ("dev_mode", &overrides.dev_mode), ("secret_key_file", &overrides.secret_key_file), ("vault_password_file", &overrides.vault_password_file), ("fact_paths", &overrides.fact_paths)] {
+            if let Some(value) = value {
+                settings.insert(key.to_string(), value.clone());
+            }
+        }
+        Config::from_settings(&settings)
+    }
+    /// Reads the `[skytree]` section of a configuration file
+    fn read(config_file: &str) -> anyhow::Result<HashMap<String, String>> {
+        let mut sections = ini::ini!(safe config_file).map_err(|err| anyhow!("Unable to read configuration file {}: {}", config_file, err))?;
+        let settings = sections
+            .remove(SECTION)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value)))
+            .collect::<HashMap<String, String>>();
+        for key in settings.keys().filter(|key| !KEYS.contains(&key.as_str())) {
+            log::warn!("Unknown setting {} in section [{}] of {}", key, SECTION, config_file);
+        }
+        Ok(settings)
+    }
+    fn from_settings(settings: &HashMap<String, String>) -> anyhow::Result<Config> {
+        let mut config = Config::default();
+        let setting = |key: &str| settings.get(key).map(|value| value.trim()).filter(|value| !value.is_empty());
+        if let Some(listen) = setting("listen") {
+            config.listen = listen
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|address| !address.is_empty())
+                .map(ListenAddress::from_str)
+                .collect::<anyhow::Result<Vec<ListenAddress>>>()
+                .context("Invalid setting listen")?;
+        }
+        if let Some(workers) = setting("workers") {
+            config.workers = match workers.parse::<usize>() {
+                Ok(0) | Err(_) => bail!("Invalid setting workers: `{}` is not a positive number", workers),
+                Ok(workers) => Some(workers)
+            };
+        }
+        config.database_url = setting("database_url").map(str::to_string);
+        config.secret_key_file = setting("secret_key_file").map(str::to_string);
+        config.vault_password_file = setting("vault_password_file").map(str::to_string);
+        if let Some(fact_paths) = setting("fact_paths") {
+            config.fact_paths = fact_paths
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|path| !path.is_empty())
+                .map(|path| match path == "*" || path.split('.').all(|part| !part.is_empty()) {
+                    true => Ok(path.to_string()),
+                    false => Err(anyhow!("invalid fact path `{}`", path)),
+                })
+                .collect::<anyhow::Result<Vec<String>>>()
+                .context("Invalid setting fact_paths")?;
+        }
+        if let Some(template_dir) = setting("template_dir") {
+            config.template_dir = template_dir.to_string();
+        }
+        if !config.template_dir.starts_with('/') {
+            let current_dir = std::env::current_dir().context("Unable to resolve relative template_dir")?;
+            config.template_dir = current_dir.join(&config.template_dir).to_string_lossy().to_string();
+        }
+        if !config.template_dir.ends_with('/') {
+            config.template_dir.push('/');
+        }
+        if let Some(dev_mode) = setting("dev_mode") {
+            config.dev_mode = match dev_mode.to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => true,
+                "false" | "no" | "off" | "0" => false,
+                _ => bail!("Invalid setting dev_mode: `{}` is not a boolean", dev_mode)
+            };
+        }
+        Ok(config)
+    }
+    /// Checks the settings needed to run the server, as opposed to offline commands
+    pub fn validate_server(&self) -> anyhow::Result<()> {
+        if self.listen.is_empty() {
+            bail!("No listen address configured");
+        }
+        if self.database_url.is_none() {
+            bail!("No database configured: set database_url in [{}], --database-url or DATABASE_URL", SECTION);
+        }
+        Ok(())
+    }
+    /// Database URL. Only valid after `validate_server`
+    pub fn database_url(&self) -> &str {
+        self.database_url.as_deref().unwrap_or_default()
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::{BTreeMap, HashSet};
+use actix_web::{http::StatusCode, web};
+use anyhow::{anyhow, bail};
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use rest::{Crud, DbFactory, IfMatch, JsonOrForm, OperationError, Principal, RequestId, RequestPrincipal, RestOperation};
+use serde::{Deserialize, Serialize};
+use crate::{AppData, authorization, memberships, schema, secrets::Disclosure, variables::{self, Owner}};
+use crate::skytree::{host_group::{HostGroup, NewHostGroup}, role_grant::{NewRoleGrant, RoleGrant}, service::{NewService, Service}, service_endpoint::{NewServiceEndpoint, ServiceEndpoint}, service_instance::{NewServiceInstance, ServiceInstance}, service_instance_relation::{NewServiceInstanceRelation, ServiceInstanceRelation}, service_relation::{NewServiceRelation, ServiceRelation}};
+
+/// What to do with the entries using a deleted host group or service
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Refuses to delete an entry in use
+    #[default]
+    Refuse,
+    /// Deletes the descendants of the entry too, along with everything attached to them
+    Cascade,
+    /// Moves the children of the entry to its parent, and deletes everything else attached to it
+    Reparent,
+}
+
+/// Hierarchy whose entries are deleted with a [Policy]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tree {
+    HostGroup,
+    Service,
+}
+impl Tree {
+    pub fn resource(self) -> &'static str {
+        match self {
+            Tree::HostGroup => "host_group",
+            Tree::Service => "service",
+        }
+    }
+    fn title(self) -> &'static str {
+        match self {
+            Tree::HostGroup => "Host group",
+            Tree::Service => "Service",
+        }
+    }
+}
+
+/// Entity affected by a deletion, named as in its REST path
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub resource: &'static str,
+    pub id: i32,
+    pub name: Option<String>,
+}
+fn entry(resource: &'static str, id: i32, name: Option<String>) -> Entry {
+    Entry { resource, id, name }
+}
+
+/// Change made by a deletion
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Change {
+    Delete(Entry),
+    /// Moves an entry under `parent`, a root entry when `None`
+    Reparent {
+        #[serde(flatten)]
+        entry: Entry,
+        parent: Option<i32>,
+    },
+    /// Removes the value of a variable from an entry
+    Unset {
+        owner: &'static str,
+        owner_id: i32,
+        variable: i32,
+        name: Option<String>,
+    },
+    /// Removes a host from a group
+    RemoveMember { host: i32, group: i32 },
+}
+
+/// Deletion of a host group or service with a policy, previewed or applied
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub resource: &'static str,
+    pub id: i32,
+    pub name: Option<String>,
+    pub policy: Policy,
+    /// Entries using the deleted one, which the `refuse` policy refuses to delete
+    pub in_use_by: Vec<Entry>,
+    /// Changes of the deletion, in the order they are made
+    pub changes: Vec<Change>,
+    /// Whether the changes were made, rather than previewed
+    pub applied: bool,
+}
+impl Plan {
+    /// Summary of [Plan::in_use_by], such as `2 host_group, 1 host`
+    fn usage(&self) -> String {
+        let mut counts = BTreeMap::<&str, usize>::new();
+        for entry in &self.in_use_by {
+            *counts.entry(entry.resource).or_default() += 1;
+        }
+        counts.iter().map(|(resource, count)| format!("{} {}", count, resource)).collect::<Vec<String>>().join(", ")
+    }
+}
+
+fn unset_changes(db: &mut SqliteConnection, owner: Owner) -> anyhow::Result<Vec<Change>> {
+    Ok(variables::values(db, owner, &Disclosure::Redacted)?
+        .into_iter()
+        .map(|value| Change::Unset { owner: owner.resource(), owner_id: owner.id(), variable: value.variable, name: value.name })
+        .collect())
+}
+
+fn grants_of(db: &mut SqliteConnection, group: i32) -> anyhow::Result<Vec<RoleGrant>> {
+    Ok(schema::role_grant::table
+        .filter(schema::role_grant::host_group.eq(group))
+        .order(schema::role_grant::id)
+        .load::<RoleGrant>(db)?)
+}
+
+/// Relations from and to a service
+fn service_relations(db: &mut SqliteConnection, service: i32) -> anyhow::Result<Vec<ServiceRelation>> {
+    let mut relations = ServiceRelation::from_service(db, service)?;
+    relations.extend(ServiceRelation::to_service(db, service)?);
+    Ok(relations)
+}
+
+/// Changes removing what is attached to host group `id`, its children aside
+fn group_attachments(db: &mut SqliteConnection, id: i32, changes: &mut Vec<Change>) -> anyhow::Result<()> {
+    changes.extend(unset_changes(db, Owner::HostGroup(id))?);
+    for host in HostGroup::hosts(db, id)? {
+        changes.push(Change::RemoveMember { host: host.id, group: id });
+    }
+    for grant in grants_of(db, id)? {
+        changes.push(Change::Delete(entry("role_grant", grant.id, Some(grant.role))));
+    }
+    Ok(())
+}
+
+/// Changes deleting a service instance and what is attached to it
+fn instance_changes(db: &mut SqliteConnection, instance: &ServiceInstance, changes: &mut Vec<Change>) -> anyhow::Result<()> {
+    changes.extend(unset_changes(db, Owner::ServiceInstance(instance.id))?);
+    for endpoint in ServiceEndpoint::of_instance(db, instance.id)? {
+        changes.push(Change::Delete(entry("service_endpoint", endpoint.id, Some(endpoint.ip))));
+    }
+    let mut relations = ServiceInstanceRelation::from_instance(db, instance.id)?;
+    relations.extend(ServiceInstanceRelation::to_instance(db, instance.id)?);
+    for relation in relations {
+        changes.push(Change::Delete(entry("service_instance_relation", relation.id, Some(relation.kind))));
+    }
+    changes.push(Change::Delete(entry("service_instance", instance.id, Some(instance.name.clone()))));
+    Ok(())
+}
+
+/// Changes removing what is attached to service `id`, its children aside
+fn service_attachments(db: &mut SqliteConnection, id: i32, changes: &mut Vec<Change>) -> anyhow::Result<()> {
+    changes.extend(unset_changes(db, Owner::Service(id))?);
+    for instance in ServiceInstance::of_service(db, id)? {
+        instance_changes(db, &instance, changes)?;
+    }
+    for relation in service_relations(db, id)? {
+        changes.push(Change::Delete(entry("service_relation", relation.id, Some(relation.kind))));
+    }
+    Ok(())
+}
+
+/// Parent and name of entry `id`
+fn node(db: &mut SqliteConnection, tree: Tree, id: i32) -> anyhow::Result<(Option<i32>, Option<String>)> {
+    Ok(match tree {
+        Tree::HostGroup => HostGroup::db_fetch(db, id).map(|group| (group.parent, Some(group.name)))?,
+        Tree::Service => Service::db_fetch(db, id).map(|service| (service.parent, service.name))?,
+    })
+}
+
+fn children(db: &mut SqliteConnection, tree: Tree, id: i32) -> anyhow::Result<Vec<Entry>> {
+    Ok(match tree {
+        Tree::HostGroup => HostGroup::children(db, id)?.into_iter().map(|group| entry("host_group", group.id, Some(group.name))).collect(),
+        Tree::Service => Service::children(db, id)?.into_iter().map(|service| entry("service", service.id, service.name)).collect(),
+    })
+}
+
+fn attachments(db: &mut SqliteConnection, tree: Tree, id: i32, changes: &mut Vec<Change>) -> anyhow::Result<()> {
+    match tree {
+        Tree::HostGroup => group_attachments(db, id, changes),
+        Tree::Service => service_attachments(db, id, changes),
+    }
+}
+
+/// Changes deleting entry `id` and its descendants, deepest first
+fn subtree(db: &mut SqliteConnection, tree: Tree, id: i32, name: Option<String>, visited: &mut HashSet<i32>, changes: &mut Vec<Change>) -> anyhow::Result<()> {
+    if !visited.insert(id) {
+        bail!("Cycle detected in {} hierarchy at {}", tree.resource(), id);
+    }
+    for child in children(db, tree, id)? {
+        subtree(db, tree, child.id, child.name, visited, changes)?;
+    }
+    attachments(db, tree, id, changes)?;
+    changes.push(Change::Delete(entry(tree.resource(), id, name)));
+    Ok(())
+}
+
+/// Entries directly using entry `id`
+fn in_use_by(db: &mut SqliteConnection, tree: Tree, id: i32) -> anyhow::Result<Vec<Entry>> {
+    let mut in_use_by = children(db, tree, id)?;
+    let owner = match tree {
+        Tree::HostGroup => Owner::HostGroup(id),
+        Tree::Service => Owner::Service(id),
+    };
+    in_use_by.extend(variables::values(db, owner, &Disclosure::Redacted)?.into_iter().map(|value| entry("variable", value.variable, value.name)));
+    match tree {
+        Tree::HostGroup => {
+            in_use_by.extend(HostGroup::hosts(db, id)?.into_iter().map(|host| entry("host", host.id, Some(host.name))));
+            in_use_by.extend(grants_of(db, id)?.into_iter().map(|grant| entry("role_grant", grant.id, Some(grant.role))));
+        },
+        Tree::Service => {
+            in_use_by.extend(ServiceInstance::of_service(db, id)?.into_iter().map(|instance| entry("service_instance", instance.id, Some(instance.name))));
+            in_use_by.extend(service_relations(db, id)?.into_iter().map(|relation| entry("service_relation", relation.id, Some(relation.kind))));
+        },
+    }
+    Ok(in_use_by)
+}
+
+/// Changes deleting entry `id` of `tree` with `policy`
+pub fn plan(db: &mut SqliteConnection, tree: Tree, id: i32, policy: Policy) -> anyhow::Result<Plan> {
+    let (parent, name) = node(db, tree, id)?;
+    let mut changes = Vec::new();
+    match policy {
+        Policy::Refuse => changes.push(Change::Delete(entry(tree.resource(), id, name.clone()))),
+        Policy::Cascade => subtree(db, tree, id, name.clone(), &mut HashSet::new(), &mut changes)?,
+        Policy::Reparent => {
+            for child in children(db, tree, id)? {
+                changes.push(Change::Reparent { entry: child, parent });
+            }
+            attachments(db, tree, id, &mut changes)?;
+            changes.push(Change::Delete(entry(tree.resource(), id, name.clone())));
+        },
+    }
+    // relations between two deleted instances are reached from both
+    let mut deleted = HashSet::new();
+    changes.retain(|change| match change {
+        Change::Delete(entry) => deleted.insert((entry.resource, entry.id)),
+        _ => true,
+    });
+    Ok(Plan { resource: tree.resource(), id, name, policy, in_use_by: in_use_by(db, tree, id)?, changes, applied: false })
+}
+
+fn delete<T, NewT>(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, id: i32) -> Result<(), OperationError>
+where T: RestOperation<T, NewT, AppData<'static>, SqliteConnection>
+{
+    T::delete(app_data, db, principal, request_id, id).map(|_| ())
+}
+
+/// Makes a change through the REST operations of the entities, so that it is authorized and audited like a request of its own
+fn apply(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, change: &Change) -> Result<(), OperationError> {
+    match change {
+        Change::Delete(entry) => match entry.resource {
+            "host_group" => delete::<HostGroup, NewHostGroup>(app_data, db, principal, request_id, entry.id),
+            "role_grant" => delete::<RoleGrant, NewRoleGrant>(app_data, db, principal, request_id, entry.id),
+            "service" => delete::<Service, NewService>(app_data, db, principal, request_id, entry.id),
+            "service_instance" => delete::<ServiceInstance, NewServiceInstance>(app_data, db, principal, request_id, entry.id),
+            "service_endpoint" => delete::<ServiceEndpoint, NewServiceEndpoint>(app_data, db, principal, request_id, entry.id),
+            "service_relation" => delete::<ServiceRelation, NewServiceRelation>(app_data, db, principal, request_id, entry.id),
+            "service_instance_relation" => delete::<ServiceInstanceRelation, NewServiceInstanceRelation>(app_data, db, principal, request_id, entry.id),
+            resource => Err(OperationError::Failed(anyhow!("Unable to delete {} entries", resource))),
+        },
+        Change::Reparent { entry, parent } => match entry.resource {
+            "host_group" => {
+                let group = HostGroup { parent: *parent, ..HostGroup::db_fetch(db, entry.id)? };
+                HostGroup::update(app_data, db, principal, request_id, entry.id, group).map(|_| ())
+            },
+            "service" => {
+                let service = Service { parent: *parent, ..Service::db_fetch(db, entry.id)? };
+                Service::update(app_data, db, principal, request_id, entry.id, service).map(|_| ())
+            },
+            resource => Err(OperationError::Failed(anyhow!("Unable to reparent {} entries", resource))),
+        },
+        Change::Unset { owner, owner_id, variable, .. } => Ok(variables::remove(app_data.get_ref(), db, principal, request_id, Owner::of(owner, *owner_id)?, *variable)?),
+        Change::RemoveMember { host, group } => Ok(memberships::remove(app_data.get_ref(), db, principal, request_id, *host, *group)?),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteQuery {
+    #[serde(default)]
+    pub policy: Policy,
+    /// Only lists the changes the deletion would make
+    #[serde(default)]
+    pub preview: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteForm {
+    #[serde(default)]
+    pub policy: Policy,
+}
+
+/// `409 Conflict` for an entry the `refuse` policy keeps, the plan in the payload
+fn in_use(tree: Tree, plan: Plan) -> Responder<AppData<'static>> {
+    let mut responder = Responder::<AppData<'static>>::from(anyhow!("{} {} is in use by {}, delete it with policy=cascade or policy=reparent", tree.title(), plan.name.as_deref().unwrap_or_default(), plan.usage()))
+        .with_status_code(StatusCode::CONFLICT);
+    responder.payload = Some(Box::new(plan));
+    responder
+}
+
+fn check_if_match(tree: Tree, db: &mut SqliteConnection, id: i32, if_match: &IfMatch) -> Result<(), OperationError> {
+    match tree {
+        Tree::HostGroup => rest::check_if_match::<HostGroup, NewHostGroup, SqliteConnection>(db, id, if_match),
+        Tree::Service => rest::check_if_match::<Service, NewService, SqliteConnection>(db, id, if_match),
+    }
+}
+
+/// Deletes entry `id` of `tree` with `policy`, or previews the deletion. Every change is made in one transaction
+fn run(app_data: web::Data<AppData<'static>>, principal: Option<Principal>, request_id: RequestId, if_match: IfMatch, tree: Tree, id: i32, query: DeleteQuery) -> Responder<AppData<'static>> {
+    let DeleteQuery { policy, preview } = query;
+    let mut db = match app_data.db() {
+        Ok(db) => db,
+        Err(err) => return err.into(),
+    };
+    if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+        return Responder::<AppData<'static>>::forbidden(err);
+    }
+    let plan = match plan(&mut db, tree, id, policy) {
+        Ok(plan) => plan,
+        Err(err) => return err.into(),
+    };
+    if policy == Policy::Refuse && !plan.in_use_by.is_empty() && !preview {
+        return in_use(tree, plan);
+    }
+    if preview {
+        return Responder::<AppData<'static>>::from(anyhow::Result::<Plan>::Ok(plan));
+    }
+    let result = db.transaction(|db| -> Result<Plan, OperationError> {
+        check_if_match(tree, db, id, &if_match)?;
+        let mut plan = self::plan(db, tree, id, policy)?;
+        for change in &plan.changes {
+            apply(&app_data, db, principal.as_ref(), &request_id, change)?;
+        }
+        plan.applied = true;
+        Ok(plan)
+    });
+    match result {
+        Ok(plan) => Responder::<AppData<'static>>::from(anyhow::Result::<Plan>::Ok(plan))
+            .with_redirect(&format!("/{}s", tree.resource()), &format!("{} deleted", tree.title())),
+        Err(err) => err.into(),
+    }
+}
+
+fn templated(responder: Responder<AppData<'static>>, tree: Tree) -> Responder<AppData<'static>> {
+    responder
+        .with_template(&format!("{}/delete", tree.resource()))
+        .with_error_template(&format!("{}/error", tree.resource()))
+}
+
+/// `DELETE /{resource}/{id}?policy=...`: deletes a host group or service, refusing by default when it is in use. `preview=true` lists the changes without making them
+pub async fn delete_entry(tree: Tree, app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, if_match: IfMatch, id: web::Path<i32>, query: web::Query<DeleteQuery>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    templated(Responder::<AppData<'static>>::from(web::block(move || run(app_data, principal, request_id, if_match, tree, id, query.into_inner())).await), tree)
+}
+
+/// `GET /{resource}/{id}/delete?policy=...`: page previewing the deletion of a host group or service
+pub async fn preview_form(tree: Tree, app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, if_match: IfMatch, id: web::Path<i32>, query: web::Query<DeleteForm>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    let query = DeleteQuery { policy: query.policy, preview: true };
+    templated(Responder::<AppData<'static>>::from(web::block(move || run(app_data, principal, request_id, if_match, tree, id, query)).await), tree)
+}
+
+/// `POST /{resource}/{id}/delete`: deletes a host group or service from the HTML form, with the `policy` form field
+pub async fn delete_form(tree: Tree, app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, if_match: IfMatch, id: web::Path<i32>, JsonOrForm(form): JsonOrForm<DeleteForm>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    templated(Responder::<AppData<'static>>::from(web::block(move || run(app_data, principal, request_id, if_match, tree, id, DeleteQuery { policy: form.policy, preview: false })).await), tree)
+}
+
+/// Registers the deletion routes of host groups and services, before the routes of [crate::ui::resource] which they replace
+pub fn configure(config: &mut web::ServiceConfig) {
+    for tree in [Tree::HostGroup, Tree::Service] {
+        let resource = tree.resource();
+        config
+            .route(&format!("/{}/{{id}}", resource), web::delete().to(move |app_data, principal, request_id, if_match, id, query| delete_entry(tree, app_data, principal, request_id, if_match, id, query)))
+            .route(&format!("/{}/{{id}}/delete", resource), web::get().to(move |app_data, principal, request_id, if_match, id, query| preview_form(tree, app_data, principal, request_id, if_match, id, query)))
+            .route(&format!("/{}/{{id}}/delete", resource), web::post().to(move |app_data, principal, request_id, if_match, id, form| delete_form(tree, app_data, principal, request_id, if_match, id, form)));
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet};
+use actix_web::web;
+use anyhow::anyhow;
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use rest::{Audit, AuditAction, AuditEntry, Crud, DbFactory, Principal, RequestId, RequestPrincipal};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use crate::{AppData, auth, authorization::{self, Role, Scope}, schema, skytree::host::Host};
+
+/// Audit log entity the changes of the facts of a host are recorded as
+pub const ENTITY: &str = "host_facts";
+/// Size limit of fact uploads, as the `setup` module output of a single host is often over the default JSON limit
+const UPLOAD_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Facts retained for a host, by dotted path
+#[derive(Debug, Clone, Serialize)]
+pub struct Facts {
+    pub host: i32,
+    /// When the facts were last uploaded, in unix seconds
+    pub gathered: Option<i64>,
+    pub facts: Map<String, Value>,
+}
+
+/// Result of uploading the facts of a host
+#[derive(Debug, Clone, Serialize)]
+pub struct Upload {
+    pub host: i32,
+    pub name: String,
+    /// Whether the retained facts differ from the previous upload
+    pub changed: bool,
+}
+
+/// Result of uploading the facts of many hosts, by inventory host name
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Import {
+    pub hosts: Vec<Upload>,
+    /// Names matching no host, whose facts were ignored
+    pub unknown: Vec<String>,
+}
+
+/// Facts of the `setup` module output, `{"ansible_facts": {...}}`, or of a fact cache entry, the facts themselves
+fn facts_of(output: &Value) -> anyhow::Result<&Map<String, Value>> {
+    let facts = output.get("ansible_facts").unwrap_or(output);
+    facts.as_object().ok_or_else(|| anyhow!("Facts must be a JSON object"))
+}
+
+/// Facts at `paths` in the `setup` module output. `*` retains every fact
+pub fn select(output: &Value, paths: &[String]) -> anyhow::Result<Map<String, Value>> {
+    let facts = facts_of(output)?;
+    if paths.iter().any(|path| path == "*") {
+        return Ok(facts.clone());
+    }
+    let mut selected = Map::new();
+    for path in paths {
+        let mut parts = path.split('.');
+        let mut value = parts.next().and_then(|first| facts.get(first));
+        for part in parts {
+            value = value.and_then(|value| value.get(part));
+        }
+        if let Some(value) = value {
+            selected.insert(path.clone(), value.clone());
+        }
+    }
+    Ok(selected)
+}
+
+/// Facts retained for `host`
+pub fn stored(db: &mut SqliteConnection, host: i32) -> anyhow::Result<Facts> {
+    let rows = schema::host_fact::table
+        .filter(schema::host_fact::host.eq(host))
+        .select((schema::host_fact::path, schema::host_fact::value, schema::host_fact::gathered))
+        .order(schema::host_fact::path)
+        .load::<(String, String, i64)>(db)?;
+    let mut facts = Facts { host, gathered: None, facts: Map::new() };
+    for (path, value, gathered) in rows {
+        facts.gathered = facts.gathered.max(Some(gathered));
+        facts.facts.insert(path, serde_json::from_str(&value)?);
+    }
+    Ok(facts)
+}
+
+/// Replaces the facts retained for `host` by the facts at the configured paths of the `setup` module output, recording changes in the audit log
+pub fn upload<A: Audit<SqliteConnection>>(audit: &A, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, host: &Host, paths: &[String], output: &Value) -> anyhow::Result<Upload> {
+    let facts = select(output, paths)?;
+    db.transaction(|db| {
+        let before = stored(db, host.id)?;
+        diesel::delete(schema::host_fact::table.filter(schema::host_fact::host.eq(host.id))).execute(db)?;
+        let gathered = auth::now();
+        let rows = facts
+            .iter()
+            .map(|(path, value)| (schema::host_fact::host.eq(host.id), schema::host_fact::path.eq(path), schema::host_fact::value.eq(value.to_string()), schema::host_fact::gathered.eq(gathered)))
+            .collect::<Vec<_>>();
+        diesel::insert_into(schema::host_fact::table).values(rows).execute(db)?;
+        let changed = before.facts != facts;
+        if changed {
+            audit.audit(db, AuditEntry {
+                entity: ENTITY.to_string(),
+                entity_id: host.id,
+                entity_key: None,
+                action: if before.gathered.is_none() { AuditAction::Create } else { AuditAction::Update },
+                before: before.gathered.map(|_| Value::Object(before.facts)),
+                after: Some(Value::Object(facts)),
+                principal: principal.cloned(),
+                request_id: request_id.0.clone(),
+            })?;
+        }
+        Ok(Upload { host: host.id, name: host.name.clone(), changed })
+    })
+}
+
+/// Uploads the facts of many hosts, given by inventory host name
+pub fn import<A: Audit<SqliteConnection>>(audit: &A, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, paths: &[String], outputs: &Map<String, Value>) -> anyhow::Result<Import> {
+    let hosts = schema::host::table
+        .filter(schema::host::name.eq_any(outputs.keys()))
+        .load::<Host>(db)?
+        .into_iter()
+        .map(|host| (host.name.clone(), host))
+        .collect::<HashMap<String, Host>>();
+    let mut import = Import::default();
+    for (name, output) in outputs {
+        match hosts.get(name) {
+            Some(host) => import.hosts.push(upload(audit, db, principal, request_id, host, paths, output)?),
+            None => import.unknown.push(name.clone()),
+        }
+    }
+    Ok(import)
+}
+
+/// Parses `path=value` fact filters, separated by commas
+fn filters(filter: &str) -> anyhow::Result<Vec<(&str, &str)>> {
+    filter
+        .split(',')
+        .map(str::trim)
+        .filter(|filter| !filter.is_empty())
+        .map(|filter| filter.split_once('=').map(|(path, value)| (path.trim(), value.trim())).ok_or_else(|| anyhow!("Invalid fact filter {}, expected path=value", filter)))
+        .collect()
+}
+
+/// Hosts whose facts match every `path=value` filter. String facts are compared as they are, other facts as JSON text
+pub fn matching(db: &mut SqliteConnection, filter: &str) -> anyhow::Result<HashSet<i32>> {
+    let mut matching: Option<HashSet<i32>> = None;
+    for (path, expected) in filters(filter)? {
+        let hosts = schema::host_fact::table
+            .filter(schema::host_fact::path.eq(path))
+            .select((schema::host_fact::host, schema::host_fact::value))
+            .load::<(i32, String)>(db)?
+            .into_iter()
+            .filter(|(_, value)| match serde_json::from_str::<Value>(value) {
+                Ok(Value::String(value)) => value == expected,
+                _ => value == expected,
+            })
+            .map(|(host, _)| host)
+            .collect::<HashSet<i32>>();
+        matching = Some(match matching {
+            Some(matching) => matching.intersection(&hosts).copied().collect(),
+            None => hosts,
+        });
+    }
+    Ok(matching.unwrap_or_default())
+}
+
+/// Sets the facts of a host as variables, dotted paths as nested dicts. Facts are read-only: they replace variables of the same name, like gathered facts do in Ansible
+pub fn overlay(variables: &mut Map<String, Value>, facts: Map<String, Value>) {
+    for (path, value) in facts {
+        let mut parts = path.split('.').collect::<Vec<&str>>();
+        let last = parts.pop().unwrap_or_default();
+        let mut target = &mut *variables;
+        for part in parts {
+            let entry = target.entry(part).or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = Value::Object(Map::new());
+            }
+            target = entry.as_object_mut().unwrap();
+        }
+        target.insert(last.to_string(), value);
+    }
+}
+
+/// `GET /host/{id}/facts`: facts retained for a host
+pub async fn get(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, id: web::Path<i32>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        Host::db_fetch(&mut db, id).and_then(|host| stored(&mut db, host.id)).into()
+    }).await)
+}
+
+/// `PUT /host/{id}/facts`: replaces the facts of a host by those of the `setup` module output given as the request body, for the editors of the host
+pub async fn put(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, id: web::Path<i32>, web::Json(output): web::Json<Value>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        let host = match Host::db_fetch(&mut db, id) {
+            Ok(host) => host,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = Scope::host(&mut db, host.id).and_then(|scope| authorization::require(&mut db, principal.as_ref(), Role::Editor, scope)) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        upload(&**app_data, &mut db, principal.as_ref(), &request_id, &host, &app_data.config().fact_paths, &output).into()
+    }).await)
+}
+
+/// `POST /facts`: uploads the facts of many hosts, as a JSON object of `setup` module outputs by inventory host name, for global editors
+pub async fn post(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, web::Json(outputs): web::Json<Map<String, Value>>) -> Responder<AppData<'static>> {
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require(&mut db, principal.as_ref(), Role::Editor, Scope::Global) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        db.transaction(|db| import(&**app_data, db, principal.as_ref(), &request_id, &app_data.config().fact_paths, &outputs)).into()
+    }).await)
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .service(web::resource("/host/{id}/facts")
+            .app_data(web::JsonConfig::default().limit(UPLOAD_LIMIT))
+            .route(web::get().to(get))
+            .route(web::put().to(put)))
+        .service(web::resource("/facts")
+            .app_data(web::JsonConfig::default().limit(UPLOAD_LIMIT))
+            .route(web::post().to(post)));
+}
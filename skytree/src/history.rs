@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+use anyhow::anyhow;
+use diesel::{prelude::*, sql_types::{BigInt, Integer, Text}, SqliteConnection};
+use rest::{DbFactory, OperationError};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use crate::{AppData, facts, inventory, memberships, schema, secrets::Disclosure};
+
+/// How the recorded states of an audit log entity map to the rows of its table
+#[derive(Debug, Clone, Copy)]
+enum Rows {
+    /// Entity of a REST resource, one row with the same fields
+    Entity,
+    /// Value of a variable, one row identified by the owner column and the variable
+    Value(&'static str),
+    /// Explicit membership of a host in a group
+    Membership,
+    /// Facts of a host, one row per dotted path
+    Facts,
+}
+
+/// Audit log entities the inventory is made of, with the table their states are replayed into
+const VERSIONED: [(&str, &str, Rows); 14] = [
+    ("host", "host", Rows::Entity),
+    ("host_group", "host_group", Rows::Entity),
+    ("variable", "variable", Rows::Entity),
+    ("service", "service", Rows::Entity),
+    ("service_instance", "service_instance", Rows::Entity),
+    ("service_endpoint", "service_endpoint", Rows::Entity),
+    ("service_relation", "service_relation", Rows::Entity),
+    ("service_instance_relation", "service_instance_relation", Rows::Entity),
+    ("host_group_variable", "host_group_variable", Rows::Value("group")),
+    ("host_variable", "host_variable", Rows::Value("host")),
+    ("service_variable", "service_variable", Rows::Value("service")),
+    ("service_instance_variable", "service_instance_variable", Rows::Value("instance")),
+    (memberships::ENTITY, "host_group_membership", Rows::Membership),
+    (facts::ENTITY, "host_fact", Rows::Facts),
+];
+
+/// Entry of an audit log entity, by id and key
+type EntryId = (i32, Option<i32>);
+
+#[derive(QueryableByName)]
+struct Column {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+/// States of the `entity` entries at `as_of`, by id and key, as recorded in the audit log: `None` for those which did not exist at that time.
+/// Entries without recorded history are left out, as they did not change since
+fn states_at(db: &mut SqliteConnection, entity: &str, as_of: i64) -> anyhow::Result<HashMap<EntryId, Option<String>>> {
+    let history = schema::audit_log::table
+        .filter(schema::audit_log::entity.eq(entity))
+        .order(schema::audit_log::id.asc())
+        .select((schema::audit_log::entity_id, schema::audit_log::entity_key, schema::audit_log::before, schema::audit_log::after, schema::audit_log::timestamp))
+        .load::<(i32, Option<i32>, Option<String>, Option<String>, i64)>(db)?;
+    let mut states = HashMap::new();
+    for (id, key, before, after, timestamp) in history {
+        match timestamp <= as_of {
+            true => { states.insert((id, key), after); },
+            false => { states.entry((id, key)).or_insert(before); },
+        }
+    }
+    Ok(states)
+}
+
+/// Replaces the rows of entry `id`, `key` of `table` by `state`, removing them when `None`
+fn replace(db: &mut SqliteConnection, table: &str, rows: Rows, columns: &[String], (id, key): EntryId, state: Option<&str>, as_of: i64) -> anyhow::Result<()> {
+    let key = || key.ok_or_else(|| anyhow!("Audit log entry of {} {} without key", table, id));
+    match rows {
+        Rows::Entity => diesel::sql_query(format!(r#"DELETE FROM temp."{}" WHERE id = ?"#, table)).bind::<Integer, _>(id).execute(db)?,
+        Rows::Value(owner) => diesel::sql_query(format!(r#"DELETE FROM temp."{}" WHERE "{}" = ? AND variable = ?"#, table, owner)).bind::<Integer, _>(id).bind::<Integer, _>(key()?).execute(db)?,
+        Rows::Membership => diesel::sql_query(r#"DELETE FROM temp.host_group_membership WHERE host = ? AND "group" = ?"#).bind::<Integer, _>(id).bind::<Integer, _>(key()?).execute(db)?,
+        Rows::Facts => diesel::sql_query("DELETE FROM temp.host_fact WHERE host = ?").bind::<Integer, _>(id).execute(db)?,
+    };
+    let Some(state) = state else {
+        return Ok(());
+    };
+    match rows {
+        Rows::Entity => {
+            let fields = columns.iter().map(|column| format!(r#"json_extract(?1, '$."{}"')"#, column)).collect::<Vec<String>>();
+            let columns = columns.iter().map(|column| format!(r#""{}""#, column)).collect::<Vec<String>>();
+            diesel::sql_query(format!(r#"INSERT INTO temp."{}" ({}) SELECT {}"#, table, columns.join(", "), fields.join(", ")))
+                .bind::<Text, _>(state)
+                .execute(db)?;
+        },
+        Rows::Value(owner) => {
+            let value = serde_json::from_str::<Value>(state)?["value"].to_string();
+            diesel::sql_query(format!(r#"INSERT INTO temp."{}" (variable, "{}", value) VALUES (?, ?, ?)"#, table, owner))
+                .bind::<Integer, _>(key()?)
+                .bind::<Integer, _>(id)
+                .bind::<Text, _>(value)
+                .execute(db)?;
+        },
+        Rows::Membership => {
+            diesel::sql_query(r#"INSERT INTO temp.host_group_membership (host, "group") VALUES (?, ?)"#)
+                .bind::<Integer, _>(id)
+                .bind::<Integer, _>(key()?)
+                .execute(db)?;
+        },
+        Rows::Facts => {
+            for (path, value) in serde_json::from_str::<Map<String, Value>>(state)? {
+                diesel::sql_query("INSERT INTO temp.host_fact (host, path, value, gathered) VALUES (?, ?, ?, ?)")
+                    .bind::<Integer, _>(id)
+                    .bind::<Text, _>(path)
+                    .bind::<Text, _>(value.to_string())
+                    .bind::<BigInt, _>(as_of)
+                    .execute(db)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Makes the inventory tables read, on this connection, as they were at `as_of`, replaying the audit log.
+///
+/// They are shadowed by temporary copies, leaving the database untouched. Statements prepared earlier on the connection keep reading the current tables, so it is meant for fresh connections
+pub fn replay(db: &mut SqliteConnection, as_of: i64) -> anyhow::Result<()> {
+    for (_, table, _) in VERSIONED {
+        diesel::sql_query(format!(r#"CREATE TEMP TABLE "{0}" AS SELECT * FROM main."{0}""#, table)).execute(db)?;
+    }
+    for (entity, table, rows) in VERSIONED {
+        let columns = diesel::sql_query("SELECT name FROM pragma_table_info(?, 'main')")
+            .bind::<Text, _>(table)
+            .load::<Column>(db)?
+            .into_iter()
+            .map(|column| column.name)
+            .collect::<Vec<String>>();
+        for (entry, state) in states_at(db, entity, as_of)? {
+            replace(db, table, rows, &columns, entry, state.as_deref(), as_of)?;
+        }
+    }
+    Ok(())
+}
+
+/// New connection reading the inventory as it was at `as_of`, see [replay]
+pub fn snapshot(app_data: &AppData<'static>, as_of: i64) -> Result<SqliteConnection, OperationError> {
+    let mut db = app_data.db()?;
+    replay(&mut db, as_of)?;
+    Ok(db)
+}
+
+/// Group or host whose inventory entry differs between two points in time
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    /// `group` or `host`
+    pub kind: &'static str,
+    pub name: String,
+    /// Entry at the start of the range, `null` when it did not exist: hosts, children and variables of a group, effective variables of a host
+    pub before: Value,
+    /// Entry at the end of the range, `null` when it did not exist
+    pub after: Value,
+}
+
+/// Groups and hosts of the inventory at `as_of`, secret values redacted
+fn entries(app_data: &AppData<'static>, as_of: i64) -> Result<BTreeMap<(&'static str, String), Value>, OperationError> {
+    let mut inventory = inventory::inventory(&mut snapshot(app_data, as_of)?, &Disclosure::Redacted)?;
+    let hostvars = inventory.remove("_meta").and_then(|mut meta| meta.get_mut("hostvars").map(Value::take));
+    let mut entries = inventory.into_iter().map(|(group, entry)| (("group", group), entry)).collect::<BTreeMap<_, _>>();
+    if let Some(Value::Object(hostvars)) = hostvars {
+        entries.extend(hostvars.into_iter().map(|(host, vars)| (("host", host), vars)));
+    }
+    Ok(entries)
+}
+
+/// Groups and hosts whose inventory entry differs between `from` and `to`, groups first
+pub fn diff(app_data: &AppData<'static>, from: i64, to: i64) -> Result<Vec<Change>, OperationError> {
+    let mut before = entries(app_data, from)?;
+    let after = entries(app_data, to)?;
+    let mut changes = Vec::new();
+    for ((kind, name), after) in after {
+        let before = before.remove(&(kind, name.clone())).unwrap_or_default();
+        if before != after {
+            changes.push(Change { kind, name, before, after });
+        }
+    }
+    changes.extend(before.into_iter().map(|((kind, name), before)| Change { kind, name, before, after: Value::Null }));
+    changes.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::connection::SimpleConnection;
+    use rest::RequestId;
+    use super::*;
+    use crate::testing;
+
+    fn members(db: &mut SqliteConnection) -> Vec<(Option<i32>, Option<i32>)> {
+        schema::host_group_membership::table
+            .select((schema::host_group_membership::host, schema::host_group_membership::group))
+            .order(schema::host_group_membership::group)
+            .load(db)
+            .unwrap()
+    }
+
+    #[test]
+    fn replays_memberships() {
+        let mut db = testing::db();
+        let app_data = testing::app_data();
+        let request_id = RequestId("test".to_string());
+        db.batch_execute("INSERT INTO host(id, name) VALUES (1, 'web1'); INSERT INTO host_group(id, name) VALUES (1, 'web'), (2, 'db');").unwrap();
+        memberships::add(&app_data, &mut db, None, &request_id, 1, 1).unwrap();
+        db.batch_execute("UPDATE audit_log SET timestamp = 100;").unwrap();
+        memberships::remove(&app_data, &mut db, None, &request_id, 1, 1).unwrap();
+        memberships::add(&app_data, &mut db, None, &request_id, 1, 2).unwrap();
+        db.batch_execute("UPDATE audit_log SET timestamp = 200 WHERE timestamp != 100;").unwrap();
+        assert_eq!(members(&mut db), vec![(Some(1), Some(2))]);
+
+        replay(&mut db, 150).unwrap();
+        assert_eq!(members(&mut db), vec![(Some(1), Some(1))]);
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+use actix_web::{web, Either, HttpResponse};
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use rest::{DbFactory, OperationError, RequestPrincipal};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use crate::{AppData, audit, authorization::{self, Role, Scope}, history, schema, smart_groups, secrets::{self, Disclosure, SecretKey}, skytree::{host::Host, host_group::HostGroup, service_endpoint}, variables::{self, Owner}};
+
+/// Output of the values of secret variables in the inventory
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Secrets {
+    #[default]
+    Redact,
+    /// In clear, for global admins
+    Plain,
+    /// As Ansible vault ciphertext, encrypted with the configured vault password
+    Vault,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Output of Ansible dynamic inventory scripts
+    #[default]
+    Json,
+    /// Ansible YAML inventory
+    Yaml,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InventoryQuery {
+    #[serde(default)]
+    pub secrets: Secrets,
+    #[serde(default)]
+    pub format: Format,
+    /// Inventory as it was at that time, replayed from the audit log
+    pub as_of: Option<String>,
+}
+
+/// The whole inventory, in the JSON format of Ansible dynamic inventory scripts called with `--list`.
+///
+/// Groups carry the variables set on them, and hosts their effective variables. Smart groups list the hosts matching their rule.
+/// Hosts with service instances also get them, with their endpoints and related instances, as [service_endpoint::SERVICE_INSTANCES_VARIABLE]
+pub fn inventory(db: &mut SqliteConnection, disclosure: &Disclosure) -> anyhow::Result<Map<String, Value>> {
+    let groups = schema::host_group::table.order(schema::host_group::id).load::<HostGroup>(db)?;
+    let hosts = schema::host::table.order(schema::host::id).load::<Host>(db)?;
+    let mut memberships = schema::host_group_membership::table
+        .select((schema::host_group_membership::host, schema::host_group_membership::group))
+        .load::<(Option<i32>, Option<i32>)>(db)?
+        .into_iter()
+        .filter_map(|(host, group)| host.zip(group))
+        .collect::<Vec<(i32, i32)>>();
+    memberships.extend(smart_groups::memberships(db)?);
+    let host_names = hosts.iter().map(|host| (host.id, host.name.clone())).collect::<HashMap<i32, String>>();
+    let group_ids = groups.iter().map(|group| group.id).collect::<HashSet<i32>>();
+    let mut inventory = Map::new();
+    let mut top = Vec::new();
+    for group in &groups {
+        let mut members = memberships
+            .iter()
+            .filter(|(_, member_of)| *member_of == group.id)
+            .filter_map(|(host, _)| host_names.get(host).cloned())
+            .collect::<Vec<String>>();
+        members.sort();
+        members.dedup();
+        let children = groups
+            .iter()
+            .filter(|child| child.parent == Some(group.id))
+            .map(|child| child.name.clone())
+            .collect::<Vec<String>>();
+        let vars = variables::values(db, Owner::HostGroup(group.id), disclosure)?
+            .into_iter()
+            .filter_map(|value| value.name.map(|name| (name, value.value)))
+            .collect::<Map<String, Value>>();
+        if !group.parent.is_some_and(|parent| group_ids.contains(&parent)) {
+            top.push(group.name.clone());
+        }
+        inventory.insert(group.name.clone(), json!({ "hosts": members, "children": children, "vars": vars }));
+    }
+    let grouped = memberships.iter().map(|(host, _)| *host).collect::<HashSet<i32>>();
+    inventory.insert("ungrouped".to_string(), json!({ "hosts": hosts.iter().filter(|host| !grouped.contains(&host.id)).map(|host| host.name.clone()).collect::<Vec<String>>() }));
+    top.push("ungrouped".to_string());
+    inventory.insert("all".to_string(), json!({ "children": top }));
+    let mut hostvars = Map::new();
+    for host in &hosts {
+        let mut vars = variables::effective(db, host.id, disclosure)?;
+        match service_endpoint::instances_variable(db, host.id)? {
+            Value::Array(instances) if instances.is_empty() => (),
+            instances => { vars.insert(service_endpoint::SERVICE_INSTANCES_VARIABLE.to_string(), instances); },
+        }
+        hostvars.insert(host.name.clone(), Value::Object(vars));
+    }
+    inventory.insert("_meta".to_string(), json!({ "hostvars": hostvars }));
+    Ok(inventory)
+}
+
+/// Group `name` of a dynamic inventory, in the YAML inventory format
+fn yaml_group(inventory: &Map<String, Value>, name: &str) -> Value {
+    let group = &inventory[name];
+    let mut yaml = Map::new();
+    let hosts = group["hosts"].as_array().into_iter().flatten().filter_map(Value::as_str).map(|host| (host.to_string(), Value::Null)).collect::<Map<String, Value>>();
+    if !hosts.is_empty() {
+        yaml.insert("hosts".to_string(), Value::Object(hosts));
+    }
+    if group["vars"].as_object().is_some_and(|vars| !vars.is_empty()) {
+        yaml.insert("vars".to_string(), group["vars"].clone());
+    }
+    let children = group["children"].as_array().into_iter().flatten().filter_map(Value::as_str).map(|child| (child.to_string(), yaml_group(inventory, child))).collect::<Map<String, Value>>();
+    if !children.is_empty() {
+        yaml.insert("children".to_string(), Value::Object(children));
+    }
+    Value::Object(yaml)
+}
+
+/// Replaces `{"__ansible_vault": ...}` by the `!vault` tagged ciphertext
+fn vault_tags(value: serde_yaml::Value) -> serde_yaml::Value {
+    match value {
+        serde_yaml::Value::Mapping(mapping) => match mapping.get(secrets::ANSIBLE_VAULT) {
+            Some(serde_yaml::Value::String(ciphertext)) if mapping.len() == 1 => serde_yaml::Value::Tagged(Box::new(serde_yaml::value::TaggedValue {
+                tag: serde_yaml::value::Tag::new("vault"),
+                value: serde_yaml::Value::String(format!("{}\n", ciphertext)),
+            })),
+            _ => serde_yaml::Value::Mapping(mapping.into_iter().map(|(key, value)| (key, vault_tags(value))).collect()),
+        },
+        serde_yaml::Value::Sequence(values) => serde_yaml::Value::Sequence(values.into_iter().map(vault_tags).collect()),
+        value => value,
+    }
+}
+
+/// A dynamic inventory in the Ansible YAML inventory format: hosts with their variables under `all`, and the group tree
+pub fn yaml(inventory: &Map<String, Value>) -> anyhow::Result<String> {
+    let all = json!({
+        "all": {
+            "hosts": inventory["_meta"]["hostvars"],
+            "children": inventory["all"]["children"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .filter(|group| *group != "ungrouped")
+                .map(|group| (group.to_string(), yaml_group(inventory, group)))
+                .collect::<Map<String, Value>>(),
+        }
+    });
+    Ok(serde_yaml::to_string(&vault_tags(serde_yaml::to_value(all)?))?)
+}
+
+/// `GET /inventory`: the inventory for Ansible, as JSON or with `?format=yaml` as YAML.
+///
+/// Secret values are redacted, unless `?secrets=plain` is given by a global admin or `?secrets=vault` asks for vault ciphertext. `?as_of=` gives the inventory at a point in time
+pub async fn get(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, query: web::Query<InventoryQuery>) -> Either<HttpResponse, Responder<AppData<'static>>> {
+    let principal = principal.map(|principal| principal.into_inner());
+    let format = query.format;
+    let result = web::block(move || -> Result<String, OperationError> {
+        let mut db = app_data.db()?;
+        authorization::require_viewer(&mut db, principal.as_ref()).map_err(OperationError::Forbidden)?;
+        let disclosure = match query.secrets {
+            Secrets::Redact => Disclosure::Redacted,
+            Secrets::Plain => {
+                authorization::require(&mut db, principal.as_ref(), Role::Admin, Scope::Global).map_err(OperationError::Forbidden)?;
+                Disclosure::Revealed(SecretKey::load(&app_data.config())?)
+            },
+            Secrets::Vault => Disclosure::Vaulted(SecretKey::load(&app_data.config())?, secrets::vault_password(&app_data.config())?),
+        };
+        let inventory = match query.as_of.as_deref().filter(|as_of| !as_of.is_empty()) {
+            Some(as_of) => inventory(&mut history::snapshot(&app_data, audit::parse_time(as_of)?)?, &disclosure)?,
+            None => inventory(&mut db, &disclosure)?,
+        };
+        Ok(match format {
+            Format::Json => Value::Object(inventory).to_string(),
+            Format::Yaml => yaml(&inventory)?,
+        })
+    }).await;
+    match result {
+        Ok(Ok(body)) => Either::Left(HttpResponse::Ok()
+            .content_type(match format {
+                Format::Json => "application/json",
+                Format::Yaml => "application/yaml",
+            })
+            .body(body)),
+        Ok(Err(err)) => Either::Right(err.into()),
+        Err(err) => Either::Right(Responder::<AppData<'static>>::from(anyhow::Error::from(err))),
+    }
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.route("/inventory", web::get().to(get));
+}
@@ -1,33 +1,72 @@
+use std::sync::{Arc, RwLock};
+use actix_web::HttpMessage;
 use diesel::{Connection, SqliteConnection};
 use handlebars::Handlebars;
+pub use config::{Config, ListenAddress};
+pub mod audit;
+pub mod auth;
+pub mod authorization;
+pub mod changeset;
+pub mod config;
+pub mod deletion;
+pub mod facts;
+pub mod history;
+pub mod inventory;
+pub mod memberships;
+pub mod names;
+pub mod patterns;
 pub mod schema;
+pub mod secrets;
 pub mod skytree;
-#[derive(Debug, Default, Clone)]
+pub mod smart_groups;
+pub mod subtrees;
+pub mod templates;
+#[cfg(test)]
+mod testing;
+pub mod topology;
+pub mod ui;
+pub mod value_schema;
+pub mod variables;
+/// Application state shared by all workers. Configuration and templates are swapped as a whole on reload
+#[derive(Debug)]
 pub struct AppData<'a> {
-    pub handlebars: Handlebars<'a>,
+    config: RwLock<Arc<Config>>,
+    handlebars: RwLock<Arc<Handlebars<'a>>>,
+}
+impl AppData<'static> {
+    pub fn new(config: Config) -> anyhow::Result<Self> {
+        Ok(AppData {
+            handlebars: RwLock::new(Arc::new(templates::handlebars(&config)?)),
+            config: RwLock::new(Arc::new(config)),
+        })
+    }
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+    /// Replaces the configuration and rebuilds the templates. On error the current configuration stays in place
+    pub fn reload(&self, config: Config) -> anyhow::Result<()> {
+        let handlebars = templates::handlebars(&config)?;
+        *self.handlebars.write().unwrap() = Arc::new(handlebars);
+        *self.config.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
 }
 impl rest::DbFactory<SqliteConnection> for AppData<'_> {
-    fn db(&self) -> SqliteConnection {
-        SqliteConnection::establish(&std::env::var("DATABASE_URL").expect("DATABASE_URL must be set")).unwrap()
+    fn db(&self) -> Result<SqliteConnection, rest::Unavailable> {
+        SqliteConnection::establish(self.config.read().unwrap().database_url()).map_err(|err| rest::Unavailable(err.into()))
     }
 }
 impl negotiated::HandlebarsFactory for AppData<'static> {
-    fn handlebars(&self) -> &Handlebars {
-        &self.handlebars
+    fn handlebars(&self) -> Arc<Handlebars<'static>> {
+        self.handlebars.read().unwrap().clone()
     }
-
-}
-pub struct Config {
-    pub template_dir: &'static str,
-}
-impl Config {
-    pub fn get_template_dir() -> &'static str {
-        unsafe {
-            CONFIG.template_dir
-        }
+    fn template_source(&self, name: &str) -> Option<String> {
+        templates::template_source(&self.config().template_dir, name)
+    }
+    fn template_config(&self) -> serde_json::Value {
+        serde_json::to_value(&*self.config()).unwrap_or_default()
+    }
+    fn template_user(&self, req: &actix_web::HttpRequest) -> serde_json::Value {
+        req.extensions().get::<rest::Principal>().and_then(|principal| serde_json::to_value(principal).ok()).unwrap_or_default()
     }
 }
-
-pub static mut CONFIG: Config = Config { 
-    template_dir: "",
-};
\ No newline at end of file
@@ -1,9 +1,12 @@
-use actix_web::{web, App, HttpServer};
-use diesel::SqliteConnection;
-use handlebars::Handlebars;
-use clap::{Parser, command};
-use rest::{RestCollection, Rest};
-use skytree::{Config, AppData, skytree::host_group::{HostGroup, NewHostGroup}};
+use actix_web::{middleware, web, App, HttpServer};
+use anyhow::Context;
+use std::{io::IsTerminal, process::ExitCode};
+#[cfg(unix)]
+use actix_web::rt::signal::unix::{signal, SignalKind};
+use diesel::{Connection, SqliteConnection};
+use clap::{Parser, Subcommand};
+use rest::Crud;
+use skytree::{audit, auth, changeset, deletion, facts, inventory, memberships, names, patterns, smart_groups, subtrees, topology, Config, ListenAddress, config::ConfigOverrides, AppData, secrets::SecretKey, templates, ui, variables, skytree::{host::{Host, NewHost}, host_group::{HostGroup, NewHostGroup}, service::{Service, NewService}, service_endpoint::{ServiceEndpoint, NewServiceEndpoint}, service_instance::{ServiceInstance, NewServiceInstance}, service_relation::{ServiceRelation, NewServiceRelation}, service_instance_relation::{ServiceInstanceRelation, NewServiceInstanceRelation}, variable::{Variable, NewVariable}, role_grant::{RoleGrant, NewRoleGrant}}, authorization::Role};
 
 #[derive(Parser, Debug)]
 #[command(name = "skytree")]
@@ -11,50 +14,339 @@ use skytree::{Config, AppData, skytree::host_group::{HostGroup, NewHostGroup}};
 #[command(version = "1.0")]
 #[command(about = "Web and REST API management of Ansible Inventory", long_about = None)]
 struct CliArguments {
-    #[arg(short='c', long="config-file")]
+    /// Configuration file. Defaults to /etc/skytree.ini
+    #[arg(short='c', long="config-file", env="SKYTREE_CONFIG_FILE")]
     config_file: Option<String>,
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the built-in templates
+    Templates {
+        #[command(subcommand)]
+        command: TemplatesCommand,
+    },
+    /// Manage users of the web interface and REST API
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manage API tokens
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand,
+    },
+    /// Manage role grants
+    Role {
+        #[command(subcommand)]
+        command: RoleCommand,
+    },
+    /// Manage values of secret variables
+    Secrets {
+        #[command(subcommand)]
+        command: SecretsCommand,
+    },
+    /// Manage Ansible facts of hosts
+    Facts {
+        #[command(subcommand)]
+        command: FactsCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum UserCommand {
+    /// Create a user. The password is read from standard input
+    Create {
+        name: String,
+    },
+    /// Change the password of a user, read from standard input, and log out their sessions
+    Password {
+        name: String,
+    },
+    /// Delete a user, their sessions and their API tokens
+    Delete {
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TokenCommand {
+    /// Create an API token for a user. The token is only shown once
+    Create {
+        /// Owner of the token
+        #[arg(short='u', long="user")]
+        user: String,
+        /// Description of the token
+        #[arg(short='n', long="name", default_value="api")]
+        name: String,
+    },
+    /// Revoke an API token
+    Revoke {
+        id: i32,
+    },
+    /// List API tokens
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+enum RoleCommand {
+    /// Grant a role to a user, globally or on a host group subtree
+    Grant {
+        user: String,
+        /// viewer, editor or admin
+        role: String,
+        /// Host group id. The role is global when omitted
+        #[arg(short='g', long="group")]
+        group: Option<i32>,
+    },
+    /// Revoke a role grant
+    Revoke {
+        id: i32,
+    },
+    /// List role grants
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+enum SecretsCommand {
+    /// Encrypt the values of secret variables stored in clear, and decrypt those of variables no longer secret. Run after changing whether a variable is secret
+    Seal,
+}
+
+#[derive(Subcommand, Debug)]
+enum FactsCommand {
+    /// Import the facts of an Ansible jsonfile fact cache directory, with a file per host named after the host
+    Import {
+        /// Fact cache directory, the `fact_caching_connection` of Ansible
+        directory: String,
+        /// Prefix of the file names, the `fact_caching_prefix` of Ansible
+        #[arg(short='p', long="prefix", default_value="")]
+        prefix: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TemplatesCommand {
+    /// Export the built-in templates for customisation
+    Dump {
+        /// Target directory. Defaults to the configured template_dir
+        #[arg(short='o', long="output-dir")]
+        output_dir: Option<String>,
+        /// Overwrite existing files
+        #[arg(short='f', long="force")]
+        force: bool,
+    },
 }
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let mut cli = CliArguments::parse();
-    if cli.config_file.is_none() {
-        cli.config_file = Some("/etc/skytree.ini".to_string());
+async fn main() -> ExitCode {
+    env_logger::init();
+    match run(CliArguments::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: CliArguments) -> anyhow::Result<()> {
+    let config = Config::load(cli.config_file.as_deref(), &cli.overrides)?;
+    if let Some(Command::Templates { command: TemplatesCommand::Dump { output_dir, force } }) = cli.command {
+        let output_dir = output_dir.unwrap_or_else(|| config.template_dir.clone());
+        templates::dump_templates(&output_dir, force)?.iter().for_each(|file| println!("{}", file));
+        return Ok(());
     }
+    config.validate_server()?;
+    let mut db = SqliteConnection::establish(config.database_url()).with_context(|| format!("Unable to open database {}", config.database_url()))?;
+    match cli.command {
+        Some(Command::User { command }) => return user_command(&mut db, command),
+        Some(Command::Token { command }) => return token_command(&mut db, command),
+        Some(Command::Role { command }) => return role_command(&mut db, command),
+        Some(Command::Secrets { command }) => return secrets_command(&mut db, config, command),
+        Some(Command::Facts { command }) => return facts_command(&mut db, config, command),
+        _ => drop(db)
+    }
+    let listen = config.listen.clone();
+    let workers = config.workers;
+    let app_data = web::Data::new(AppData::new(config)?);
+
+    #[cfg(unix)]
     {
-        let ini_file_settings = ini::ini!(&cli.config_file.unwrap());
-        let mut config_template_dir = "templates".to_string();
-        if let Some(settings) = ini_file_settings.get("skytree") {
-            config_template_dir = settings.get("template_dir").unwrap_or(&Some(config_template_dir.clone())).as_deref().unwrap_or(&config_template_dir).to_string();
-        }
-        if config_template_dir.is_empty() {
-            config_template_dir = "templates".to_string();
-        }
-        if !config_template_dir.starts_with('/') {
-            config_template_dir = format!("{}/{}", std::env::current_dir().unwrap().to_str().unwrap(), config_template_dir);
-        }
-        if config_template_dir.chars().rev().next().unwrap() != '/' {
-            config_template_dir = format!("{}/", config_template_dir);
-        }
-        unsafe {
-            skytree::CONFIG.template_dir = Box::leak(config_template_dir.into_boxed_str());
-        }
+        let mut hangup = signal(SignalKind::hangup()).context("Unable to handle SIGHUP")?;
+        let app_data = app_data.clone();
+        actix_web::rt::spawn(async move {
+            while hangup.recv().await.is_some() {
+                log::info!("SIGHUP received, reloading configuration");
+                if let Err(err) = reload(&app_data, cli.config_file.as_deref(), &cli.overrides) {
+                    log::error!("Configuration not reloaded: {:#}", err);
+                }
+            }
+        });
     }
-    let mut handlebars = Handlebars::new();
-    handlebars.set_strict_mode(true);
-    handlebars.register_templates_directory(".hbs", Config::get_template_dir()).unwrap();
-    let app_data = web::Data::new( AppData { handlebars } );
 
-    HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
             .app_data(app_data.clone())
-            .route("/host_groups", web::get().to(<HostGroup as RestCollection<rest::RestCollectionGetParameters, AppData, SqliteConnection>>::get))
-            .route("/host_group", web::post().to(<HostGroup as Rest<HostGroup, NewHostGroup, AppData, SqliteConnection>>::post))
-            .route("/host_group/{id}", web::get().to(<HostGroup as Rest<HostGroup, NewHostGroup, AppData, SqliteConnection>>::get))
-            .route("/host_group/{id}", web::put().to(<HostGroup as Rest<HostGroup, NewHostGroup, AppData, SqliteConnection>>::put))
-            .route("/host_group/{id}", web::delete().to(<HostGroup as Rest<HostGroup, NewHostGroup, AppData, SqliteConnection>>::delete))
-    })
-    .bind(("127.0.0.1", 3000))?
-    .run()
-    .await
+            .wrap(middleware::from_fn(rest::csrf))
+            .wrap(middleware::from_fn(auth::authenticate))
+            .wrap(middleware::from_fn(rest::request_id))
+            .configure(auth::configure)
+            .configure(audit::configure)
+            .configure(changeset::configure)
+            .configure(variables::configure)
+            .configure(memberships::configure)
+            .configure(inventory::configure)
+            .configure(patterns::configure)
+            .configure(facts::configure)
+            .configure(smart_groups::configure)
+            .configure(topology::configure)
+            .configure(deletion::configure)
+            .configure(subtrees::configure)
+            .configure(names::configure)
+            .route("/", web::get().to(ui::index))
+            .configure(ui::resource::<HostGroup, NewHostGroup>)
+            .configure(ui::resource::<Host, NewHost>)
+            .configure(ui::resource::<Service, NewService>)
+            .configure(ui::resource::<ServiceInstance, NewServiceInstance>)
+            .configure(ui::resource::<ServiceEndpoint, NewServiceEndpoint>)
+            .configure(ui::resource::<ServiceRelation, NewServiceRelation>)
+            .configure(ui::resource::<ServiceInstanceRelation, NewServiceInstanceRelation>)
+            .configure(ui::resource::<Variable, NewVariable>)
+            .configure(ui::resource::<RoleGrant, NewRoleGrant>)
+    });
+    if let Some(workers) = workers {
+        server = server.workers(workers);
+    }
+    for address in &listen {
+        log::info!("Listening on {}", address);
+        server = match address {
+            ListenAddress::Tcp(address) => server.bind(address),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => server.bind_uds(path),
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => Err(std::io::Error::other("Unix domain sockets are not supported on this platform")),
+        }.with_context(|| format!("Unable to listen on {}", address))?;
+    }
+    Ok(server.run().await?)
+}
+
+/// Reads a password from standard input, prompting for it on a terminal
+fn read_password() -> anyhow::Result<String> {
+    if std::io::stdin().is_terminal() {
+        eprint!("Password: ");
+    }
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        anyhow::bail!("Password must not be empty");
+    }
+    Ok(password)
+}
+
+fn user_command(db: &mut SqliteConnection, command: UserCommand) -> anyhow::Result<()> {
+    let user = match command {
+        UserCommand::Create { name } => auth::create_user(db, &name, &read_password()?)?,
+        UserCommand::Password { name } => auth::set_password(db, &name, &read_password()?)?,
+        UserCommand::Delete { name } => auth::delete_user(db, &name)?,
+    };
+    println!("{}\t{}", user.id, user.name);
+    Ok(())
+}
+
+fn token_command(db: &mut SqliteConnection, command: TokenCommand) -> anyhow::Result<()> {
+    match command {
+        TokenCommand::Create { user, name } => {
+            let (api_token, token) = auth::create_token(db, &user, &name)?;
+            eprintln!("Created API token {} for {}. Store it now, it cannot be shown again", api_token.id, user);
+            println!("{}", token);
+        },
+        TokenCommand::Revoke { id } => {
+            let api_token = auth::revoke_token(db, id)?;
+            println!("{}\t{}\trevoked", api_token.id, api_token.name);
+        },
+        TokenCommand::List => {
+            for (api_token, user) in auth::list_tokens(db)? {
+                println!("{}\t{}\t{}\t{}", api_token.id, user, api_token.name, if api_token.revoked.is_some() { "revoked" } else { "active" });
+            }
+        },
+    }
+    Ok(())
+}
+
+fn role_command(db: &mut SqliteConnection, command: RoleCommand) -> anyhow::Result<()> {
+    let print = |role_grant: &RoleGrant, user: &str| println!("{}\t{}\t{}\t{}", role_grant.id, user, role_grant.role, role_grant.host_group.map(|group| group.to_string()).unwrap_or_else(|| "global".to_string()));
+    match command {
+        RoleCommand::Grant { user, role, group } => {
+            let role = role.parse::<Role>()?;
+            let user_id = auth::find_user(db, &user)?.id;
+            let role_grant = RoleGrant::db_insert(db, &NewRoleGrant { user: user_id, role: role.to_string(), host_group: group })?;
+            print(&role_grant, &user);
+        },
+        RoleCommand::Revoke { id } => {
+            let role_grant = RoleGrant::db_delete(db, id)?;
+            println!("{}\t{}\trevoked", role_grant.id, role_grant.role);
+        },
+        RoleCommand::List => RoleGrant::list(db)?.iter().for_each(|(role_grant, user)| print(role_grant, user)),
+    }
+    Ok(())
+}
+
+fn secrets_command(db: &mut SqliteConnection, config: Config, command: SecretsCommand) -> anyhow::Result<()> {
+    match command {
+        SecretsCommand::Seal => {
+            let key = SecretKey::load(&config)?;
+            let app_data = AppData::new(config)?;
+            let request_id = rest::RequestId(format!("{:032x}", rand::random::<u128>()));
+            let changed = db.transaction(|db| variables::reseal(&app_data, db, None, &request_id, &key))?;
+            println!("{} values resealed", changed);
+        },
+    }
+    Ok(())
+}
+
+fn facts_command(db: &mut SqliteConnection, config: Config, command: FactsCommand) -> anyhow::Result<()> {
+    match command {
+        FactsCommand::Import { directory, prefix } => {
+            let mut outputs = serde_json::Map::new();
+            for entry in std::fs::read_dir(&directory).with_context(|| format!("Unable to read fact cache directory {}", directory))? {
+                let path = entry?.path();
+                let Some(name) = path.file_name().and_then(|name| name.to_str()).and_then(|name| name.strip_prefix(prefix.as_str())) else { continue };
+                let facts = std::fs::read_to_string(&path).with_context(|| format!("Unable to read {}", path.display()))?;
+                match serde_json::from_str(&facts) {
+                    Ok(facts) => { outputs.insert(name.to_string(), facts); },
+                    Err(err) => log::warn!("Skipping {}, not a JSON fact cache entry: {}", path.display(), err),
+                }
+            }
+            let paths = config.fact_paths.clone();
+            let app_data = AppData::new(config)?;
+            let request_id = rest::RequestId(format!("{:032x}", rand::random::<u128>()));
+            let import = db.transaction(|db| facts::import(&app_data, db, None, &request_id, &paths, &outputs))?;
+            for upload in import.hosts {
+                println!("{}\t{}\t{}", upload.host, upload.name, if upload.changed { "changed" } else { "unchanged" });
+            }
+            for name in import.unknown {
+                eprintln!("No host named {}, facts ignored", name);
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Reloads the configuration file and templates. Listen addresses and workers are bound at startup and only change on restart
+#[cfg(unix)]
+fn reload(app_data: &AppData<'static>, config_file: Option<&str>, overrides: &ConfigOverrides) -> anyhow::Result<()> {
+    let config = Config::load(config_file, overrides)?;
+    config.validate_server()?;
+    SqliteConnection::establish(config.database_url()).with_context(|| format!("Unable to open database {}", config.database_url()))?;
+    let current = app_data.config();
+    if config.listen != current.listen || config.workers != current.workers {
+        log::warn!("Listen addresses and workers only change on restart");
+    }
+    app_data.reload(config)
 }
@@ -0,0 +1,203 @@
+use actix_web::{http::StatusCode, web};
+use anyhow::Context;
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use serde::Deserialize;
+use rest::{Audit, AuditAction, AuditEntry, Crud, DbFactory, JsonOrForm, OperationError, Principal, RequestId, RequestPrincipal, RestOperation, RestPre, RestValidate};
+use crate::{AppData, authorization::{self, Role, Scope}, schema};
+use crate::skytree::{host::{Host, NewHost}, host_group::HostGroup};
+
+/// Audit log entity explicit group memberships are recorded as, identified by the host and, as their key, the group
+pub const ENTITY: &str = "host_group_membership";
+
+/// Makes `host` an explicit member of `group`, recording it in the audit log. Callers authorize the change
+pub fn add<A: Audit<SqliteConnection>>(audit: &A, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, host: i32, group: i32) -> anyhow::Result<()> {
+    diesel::insert_into(schema::host_group_membership::table)
+        .values((schema::host_group_membership::host.eq(host), schema::host_group_membership::group.eq(group)))
+        .execute(db)?;
+    audit.audit(db, audit_entry(principal, request_id, host, group, AuditAction::Create))
+}
+
+/// Removes `host` from the explicit members of `group`, if it is one, recording it in the audit log. Callers authorize the change
+pub fn remove<A: Audit<SqliteConnection>>(audit: &A, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, host: i32, group: i32) -> anyhow::Result<()> {
+    let removed = diesel::delete(schema::host_group_membership::table
+        .filter(schema::host_group_membership::host.eq(host))
+        .filter(schema::host_group_membership::group.eq(group)))
+        .execute(db)?;
+    if removed > 0 {
+        audit.audit(db, audit_entry(principal, request_id, host, group, AuditAction::Delete))?;
+    }
+    Ok(())
+}
+
+fn audit_entry(principal: Option<&Principal>, request_id: &RequestId, host: i32, group: i32, action: AuditAction) -> AuditEntry {
+    let state = Some(serde_json::json!({ "host": host, "group": group }));
+    AuditEntry {
+        entity: ENTITY.to_string(),
+        entity_id: host,
+        entity_key: Some(group),
+        action,
+        before: if action == AuditAction::Create { None } else { state.clone() },
+        after: if action == AuditAction::Delete { None } else { state },
+        principal: principal.cloned(),
+        request_id: request_id.0.clone(),
+    }
+}
+
+/// Whether `host` is an explicit member of `group`
+pub fn is_member(db: &mut SqliteConnection, host: i32, group: i32) -> anyhow::Result<bool> {
+    Ok(diesel::select(diesel::dsl::exists(schema::host_group_membership::table
+        .filter(schema::host_group_membership::host.eq(host))
+        .filter(schema::host_group_membership::group.eq(group))))
+        .get_result(db)?)
+}
+
+/// Makes `host` an explicit member of `group`, unless it is one already, and returns its groups. Takes an editor of the group and of the host,
+/// as the editors of a group edit its members
+pub fn join(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, host: i32, group: i32) -> Result<Vec<HostGroup>, OperationError> {
+    check_exists(db, Some(host), group)?;
+    authorization::require(db, principal, Role::Editor, Scope::group(Some(group))).map_err(OperationError::Forbidden)?;
+    let scope = Scope::host(db, host)?;
+    authorization::require(db, principal, Role::Editor, scope).map_err(OperationError::Forbidden)?;
+    if !is_member(db, host, group)? {
+        add(app_data.get_ref(), db, principal, request_id, host, group)?;
+    }
+    Ok(Host::groups(db, host)?)
+}
+
+/// Removes `host` from the explicit members of `group`, if it is one, and returns its groups. Takes an editor of the group
+pub fn leave(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, host: i32, group: i32) -> Result<Vec<HostGroup>, OperationError> {
+    check_exists(db, Some(host), group)?;
+    authorization::require(db, principal, Role::Editor, Scope::group(Some(group))).map_err(OperationError::Forbidden)?;
+    remove(app_data.get_ref(), db, principal, request_id, host, group)?;
+    Ok(Host::groups(db, host)?)
+}
+
+/// Creates a host as an explicit member of `group`, through the hooks of a host creation, but authorized as an editor of the group rather than globally
+pub fn create_host(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, group: i32, new_host: NewHost) -> Result<Host, OperationError> {
+    check_exists(db, None, group)?;
+    authorization::require(db, principal, Role::Editor, Scope::group(Some(group))).map_err(OperationError::Forbidden)?;
+    let filtered_new_host = <Host as RestPre<Host, NewHost, AppData<'static>>>::pre_post(app_data, principal, &new_host)?;
+    <Host as RestValidate<Host, NewHost, SqliteConnection>>::validate_post(db, &filtered_new_host).map_err(OperationError::Failed)?;
+    let result = Host::db_insert(db, &filtered_new_host);
+    let host = Host::complete_create(app_data, db, principal, request_id, &new_host, result)?;
+    add(app_data.get_ref(), db, principal, request_id, host.id, group)?;
+    Ok(host)
+}
+
+/// Fails when the host or the group is missing
+fn check_exists(db: &mut SqliteConnection, host: Option<i32>, group: i32) -> anyhow::Result<()> {
+    host.map_or(Ok(()), |host| Host::db_fetch(db, host).map(|_| ()).with_context(|| format!("No host {}", host)))
+        .and_then(|_| HostGroup::db_fetch(db, group).map(|_| ()).with_context(|| format!("No host group {}", group)))
+}
+
+/// `404 Not Found` when the host or the group is missing
+fn missing(db: &mut SqliteConnection, host: Option<i32>, group: i32) -> Option<Responder<AppData<'static>>> {
+    check_exists(db, host, group).err().map(|err| Responder::<AppData<'static>>::from(err).with_status_code(StatusCode::NOT_FOUND))
+}
+
+/// Response to a membership change, redirecting forms to `location`
+fn membership_change(result: Result<Vec<HostGroup>, OperationError>, location: &str, flash: &str) -> Responder<AppData<'static>> {
+    match result {
+        Ok(groups) => Responder::<AppData<'static>>::from(anyhow::Result::<Vec<HostGroup>>::Ok(groups)).with_redirect(location, flash),
+        Err(err) => err.into(),
+    }
+}
+
+/// `PUT /host/{id}/groups/{group}`: makes a host an explicit member of a group, as an editor of both
+pub async fn put(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, path: web::Path<(i32, i32)>) -> Responder<AppData<'static>> {
+    let (host, group) = path.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Some(responder) = missing(&mut db, Some(host), group) {
+            return responder;
+        }
+        let result = db.transaction(|db| join(&app_data, db, principal.as_ref(), &request_id, host, group));
+        membership_change(result, &format!("/host/{}", host), "Host added to the group")
+    }).await)
+        .with_error_template("host/error")
+}
+
+/// `DELETE /host/{id}/groups/{group}`: removes a host from the explicit members of a group, as an editor of the group
+pub async fn delete(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, path: web::Path<(i32, i32)>) -> Responder<AppData<'static>> {
+    let (host, group) = path.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Some(responder) = missing(&mut db, Some(host), group) {
+            return responder;
+        }
+        let result = db.transaction(|db| leave(&app_data, db, principal.as_ref(), &request_id, host, group));
+        membership_change(result, &format!("/host/{}", host), "Host removed from the group")
+    }).await)
+        .with_error_template("host/error")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MembershipForm {
+    pub group: i32,
+}
+
+/// `POST /host/{id}/groups`: makes a host an explicit member of the group chosen in the form of its page
+pub async fn post_form(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, host: web::Path<i32>, JsonOrForm(form): JsonOrForm<MembershipForm>) -> Responder<AppData<'static>> {
+    put(app_data, principal, request_id, web::Path::from((host.into_inner(), form.group))).await
+}
+
+/// `POST /host_group/{id}/hosts/{host}/delete`: removes a host from a group from the page of the group
+pub async fn delete_host_form(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, path: web::Path<(i32, i32)>) -> Responder<AppData<'static>> {
+    let (group, host) = path.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Some(responder) = missing(&mut db, Some(host), group) {
+            return responder;
+        }
+        let result = db.transaction(|db| leave(&app_data, db, principal.as_ref(), &request_id, host, group));
+        membership_change(result, &format!("/host_group/{}", group), "Host removed from the group")
+    }).await)
+        .with_error_template("host_group/error")
+}
+
+/// `POST /host_group/{id}/hosts`: creates a host as a member of a group, as an editor of the group
+pub async fn post_host(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, group: web::Path<i32>, JsonOrForm(new_host): JsonOrForm<NewHost>) -> Responder<AppData<'static>> {
+    let group = group.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Some(responder) = missing(&mut db, None, group) {
+            return responder;
+        }
+        match db.transaction(|db| create_host(&app_data, db, principal.as_ref(), &request_id, group, new_host)) {
+            Ok(host) => {
+                let etag = rest::etag(&host);
+                Responder::<AppData<'static>>::from(anyhow::Result::<Host>::Ok(host)).with_etag(&etag)
+            },
+            Err(err) => err.into(),
+        }
+    }).await)
+        .with_redirect("/host/{id}", "Host created")
+        .with_error_template("host/error")
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/host/{id}/groups/{group}", web::put().to(put))
+        .route("/host/{id}/groups/{group}", web::delete().to(delete))
+        .route("/host/{id}/groups", web::post().to(post_form))
+        .route("/host/{id}/groups/{group}/delete", web::post().to(delete))
+        .route("/host_group/{id}/hosts/{host}/delete", web::post().to(delete_host_form))
+        .route("/host_group/{id}/hosts", web::post().to(post_host));
+}
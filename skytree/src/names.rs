@@ -0,0 +1,117 @@
+use std::net::IpAddr;
+use actix_web::{http::StatusCode, web};
+use anyhow::{anyhow, bail};
+use diesel::SqliteConnection;
+use negotiated::Responder;
+use rest::{DbFactory, RequestPrincipal};
+use crate::{AppData, authorization, ui::Form, skytree::{host::{Host, NewHost}, host_group::{HostGroup, NewHostGroup}}};
+
+/// Groups every inventory has, which no host group may be named after
+const RESERVED_GROUP_NAMES: [&str; 2] = ["all", "ungrouped"];
+
+/// Whether `name` is a valid Ansible group name: letters, digits and underscores, not starting with a digit
+pub fn is_group_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn is_dns_name(name: &str) -> bool {
+    name.len() <= 253 && name.split('.').all(|label| {
+        !label.is_empty() && label.len() <= 63 && !label.starts_with('-') && !label.ends_with('-') && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+/// Checks the name of a host group, returning it trimmed
+pub fn checked_group_name(name: &str) -> anyhow::Result<String> {
+    let name = name.trim();
+    if !is_group_name(name) {
+        bail!("Invalid host group name {:?}, expected letters, digits and underscores, not starting with a digit", name);
+    }
+    if RESERVED_GROUP_NAMES.contains(&name) {
+        bail!("Invalid host group name {}, reserved by Ansible", name);
+    }
+    Ok(name.to_string())
+}
+
+/// Checks the name of a host, a hostname or an IP address, returning it trimmed, without trailing dot
+pub fn checked_host_name(name: &str) -> anyhow::Result<String> {
+    let name = name.trim().trim_end_matches('.');
+    if name.parse::<IpAddr>().is_err() && !is_dns_name(name) {
+        bail!("Invalid host name {:?}, expected a hostname or an IP address", name);
+    }
+    Ok(name.to_string())
+}
+
+/// Entity with a unique name
+pub trait Named {
+    /// Id of the entity named `name`
+    fn id_of(db: &mut SqliteConnection, name: &str) -> anyhow::Result<Option<i32>>;
+}
+impl Named for HostGroup {
+    fn id_of(db: &mut SqliteConnection, name: &str) -> anyhow::Result<Option<i32>> {
+        Ok(HostGroup::by_name(db, name)?.map(|group| group.id))
+    }
+}
+impl Named for Host {
+    fn id_of(db: &mut SqliteConnection, name: &str) -> anyhow::Result<Option<i32>> {
+        Ok(Host::by_name(db, name)?.map(|host| host.id))
+    }
+}
+
+/// `GET /{resource}/by-name/{name}`: the entity named `name`, as `GET /{resource}/{id}` shows it
+pub async fn by_name<T, NewT>(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, name: web::Path<String>) -> Responder<AppData<'static>>
+where T: Form<NewT> + Named + rest::Rest<T, NewT, AppData<'static>, SqliteConnection>,
+{
+    let name = name.into_inner();
+    let lookup_data = app_data.clone();
+    let lookup_principal = principal.clone().map(|principal| principal.into_inner());
+    let found = web::block(move || -> Result<i32, (StatusCode, anyhow::Error)> {
+        let mut db = lookup_data.db().map_err(|err| (StatusCode::SERVICE_UNAVAILABLE, err.into()))?;
+        authorization::require_viewer(&mut db, lookup_principal.as_ref()).map_err(|err| (StatusCode::FORBIDDEN, err))?;
+        T::id_of(&mut db, &name)
+            .map_err(|err| (StatusCode::OK, err))?
+            .ok_or_else(|| (StatusCode::NOT_FOUND, anyhow!("No {} named {}", T::RESOURCE.replace('_', " "), name)))
+    }).await;
+    let (status_code, err) = match found {
+        Ok(Ok(id)) => return T::get(app_data, principal, web::Path::from(id)).await,
+        Ok(Err(failure)) => failure,
+        Err(err) => (StatusCode::OK, anyhow::Error::from(err)),
+    };
+    Responder::<AppData<'static>>::from(err)
+        .with_status_code(status_code)
+        .with_error_template(&format!("{}/error", T::RESOURCE))
+}
+
+/// Registers the name lookups of host groups and hosts, before the routes of [crate::ui::resource]
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/host_group/by-name/{name}", web::get().to(by_name::<HostGroup, NewHostGroup>))
+        .route("/host/by-name/{name}", web::get().to(by_name::<Host, NewHost>));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_group_names() {
+        assert_eq!(checked_group_name(" web_servers ").unwrap(), "web_servers");
+        assert_eq!(checked_group_name("_private").unwrap(), "_private");
+        assert!(checked_group_name("1web").is_err());
+        assert!(checked_group_name("web-servers").is_err());
+        assert!(checked_group_name("").is_err());
+        assert!(checked_group_name("all").is_err());
+        assert!(checked_group_name("ungrouped").is_err());
+    }
+
+    #[test]
+    fn checks_host_names() {
+        assert_eq!(checked_host_name(" web1.example.com. ").unwrap(), "web1.example.com");
+        assert_eq!(checked_host_name("192.0.2.1").unwrap(), "192.0.2.1");
+        assert_eq!(checked_host_name("2001:db8::1").unwrap(), "2001:db8::1");
+        assert!(checked_host_name("web_1").is_err());
+        assert!(checked_host_name("-web").is_err());
+        assert!(checked_host_name("web..example").is_err());
+        assert!(checked_host_name(&format!("{}.example", "a".repeat(64))).is_err());
+        assert!(checked_host_name("").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::{collections::HashSet, net::Ipv6Addr, sync::OnceLock};
+use actix_web::web;
+use anyhow::{anyhow, bail};
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use regex::Regex;
+use rest::{DbFactory, RequestPrincipal, RestAuthorize, RestCollection, RestCollectionGetParameters};
+use serde::Deserialize;
+use crate::{AppData, facts, schema, smart_groups, skytree::{host::Host, host_group::HostGroup}};
+
+/// Hosts and groups a pattern is evaluated against. Hosts are ordered by name
+struct Inventory {
+    hosts: Vec<Host>,
+    groups: Vec<HostGroup>,
+    /// (host, group) pairs, smart group members included
+    memberships: Vec<(i32, i32)>,
+}
+impl Inventory {
+    fn load(db: &mut SqliteConnection) -> anyhow::Result<Inventory> {
+        let mut memberships = schema::host_group_membership::table
+            .select((schema::host_group_membership::host, schema::host_group_membership::group))
+            .load::<(Option<i32>, Option<i32>)>(db)?
+            .into_iter()
+            .filter_map(|(host, group)| host.zip(group))
+            .collect::<Vec<(i32, i32)>>();
+        memberships.extend(smart_groups::memberships(db)?);
+        Ok(Inventory {
+            hosts: schema::host::table.order((schema::host::name, schema::host::id)).load::<Host>(db)?,
+            groups: schema::host_group::table.order(schema::host_group::id).load::<HostGroup>(db)?,
+            memberships,
+        })
+    }
+    /// Hosts of a group and of its descendants, like Ansible's `group.get_hosts()`
+    fn group_hosts(&self, group: i32) -> Vec<i32> {
+        let mut groups = vec![group];
+        let mut index = 0;
+        while index < groups.len() {
+            let parent = groups[index];
+            groups.extend(self.groups.iter().filter(|child| child.parent == Some(parent) && !groups.contains(&child.id)).map(|child| child.id).collect::<Vec<i32>>());
+            index += 1;
+        }
+        self.hosts
+            .iter()
+            .filter(|host| self.memberships.iter().any(|(member, of)| *member == host.id && groups.contains(of)))
+            .map(|host| host.id)
+            .collect()
+    }
+    fn ungrouped(&self) -> Vec<i32> {
+        self.hosts
+            .iter()
+            .filter(|host| !self.memberships.iter().any(|(member, _)| *member == host.id))
+            .map(|host| host.id)
+            .collect()
+    }
+}
+
+/// IPv6 address of a term, bare or in brackets, whose colons do not separate terms, like Ansible's `split_host_pattern`
+fn ipv6_address(term: &str) -> Option<&str> {
+    let term = term.trim();
+    let address = term.strip_prefix('[').and_then(|term| term.strip_suffix(']')).unwrap_or(term);
+    address.parse::<Ipv6Addr>().is_ok().then_some(address)
+}
+
+/// Splits a pattern on `,`, or on `:` for terms that are neither regular expressions nor IPv6 addresses, outside of `[...]` subscripts
+fn split(pattern: &str) -> Vec<String> {
+    fn split_on(text: &str, separator: char) -> Vec<String> {
+        let mut parts = vec![String::new()];
+        let mut depth = 0;
+        for c in text.chars() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                c if c == separator && depth == 0 => { parts.push(String::new()); continue },
+                _ => (),
+            }
+            parts.last_mut().unwrap().push(c);
+        }
+        parts
+    }
+    split_on(pattern, ',')
+        .into_iter()
+        .flat_map(|part| {
+            let term = part.trim().trim_start_matches(['&', '!']);
+            match term.starts_with('~') || ipv6_address(term).is_some() {
+                true => vec![part],
+                false => split_on(&part, ':'),
+            }
+        })
+        .map(|part| {
+            let part = part.trim();
+            let term = part.trim_start_matches(['&', '!']);
+            match ipv6_address(term) {
+                Some(address) => format!("{}{}", &part[..part.len() - term.len()], address),
+                None => part.to_string(),
+            }
+        })
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// Regular expression of a shell style glob, matching whole names like Python's `fnmatch`
+fn glob(pattern: &str) -> anyhow::Result<Regex> {
+    let mut expression = String::from("^(?:");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => expression.push_str(".*"),
+            '?' => expression.push('.'),
+            '[' => {
+                let mut class = String::new();
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    class.push(c);
+                }
+                let class = match class.strip_prefix('!') {
+                    Some(negated) => format!("^{}", negated),
+                    None => class,
+                };
+                expression.push_str(&format!("[{}]", class.replace('\\', "\\\\")));
+            },
+            c => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push_str(")$");
+    Ok(Regex::new(&expression)?)
+}
+
+/// Matcher of a term: `~regex`, matched from the start of names, or a glob
+fn matcher(term: &str) -> anyhow::Result<Regex> {
+    match term.strip_prefix('~') {
+        Some(expression) => Regex::new(&format!("^(?:{})", expression)).map_err(|err| anyhow!("Invalid regular expression {}: {}", expression, err)),
+        None => glob(term),
+    }
+}
+
+/// Start and inclusive end of a subscript. An open end runs to the last host
+type Subscript = (i64, Option<i64>);
+
+/// Splits `webservers[0:2]` into the pattern and its subscript, with an inclusive end like Ansible
+fn subscript(term: &str) -> anyhow::Result<(&str, Option<Subscript>)> {
+    static SUBSCRIPT: OnceLock<Regex> = OnceLock::new();
+    let subscript = SUBSCRIPT.get_or_init(|| Regex::new(r"^(.+)\[(-?[0-9]+)(?:[:-](-?[0-9]*))?\]$").unwrap());
+    if term.starts_with('~') {
+        return Ok((term, None));
+    }
+    Ok(match subscript.captures(term) {
+        Some(captures) => {
+            let start = captures[2].parse::<i64>()?;
+            let end = match captures.get(3) {
+                None => Some(start),
+                Some(end) if end.as_str().is_empty() => None,
+                Some(end) => Some(end.as_str().parse::<i64>()?),
+            };
+            (captures.get(1).unwrap().as_str(), Some((start, end)))
+        },
+        None => (term, None),
+    })
+}
+
+/// Hosts `start` to `end` included. Negative indexes count from the end
+fn slice(hosts: Vec<i32>, (start, end): Subscript) -> Vec<i32> {
+    let count = hosts.len() as i64;
+    let index = |index: i64| if index < 0 { count + index } else { index };
+    let (start, end) = (index(start).max(0), index(end.unwrap_or(-1)).min(count - 1));
+    if start > end {
+        return Vec::new();
+    }
+    hosts[start as usize..=end as usize].to_vec()
+}
+
+/// Hosts matched by a term without its `&` or `!` prefix.
+///
+/// Matching groups contribute their hosts and those of their descendants. Host names are matched too when no group matched,
+/// or when the term is a regular expression or a glob
+fn hosts_of(inventory: &Inventory, term: &str) -> anyhow::Result<Vec<i32>> {
+    let (pattern, subscript) = subscript(term)?;
+    let mut hosts = Vec::new();
+    if pattern == "all" || pattern == "*" {
+        hosts = inventory.hosts.iter().map(|host| host.id).collect();
+    } else if pattern == "ungrouped" {
+        hosts = inventory.ungrouped();
+    } else {
+        let matcher = matcher(pattern)?;
+        let groups = inventory.groups.iter().filter(|group| matcher.is_match(&group.name)).collect::<Vec<&HostGroup>>();
+        for group in &groups {
+            hosts.extend(inventory.group_hosts(group.id));
+        }
+        if groups.is_empty() || pattern.starts_with('~') || pattern.contains(['.', '?', '*', '[']) {
+            hosts.extend(inventory.hosts.iter().filter(|host| matcher.is_match(&host.name)).map(|host| host.id));
+        }
+    }
+    let mut seen = HashSet::new();
+    hosts.retain(|host| seen.insert(*host));
+    Ok(match subscript {
+        Some(subscript) => slice(hosts, subscript),
+        None => hosts,
+    })
+}
+
+/// Hosts matched by an Ansible host pattern such as `webservers:&staging:!web3`.
+///
+/// Terms are separated by `,` or `:`. Terms without prefix are united first, then intersected with the `&` terms, then the `!`
+/// terms are excluded, like Ansible does. A pattern made of intersections and exclusions only starts from `all`
+pub fn evaluate(db: &mut SqliteConnection, pattern: &str) -> anyhow::Result<Vec<Host>> {
+    let terms = split(pattern);
+    if terms.is_empty() {
+        bail!("Empty host pattern");
+    }
+    let inventory = Inventory::load(db)?;
+    let unions = terms.iter().filter(|term| !term.starts_with(['&', '!'])).map(String::as_str).collect::<Vec<&str>>();
+    let mut hosts = Vec::new();
+    for term in if unions.is_empty() { vec!["all"] } else { unions } {
+        for host in hosts_of(&inventory, term)? {
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+    }
+    for term in terms.iter().filter_map(|term| term.strip_prefix('&')) {
+        let intersected = hosts_of(&inventory, term)?;
+        hosts.retain(|host| intersected.contains(host));
+    }
+    for term in terms.iter().filter_map(|term| term.strip_prefix('!')) {
+        let excluded = hosts_of(&inventory, term)?;
+        hosts.retain(|host| !excluded.contains(host));
+    }
+    Ok(hosts
+        .into_iter()
+        .filter_map(|id| inventory.hosts.iter().find(|host| host.id == id).cloned())
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HostsQuery {
+    pub q: Option<String>,
+    /// Ansible host pattern
+    pub pattern: Option<String>,
+    /// `path=value` fact filters, separated by commas
+    pub fact: Option<String>,
+}
+
+/// Hosts matching `pattern`, all hosts without it, whose facts match the `fact` filters, if any
+fn search(db: &mut SqliteConnection, pattern: Option<&str>, fact: Option<&str>) -> anyhow::Result<Vec<Host>> {
+    let mut hosts = match pattern {
+        Some(pattern) => evaluate(db, pattern)?,
+        None => schema::host::table.order((schema::host::name, schema::host::id)).load::<Host>(db)?,
+    };
+    if let Some(fact) = fact {
+        let matching = facts::matching(db, fact)?;
+        hosts.retain(|host| matching.contains(&host.id));
+    }
+    Ok(hosts)
+}
+
+/// `GET /hosts`: hosts matching `?pattern=`, an Ansible host pattern, and `?fact=` filters, or the regular host collection without them
+pub async fn hosts(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, query: web::Query<HostsQuery>) -> Responder<AppData<'static>> {
+    let HostsQuery { q, pattern, fact } = query.into_inner();
+    let (pattern, fact) = (pattern.filter(|pattern| !pattern.is_empty()), fact.filter(|fact| !fact.is_empty()));
+    if pattern.is_none() && fact.is_none() {
+        return <Host as RestCollection<RestCollectionGetParameters, AppData<'static>, SqliteConnection>>::get(app_data, principal, web::Query(RestCollectionGetParameters { q })).await;
+    }
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = Host::authorize_list(&app_data, &mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        search(&mut db, pattern.as_deref(), fact.as_deref()).into()
+    }).await)
+        .with_template("host/list")
+        .with_error_template("host/error")
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.route("/hosts", web::get().to(hosts));
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::connection::SimpleConnection;
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn splits_terms() {
+        assert_eq!(split("webservers:&staging:!web3"), vec!["webservers", "&staging", "!web3"]);
+        assert_eq!(split("web[0:2], db"), vec!["web[0:2]", "db"]);
+        assert_eq!(split("~web[0-9]:1, db"), vec!["~web[0-9]:1", "db"]);
+        assert_eq!(split("2001:db8::1,!fe80::1"), vec!["2001:db8::1", "!fe80::1"]);
+        assert_eq!(split("[2001:db8::1]:web"), vec!["2001:db8::1", "web"]);
+        assert!(split(" , ").is_empty());
+    }
+
+    #[test]
+    fn globs_match_whole_names() {
+        assert!(glob("web*").unwrap().is_match("web12"));
+        assert!(!glob("web*").unwrap().is_match("oldweb1"));
+        assert!(glob("db?.example").unwrap().is_match("db1.example"));
+        assert!(!glob("db?.example").unwrap().is_match("db1xexample"));
+        assert!(glob("web[12]").unwrap().is_match("web2"));
+        assert!(!glob("web[!12]").unwrap().is_match("web2"));
+    }
+
+    #[test]
+    fn slices_include_their_end() {
+        let hosts = vec![1, 2, 3, 4];
+        assert_eq!(slice(hosts.clone(), (0, Some(0))), vec![1]);
+        assert_eq!(slice(hosts.clone(), (1, Some(2))), vec![2, 3]);
+        assert_eq!(slice(hosts.clone(), (2, None)), vec![3, 4]);
+        assert_eq!(slice(hosts.clone(), (-1, Some(-1))), vec![4]);
+        assert!(slice(hosts.clone(), (3, Some(1))).is_empty());
+        assert_eq!(subscript("web[1:]").unwrap(), ("web", Some((1, None))));
+        assert_eq!(subscript("~web[0-9]").unwrap(), ("~web[0-9]", None));
+    }
+
+    #[test]
+    fn unites_then_intersects_then_excludes() {
+        let mut db = testing::db();
+        db.batch_execute(r#"
+            INSERT INTO host(id, name) VALUES (1, 'web1'), (2, 'web2'), (3, 'web3'), (4, 'db1'), (5, '2001:db8::1');
+            INSERT INTO host_group(id, name, parent) VALUES (1, 'webservers', NULL), (2, 'staging', NULL), (3, 'canary', 1);
+            INSERT INTO host_group_membership(host, "group") VALUES (1, 1), (2, 3), (3, 1), (2, 2), (3, 2), (4, 2);
+        "#).unwrap();
+        let names = |db: &mut SqliteConnection, pattern: &str| evaluate(db, pattern).unwrap().into_iter().map(|host| host.name).collect::<Vec<String>>();
+        assert_eq!(names(&mut db, "webservers"), vec!["web1", "web2", "web3"]);
+        assert_eq!(names(&mut db, "webservers:&staging:!web3"), vec!["web2"]);
+        assert_eq!(names(&mut db, "!staging"), vec!["2001:db8::1", "web1"]);
+        assert_eq!(names(&mut db, "ungrouped"), vec!["2001:db8::1"]);
+        assert_eq!(names(&mut db, "2001:db8::1"), vec!["2001:db8::1"]);
+        assert_eq!(names(&mut db, "~web[12],db*"), vec!["web1", "web2", "db1"]);
+        assert_eq!(names(&mut db, "webservers[0]"), vec!["web1"]);
+        assert!(evaluate(&mut db, ",").is_err());
+    }
+}
@@ -1,5 +1,32 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (id) {
+        id -> Integer,
+        entity -> Text,
+        entity_id -> Integer,
+        action -> Text,
+        before -> Nullable<Text>,
+        after -> Nullable<Text>,
+        user -> Nullable<Integer>,
+        principal -> Nullable<Text>,
+        timestamp -> BigInt,
+        request_id -> Text,
+        entity_key -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    api_token (id) {
+        id -> Integer,
+        user -> Integer,
+        name -> Text,
+        token_hash -> Text,
+        created -> BigInt,
+        revoked -> Nullable<BigInt>,
+    }
+}
+
 diesel::table! {
     db_version (key) {
         key -> Text,
@@ -10,7 +37,16 @@ diesel::table! {
 diesel::table! {
     host (id) {
         id -> Integer,
-        name -> Nullable<Text>,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    host_fact (host, path) {
+        host -> Integer,
+        path -> Text,
+        value -> Text,
+        gathered -> BigInt,
     }
 }
 
@@ -18,7 +54,8 @@ diesel::table! {
     host_group (id) {
         id -> Integer,
         parent -> Nullable<Integer>,
-        name -> Nullable<Text>,
+        name -> Text,
+        rule -> Nullable<Text>,
     }
 }
 
@@ -45,11 +82,31 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    role_grant (id) {
+        id -> Integer,
+        user -> Integer,
+        role -> Text,
+        host_group -> Nullable<Integer>,
+    }
+}
+
 diesel::table! {
     service (id) {
         id -> Integer,
         name -> Nullable<Text>,
-        parent -> Nullable<Binary>,
+        parent -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    service_endpoint (id) {
+        id -> Integer,
+        instance -> Integer,
+        ip -> Text,
+        port -> Integer,
+        protocol -> Text,
+        dns_name -> Nullable<Text>,
     }
 }
 
@@ -63,6 +120,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    service_instance_relation (id) {
+        id -> Integer,
+        kind -> Text,
+        source -> Integer,
+        target -> Integer,
+    }
+}
+
 diesel::table! {
     service_instance_variable (variable, instance) {
         variable -> Integer,
@@ -71,6 +137,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    service_relation (id) {
+        id -> Integer,
+        kind -> Text,
+        source -> Integer,
+        target -> Integer,
+    }
+}
+
 diesel::table! {
     service_variable (variable, service) {
         variable -> Integer,
@@ -79,35 +154,70 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    session (id) {
+        id -> Integer,
+        user -> Integer,
+        token_hash -> Text,
+        expires -> BigInt,
+    }
+}
+
+diesel::table! {
+    user (id) {
+        id -> Integer,
+        name -> Text,
+        password_hash -> Text,
+    }
+}
+
 diesel::table! {
     variable (id) {
         id -> Integer,
         name -> Nullable<Text>,
+        hash_behaviour -> Text,
+        value_schema -> Nullable<Text>,
+        secret -> Bool,
     }
 }
 
+diesel::joinable!(api_token -> user (user));
+diesel::joinable!(host_fact -> host (host));
 diesel::joinable!(host_group_membership -> host (host));
 diesel::joinable!(host_group_membership -> host_group (group));
 diesel::joinable!(host_group_variable -> variable (variable));
 diesel::joinable!(host_variable -> host (host));
 diesel::joinable!(host_variable -> variable (variable));
+diesel::joinable!(role_grant -> host_group (host_group));
+diesel::joinable!(role_grant -> user (user));
+diesel::joinable!(service_endpoint -> service_instance (instance));
 diesel::joinable!(service_instance -> host (host));
 diesel::joinable!(service_instance -> service (service));
 diesel::joinable!(service_instance_variable -> service_instance (instance));
 diesel::joinable!(service_instance_variable -> variable (variable));
+diesel::joinable!(session -> user (user));
 diesel::joinable!(service_variable -> service (service));
 diesel::joinable!(service_variable -> variable (variable));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_token,
+    audit_log,
     db_version,
     host,
+    host_fact,
     host_group,
     host_group_membership,
     host_group_variable,
     host_variable,
+    role_grant,
     service,
+    service_endpoint,
     service_instance,
+    service_instance_relation,
     service_instance_variable,
+    service_relation,
     service_variable,
+    session,
+    user,
     variable,
 );
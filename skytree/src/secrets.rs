@@ -0,0 +1,211 @@
+use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::Config;
+
+/// Output of secret values to callers not allowed to see them
+pub const REDACTED: &str = "<redacted>";
+/// Key of the object a sealed value is stored as
+const SEALED: &str = "$sealed";
+/// Key of the object Ansible reads vault ciphertext from in JSON
+pub const ANSIBLE_VAULT: &str = "__ansible_vault";
+const VAULT_ITERATIONS: u32 = 10000;
+
+/// Key secret values are encrypted with at rest, the SHA-256 of the content of `secret_key_file`
+pub struct SecretKey([u8; 32]);
+impl SecretKey {
+    pub fn load(config: &Config) -> anyhow::Result<SecretKey> {
+        let file = config.secret_key_file.as_deref().ok_or_else(|| anyhow!("No secret_key_file configured, secret variables are unavailable"))?;
+        let key = std::fs::read(file).with_context(|| format!("Unable to read secret_key_file {}", file))?;
+        if key.trim_ascii().is_empty() {
+            bail!("secret_key_file {} is empty", file);
+        }
+        Ok(SecretKey(Sha256::digest(key.trim_ascii()).into()))
+    }
+    /// Encrypts `value` with AES-256-GCM, as `{"$sealed": "<base64 nonce and ciphertext>"}`
+    pub fn seal(&self, value: &Value) -> anyhow::Result<Value> {
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = Aes256Gcm::new(&self.0.into())
+            .encrypt(Nonce::from_slice(&nonce), value.to_string().as_bytes())
+            .map_err(|_| anyhow!("Unable to encrypt secret value"))?;
+        Ok(serde_json::json!({ SEALED: STANDARD.encode([nonce.as_slice(), &ciphertext].concat()) }))
+    }
+    /// Decrypts a sealed value. Values stored in clear are returned as they are
+    pub fn unseal(&self, value: &Value) -> anyhow::Result<Value> {
+        let Some(sealed) = sealed(value) else { return Ok(value.clone()) };
+        let sealed = STANDARD.decode(sealed).context("Invalid sealed value")?;
+        if sealed.len() < 12 {
+            bail!("Invalid sealed value");
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let plaintext = Aes256Gcm::new(&self.0.into())
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Unable to decrypt secret value, was secret_key_file changed?"))?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+fn sealed(value: &Value) -> Option<&str> {
+    match value {
+        Value::Object(object) if object.len() == 1 => object.get(SEALED).and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+pub fn is_sealed(value: &Value) -> bool {
+    sealed(value).is_some()
+}
+
+/// Content of `vault_password_file`, without the trailing newline
+pub fn vault_password(config: &Config) -> anyhow::Result<String> {
+    let file = config.vault_password_file.as_deref().ok_or_else(|| anyhow!("No vault_password_file configured"))?;
+    let password = std::fs::read_to_string(file).with_context(|| format!("Unable to read vault_password_file {}", file))?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn hexlify(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Encrypts `plaintext` like `ansible-vault encrypt_string`, in the `$ANSIBLE_VAULT;1.1;AES256` format
+pub fn vault(password: &str, plaintext: &str) -> String {
+    let mut salt = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut salt);
+    vault_with_salt(password, plaintext, &salt)
+}
+
+/// `vault` with the given PBKDF2 salt, making the output deterministic
+fn vault_with_salt(password: &str, plaintext: &str, salt: &[u8; 32]) -> String {
+    let mut derived = [0u8; 80];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, VAULT_ITERATIONS, &mut derived);
+    let (cipher_key, rest) = derived.split_at(32);
+    let (hmac_key, iv) = rest.split_at(32);
+    // Ansible pads to the AES block size, although CTR mode does not need it
+    let padding = 16 - plaintext.len() % 16;
+    let mut ciphertext = plaintext.as_bytes().to_vec();
+    ciphertext.extend(std::iter::repeat_n(padding as u8, padding));
+    ctr::Ctr128BE::<aes::Aes256>::new(cipher_key.into(), iv.into()).apply_keystream(&mut ciphertext);
+    let mut hmac = <Hmac<Sha256> as Mac>::new_from_slice(hmac_key).expect("HMAC takes keys of any size");
+    hmac.update(&ciphertext);
+    let vaulttext = hexlify(format!("{}\n{}\n{}", hexlify(salt), hexlify(&hmac.finalize().into_bytes()), hexlify(&ciphertext)).as_bytes());
+    let lines = vaulttext.as_bytes().chunks(80).map(|line| String::from_utf8_lossy(line).to_string()).collect::<Vec<String>>();
+    format!("$ANSIBLE_VAULT;1.1;AES256\n{}", lines.join("\n"))
+}
+
+/// How values of secret variables are output
+pub enum Disclosure {
+    /// Replaced by [REDACTED]
+    Redacted,
+    /// In clear
+    Revealed(SecretKey),
+    /// As Ansible vault ciphertext encrypted with this password, `{"__ansible_vault": ...}` in JSON. Values other than strings are encrypted as JSON text
+    Vaulted(SecretKey, String),
+}
+impl Disclosure {
+    /// Key to decrypt the stored values with, unless they are redacted anyway
+    pub fn key(&self) -> Option<&SecretKey> {
+        match self {
+            Disclosure::Redacted => None,
+            Disclosure::Revealed(key) | Disclosure::Vaulted(key, _) => Some(key),
+        }
+    }
+    /// Output of the decrypted `value` of a secret variable
+    pub fn disclose(&self, value: Value) -> Value {
+        match self {
+            Disclosure::Redacted => Value::String(REDACTED.to_string()),
+            Disclosure::Revealed(_) => value,
+            Disclosure::Vaulted(_, password) => {
+                let plaintext = match value {
+                    Value::String(text) => text,
+                    value => value.to_string(),
+                };
+                serde_json::json!({ ANSIBLE_VAULT: vault(password, &plaintext) })
+            },
+        }
+    }
+    /// Output of the stored `value` of a secret variable
+    pub fn disclose_stored(&self, value: &Value) -> anyhow::Result<Value> {
+        Ok(match self.key() {
+            Some(key) => self.disclose(key.unseal(value)?),
+            None => self.disclose(Value::Null),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn unhexlify(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|index| u8::from_str_radix(&text[index..index + 2], 16).unwrap()).collect()
+    }
+
+    /// Decrypts vault text like Ansible's `VaultAES256.decrypt`, checking the HMAC
+    fn unvault(password: &str, vaulted: &str) -> String {
+        let (header, body) = vaulted.split_once('\n').unwrap();
+        assert_eq!(header, "$ANSIBLE_VAULT;1.1;AES256");
+        assert!(body.lines().all(|line| line.len() <= 80));
+        let body = String::from_utf8(unhexlify(&body.replace('\n', ""))).unwrap();
+        let [salt, hmac, ciphertext] = body.split('\n').map(unhexlify).collect::<Vec<Vec<u8>>>().try_into().unwrap();
+        let mut derived = [0u8; 80];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, VAULT_ITERATIONS, &mut derived);
+        let (cipher_key, rest) = derived.split_at(32);
+        let (hmac_key, iv) = rest.split_at(32);
+        let mut expected = <Hmac<Sha256> as Mac>::new_from_slice(hmac_key).unwrap();
+        expected.update(&ciphertext);
+        expected.verify_slice(&hmac).unwrap();
+        let mut plaintext = ciphertext;
+        ctr::Ctr128BE::<aes::Aes256>::new(cipher_key.into(), iv.into()).apply_keystream(&mut plaintext);
+        let padding = *plaintext.last().unwrap() as usize;
+        assert!((1..=16).contains(&padding) && plaintext.ends_with(&vec![padding as u8; padding]));
+        plaintext.truncate(plaintext.len() - padding);
+        String::from_utf8(plaintext).unwrap()
+    }
+
+    #[test]
+    fn vaults_like_ansible() {
+        for plaintext in ["", "s3cret", "exactly 16 bytes", &"long ".repeat(40)] {
+            let vaulted = vault("vault password", plaintext);
+            assert_eq!(unvault("vault password", &vaulted), plaintext);
+        }
+        let vaulted = Disclosure::Vaulted(SecretKey([7; 32]), "pw".to_string()).disclose(json!({ "user": "admin" }));
+        assert_eq!(unvault("pw", vaulted[ANSIBLE_VAULT].as_str().unwrap()), r#"{"user":"admin"}"#);
+    }
+
+    /// `ansible-vault encrypt_string` output for `fooooo`, with the password `password`
+    const ANSIBLE_VECTOR: &str = "$ANSIBLE_VAULT;1.1;AES256
+62313365396662343061393464336163383764373764613633653634306231386433626436623361
+6134333665353966363534333632666535333761666131620a663537646436643839616531643561
+63396265333966386166373632626539326166353965363262633030333630313338646335303630
+3438626666666137650a353638643435666633633964366338633066623234616432373231333331
+6564";
+
+    #[test]
+    fn vaults_like_ansible_vector() {
+        assert_eq!(unvault("password", ANSIBLE_VECTOR), "fooooo");
+        let body = String::from_utf8(unhexlify(&ANSIBLE_VECTOR.split_once('\n').unwrap().1.replace('\n', ""))).unwrap();
+        let salt = unhexlify(body.split('\n').next().unwrap()).try_into().unwrap();
+        assert_eq!(vault_with_salt("password", "fooooo", &salt), ANSIBLE_VECTOR);
+    }
+
+    #[test]
+    fn seals_and_unseals() {
+        let key = SecretKey([7; 32]);
+        let value = json!({ "password": "s3cret", "port": 5432 });
+        let sealed = key.seal(&value).unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.to_string().contains("s3cret"));
+        assert_ne!(key.seal(&value).unwrap(), sealed);
+        assert_eq!(key.unseal(&sealed).unwrap(), value);
+        assert_eq!(key.unseal(&value).unwrap(), value);
+        assert!(SecretKey([8; 32]).unseal(&sealed).is_err());
+        assert_eq!(Disclosure::Redacted.disclose_stored(&sealed).unwrap(), json!(REDACTED));
+    }
+}
@@ -0,0 +1,108 @@
+use async_trait::async_trait;
+use rest::{RestCollection, Rest, Crud};
+use rest_derive::{Rest, Crud};
+use serde::{Deserialize, Serialize};
+use diesel::{self, *};
+use crate::{authorization::{self, Role, Scope}, facts, names, schema, smart_groups, secrets::Disclosure, variables::{self, Owner}};
+use crate::skytree::{host_group::HostGroup, service_instance::ServiceInstance, variable::Variable};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Rest, Crud)]
+#[diesel(table_name = schema::host, treat_none_as_null = true)]
+#[rest(post=false,pre=true,context=true,authorize=true,validate=true,audit=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
+#[crud(table_name=schema::host, connection=diesel::sqlite::SqliteConnection)]
+pub struct Host {
+    pub id: i32,
+    pub name: String,
+}
+
+impl Host {
+    pub fn by_name(db: &mut SqliteConnection, name: &str) -> anyhow::Result<Option<Host>> {
+        Ok(schema::host::table
+            .filter(schema::host::dsl::name.eq(name))
+            .first::<Host>(db)
+            .optional()?)
+    }
+    pub fn groups(db: &mut SqliteConnection, id: i32) -> anyhow::Result<Vec<HostGroup>> {
+        Ok(schema::host_group::table
+            .inner_join(schema::host_group_membership::table)
+            .filter(schema::host_group_membership::dsl::host.eq(id))
+            .select(schema::host_group::all_columns)
+            .order(schema::host_group::dsl::name)
+            .load::<HostGroup>(db)?)
+    }
+}
+
+impl rest::RestContext<Host, SqliteConnection> for Host {
+    fn context(db: &mut SqliteConnection, object: &Host) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "groups": Host::groups(db, object.id)?,
+            "smart_groups": smart_groups::groups_of(db, object.id)?,
+            "instances": ServiceInstance::of_host(db, object.id)?,
+            "variables": variables::values(db, Owner::Host(object.id), &Disclosure::Redacted)?,
+            "effective_variables": variables::effective(db, object.id, &Disclosure::Redacted)?,
+            "facts": facts::stored(db, object.id)?,
+            "host_groups": schema::host_group::table.order(schema::host_group::name).load::<HostGroup>(db)?,
+            "all_variables": schema::variable::table.order(schema::variable::name).load::<Variable>(db)?,
+        }))
+    }
+}
+
+/// Fails when a host other than `id`, or any host for a new one, is named `name`
+fn check_unique(db: &mut SqliteConnection, id: Option<i32>, name: &str) -> anyhow::Result<()> {
+    if let Some(other) = Host::by_name(db, name)?.filter(|other| Some(other.id) != id) {
+        anyhow::bail!("Host name {} is already taken by host {}", name, other.id);
+    }
+    Ok(())
+}
+
+impl rest::RestPre<Host, NewHost, crate::AppData<'static>> for Host {
+    fn pre_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, new_object: &NewHost) -> anyhow::Result<NewHost> {
+        Ok(NewHost { name: names::checked_host_name(&new_object.name)? })
+    }
+    fn pre_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+    fn pre_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32, to_update: &Host) -> anyhow::Result<(i32, Host)> {
+        Ok((id, Host { name: names::checked_host_name(&to_update.name)?, ..to_update.clone() }))
+    }
+    fn pre_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+}
+
+/// Hosts are edited by the editors of any of their groups. Hosts created here belong to no group yet, so creating one is global: editors of a group create hosts in it with [crate::memberships::create_host]
+impl rest::RestAuthorize<Host, NewHost, crate::AppData<'static>, SqliteConnection> for Host {
+    fn authorize_list(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _new_object: &NewHost) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)
+    }
+    fn authorize_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32, _to_update: &Host) -> anyhow::Result<()> {
+        let scope = Scope::host(db, id)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+    fn authorize_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<()> {
+        let scope = Scope::host(db, id)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+}
+
+/// Host names are unique
+impl rest::RestValidate<Host, NewHost, SqliteConnection> for Host {
+    fn validate_post(db: &mut SqliteConnection, new_object: &NewHost) -> anyhow::Result<()> {
+        check_unique(db, None, &new_object.name)
+    }
+    fn validate_put(db: &mut SqliteConnection, id: i32, to_update: &Host) -> anyhow::Result<()> {
+        check_unique(db, Some(id), &to_update.name)
+    }
+    fn validate_post_batch(_db: &mut SqliteConnection, new_object: &NewHost, batch: &[NewHost]) -> anyhow::Result<()> {
+        if let Some(index) = batch.iter().position(|other| other.name == new_object.name) {
+            anyhow::bail!("Host name {} is already taken by item {} of the request", new_object.name, index);
+        }
+        Ok(())
+    }
+}
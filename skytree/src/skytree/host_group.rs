@@ -1,16 +1,175 @@
 use async_trait::async_trait;
+use anyhow::Context;
 use rest::{RestCollection, Rest, Crud};
 use rest_derive::{Rest, Crud};
 use serde::{Deserialize, Serialize};
 use diesel::{self, *};
-use crate::schema;
+use crate::{authorization::{self, Role, Scope}, names, schema, secrets::Disclosure, smart_groups, variables::{self, Owner}};
+use crate::skytree::{host::Host, variable::Variable};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Rest, Crud)]
-#[diesel(table_name = schema::host_group)]
-#[rest(post=true,pre=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
+#[diesel(table_name = schema::host_group, treat_none_as_null = true)]
+#[rest(post=true,pre=true,context=true,authorize=true,validate=true,audit=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
 #[crud(table_name=schema::host_group, connection=diesel::sqlite::SqliteConnection)]
 pub struct HostGroup {
     pub id: i32,
     pub parent: Option<i32>,
-    pub name: Option<String>,
-}
\ No newline at end of file
+    pub name: String,
+    /// Rule computing members of a smart group, in addition to its explicit members. See [smart_groups::Rule]
+    #[serde(default)]
+    pub rule: Option<String>,
+}
+impl HostGroup {
+    /// Ancestors of a group, from the root of the hierarchy down to the group parent
+    pub fn ancestors(db: &mut SqliteConnection, group: &HostGroup) -> anyhow::Result<Vec<HostGroup>> {
+        let mut ancestors: Vec<HostGroup> = Vec::new();
+        let mut parent = group.parent;
+        while let Some(parent_id) = parent {
+            if parent_id == group.id || ancestors.iter().any(|ancestor| ancestor.id == parent_id) {
+                anyhow::bail!("Cycle detected in host group hierarchy at group {}", parent_id);
+            }
+            let ancestor = HostGroup::db_fetch(db, parent_id)?;
+            parent = ancestor.parent;
+            ancestors.push(ancestor);
+        }
+        ancestors.reverse();
+        Ok(ancestors)
+    }
+    /// Fails unless `parent` is a group other than `id` and its descendants, or the root
+    pub fn check_parent(db: &mut SqliteConnection, id: i32, parent: Option<i32>) -> anyhow::Result<()> {
+        let Some(parent) = parent else {
+            return Ok(());
+        };
+        let parent = HostGroup::db_fetch(db, parent).with_context(|| format!("Unknown host group {}", parent))?;
+        if parent.id == id || HostGroup::ancestors(db, &parent)?.iter().any(|ancestor| ancestor.id == id) {
+            anyhow::bail!("Host group {} cannot be put under {}, which is itself or one of its descendants", id, parent.name);
+        }
+        Ok(())
+    }
+    pub fn by_name(db: &mut SqliteConnection, name: &str) -> anyhow::Result<Option<HostGroup>> {
+        Ok(schema::host_group::table
+            .filter(schema::host_group::dsl::name.eq(name))
+            .first::<HostGroup>(db)
+            .optional()?)
+    }
+    pub fn children(db: &mut SqliteConnection, id: i32) -> anyhow::Result<Vec<HostGroup>> {
+        Ok(schema::host_group::table
+            .filter(schema::host_group::dsl::parent.eq(id))
+            .order(schema::host_group::dsl::name)
+            .load::<HostGroup>(db)?)
+    }
+    pub fn hosts(db: &mut SqliteConnection, id: i32) -> anyhow::Result<Vec<Host>> {
+        Ok(schema::host::table
+            .inner_join(schema::host_group_membership::table)
+            .filter(schema::host_group_membership::dsl::group.eq(id))
+            .select(schema::host::all_columns)
+            .order(schema::host::dsl::name)
+            .load::<Host>(db)?)
+    }
+}
+
+impl rest::RestContext<HostGroup, SqliteConnection> for HostGroup {
+    fn context(db: &mut SqliteConnection, object: &HostGroup) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "ancestors": HostGroup::ancestors(db, object)?,
+            "children": HostGroup::children(db, object.id)?,
+            "hosts": HostGroup::hosts(db, object.id)?,
+            "matching_hosts": match object.rule.as_deref() {
+                Some(rule) => smart_groups::members(db, &rule.parse()?)?,
+                None => Vec::new(),
+            },
+            "variables": variables::values(db, Owner::HostGroup(object.id), &Disclosure::Redacted)?,
+            "all_variables": schema::variable::table.order(schema::variable::name).load::<Variable>(db)?,
+        }))
+    }
+}
+
+/// Rule of a group, checked. Blank rules, as sent by forms, make a regular group
+fn checked_rule(rule: Option<&str>) -> anyhow::Result<Option<String>> {
+    match rule.map(str::trim).filter(|rule| !rule.is_empty()) {
+        Some(rule) => {
+            smart_groups::check(rule)?;
+            Ok(Some(rule.to_string()))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Fails when a group other than `id`, or any group for a new one, is named `name`
+fn check_unique(db: &mut SqliteConnection, id: Option<i32>, name: &str) -> anyhow::Result<()> {
+    if let Some(other) = HostGroup::by_name(db, name)?.filter(|other| Some(other.id) != id) {
+        anyhow::bail!("Host group name {} is already taken by host group {}", name, other.id);
+    }
+    Ok(())
+}
+
+impl rest::RestPre<HostGroup, NewHostGroup, crate::AppData<'static>> for HostGroup {
+    fn pre_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, new_object: &NewHostGroup) -> anyhow::Result<NewHostGroup> {
+        Ok(NewHostGroup { name: names::checked_group_name(&new_object.name)?, rule: checked_rule(new_object.rule.as_deref())?, ..new_object.clone() })
+    }
+    fn pre_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+    fn pre_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32, to_update: &HostGroup) -> anyhow::Result<(i32, HostGroup)> {
+        Ok((id, HostGroup { name: names::checked_group_name(&to_update.name)?, rule: checked_rule(to_update.rule.as_deref())?, ..to_update.clone() }))
+    }
+    fn pre_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+}
+impl rest::RestPost<HostGroup, NewHostGroup, crate::AppData<'static>> for HostGroup {
+    fn post_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _new_object: &NewHostGroup, result: anyhow::Result<HostGroup>) -> anyhow::Result<HostGroup> {
+        result
+    }
+    fn post_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _id: i32, result: anyhow::Result<HostGroup>) -> anyhow::Result<HostGroup> {
+        result
+    }
+    fn post_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _id: i32, _to_update: &HostGroup, result: anyhow::Result<HostGroup>) -> anyhow::Result<HostGroup> {
+        result
+    }
+    fn post_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _id: i32, result: anyhow::Result<HostGroup>) -> anyhow::Result<HostGroup> {
+        result
+    }
+}
+
+/// Editors of a group subtree manage the groups below it, and may move groups between subtrees they edit
+impl rest::RestAuthorize<HostGroup, NewHostGroup, crate::AppData<'static>, SqliteConnection> for HostGroup {
+    fn authorize_list(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, new_object: &NewHostGroup) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::group(new_object.parent))
+    }
+    fn authorize_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32, to_update: &HostGroup) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::group(Some(id)))?;
+        if to_update.parent != HostGroup::db_fetch(db, id)?.parent {
+            authorization::require(db, principal, Role::Editor, Scope::group(to_update.parent))?;
+        }
+        Ok(())
+    }
+    fn authorize_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::group(Some(id)))
+    }
+}
+
+/// Host group names are unique, and groups cannot be moved below themselves
+impl rest::RestValidate<HostGroup, NewHostGroup, SqliteConnection> for HostGroup {
+    fn validate_post(db: &mut SqliteConnection, new_object: &NewHostGroup) -> anyhow::Result<()> {
+        check_unique(db, None, &new_object.name)
+    }
+    fn validate_put(db: &mut SqliteConnection, id: i32, to_update: &HostGroup) -> anyhow::Result<()> {
+        if to_update.parent != HostGroup::db_fetch(db, id)?.parent {
+            HostGroup::check_parent(db, id, to_update.parent)?;
+        }
+        check_unique(db, Some(id), &to_update.name)
+    }
+    fn validate_post_batch(_db: &mut SqliteConnection, new_object: &NewHostGroup, batch: &[NewHostGroup]) -> anyhow::Result<()> {
+        if let Some(index) = batch.iter().position(|other| other.name == new_object.name) {
+            anyhow::bail!("Host group name {} is already taken by item {} of the request", new_object.name, index);
+        }
+        Ok(())
+    }
+}
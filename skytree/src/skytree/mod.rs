@@ -1 +1,9 @@
-pub mod host_group;
\ No newline at end of file
+pub mod host;
+pub mod host_group;
+pub mod role_grant;
+pub mod service;
+pub mod service_endpoint;
+pub mod service_instance;
+pub mod service_instance_relation;
+pub mod service_relation;
+pub mod variable;
\ No newline at end of file
@@ -0,0 +1,77 @@
+use async_trait::async_trait;
+use rest::{RestCollection, Rest, Crud};
+use rest_derive::{Rest, Crud};
+use serde::{Deserialize, Serialize};
+use diesel::{self, *};
+use crate::{auth::User, authorization::{self, Role, Scope}, schema, skytree::host_group::HostGroup};
+
+/// Role granted to a user, globally or on a host group subtree
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Rest, Crud)]
+#[diesel(table_name = schema::role_grant, treat_none_as_null = true)]
+#[rest(post=false,pre=true,context=true,authorize=true,audit=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
+#[crud(table_name=schema::role_grant, search_field=role, connection=diesel::sqlite::SqliteConnection)]
+pub struct RoleGrant {
+    pub id: i32,
+    pub user: i32,
+    pub role: String,
+    pub host_group: Option<i32>,
+}
+
+impl RoleGrant {
+    /// Role grants, with the name of their user
+    pub fn list(db: &mut SqliteConnection) -> anyhow::Result<Vec<(RoleGrant, String)>> {
+        Ok(schema::role_grant::table
+            .inner_join(schema::user::table)
+            .select((schema::role_grant::all_columns, schema::user::name))
+            .order(schema::role_grant::id)
+            .load::<(RoleGrant, String)>(db)?)
+    }
+}
+
+impl rest::RestContext<RoleGrant, SqliteConnection> for RoleGrant {
+    fn context(db: &mut SqliteConnection, object: &RoleGrant) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "user": schema::user::table.find(object.user).first::<User>(db)?,
+            "host_group": object.host_group.map(|group| HostGroup::db_fetch(db, group)).transpose()?,
+        }))
+    }
+}
+
+impl rest::RestPre<RoleGrant, NewRoleGrant, crate::AppData<'static>> for RoleGrant {
+    fn pre_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, new_object: &NewRoleGrant) -> anyhow::Result<NewRoleGrant> {
+        new_object.role.parse::<Role>()?;
+        Ok(new_object.clone())
+    }
+    fn pre_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+    fn pre_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32, to_update: &RoleGrant) -> anyhow::Result<(i32, RoleGrant)> {
+        to_update.role.parse::<Role>()?;
+        Ok((id, to_update.clone()))
+    }
+    fn pre_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+}
+
+/// Admins grant roles within their own scope
+impl rest::RestAuthorize<RoleGrant, NewRoleGrant, crate::AppData<'static>, SqliteConnection> for RoleGrant {
+    fn authorize_list(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Admin, Scope::Anywhere)
+    }
+    fn authorize_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, new_object: &NewRoleGrant) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Admin, Scope::group(new_object.host_group))
+    }
+    fn authorize_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Admin, Scope::Anywhere)
+    }
+    fn authorize_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32, to_update: &RoleGrant) -> anyhow::Result<()> {
+        let current = RoleGrant::db_fetch(db, id)?;
+        authorization::require(db, principal, Role::Admin, Scope::group(current.host_group))?;
+        authorization::require(db, principal, Role::Admin, Scope::group(to_update.host_group))
+    }
+    fn authorize_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<()> {
+        let current = RoleGrant::db_fetch(db, id)?;
+        authorization::require(db, principal, Role::Admin, Scope::group(current.host_group))
+    }
+}
@@ -0,0 +1,58 @@
+use async_trait::async_trait;
+use rest::{RestCollection, Rest, Crud};
+use rest_derive::{Rest, Crud};
+use serde::{Deserialize, Serialize};
+use diesel::{self, *};
+use crate::{authorization::{self, Role, Scope}, schema, secrets::Disclosure, variables::{self, Owner}};
+use crate::skytree::{service_instance::ServiceInstance, service_relation::ServiceRelation};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Rest, Crud)]
+#[diesel(table_name = schema::service, treat_none_as_null = true)]
+#[rest(post=false,pre=false,context=true,authorize=true,audit=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
+#[crud(table_name=schema::service, connection=diesel::sqlite::SqliteConnection)]
+pub struct Service {
+    pub id: i32,
+    pub name: Option<String>,
+    pub parent: Option<i32>,
+}
+
+impl Service {
+    pub fn children(db: &mut SqliteConnection, id: i32) -> anyhow::Result<Vec<Service>> {
+        Ok(schema::service::table
+            .filter(schema::service::dsl::parent.eq(id))
+            .order(schema::service::dsl::name)
+            .load::<Service>(db)?)
+    }
+}
+
+impl rest::RestContext<Service, SqliteConnection> for Service {
+    fn context(db: &mut SqliteConnection, object: &Service) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "parent": object.parent.map(|parent| Service::db_fetch(db, parent)).transpose()?,
+            "children": Service::children(db, object.id)?,
+            "instances": ServiceInstance::of_service(db, object.id)?,
+            "relations_from": ServiceRelation::from_service(db, object.id)?,
+            "relations_to": ServiceRelation::to_service(db, object.id)?,
+            "variables": variables::values(db, Owner::Service(object.id), &Disclosure::Redacted)?,
+        }))
+    }
+}
+
+/// Services are outside the group hierarchy, so editing them takes a global role
+impl rest::RestAuthorize<Service, NewService, crate::AppData<'static>, SqliteConnection> for Service {
+    fn authorize_list(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _new_object: &NewService) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)
+    }
+    fn authorize_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32, _to_update: &Service) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)
+    }
+    fn authorize_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)
+    }
+}
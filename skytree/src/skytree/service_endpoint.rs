@@ -0,0 +1,189 @@
+use std::net::{IpAddr, SocketAddr};
+use async_trait::async_trait;
+use anyhow::{anyhow, bail};
+use rest::{RestCollection, Rest, Crud};
+use rest_derive::{Rest, Crud};
+use serde::{Deserialize, Serialize};
+use diesel::{self, *};
+use crate::{authorization::{self, Role, Scope}, names, schema, topology};
+use crate::skytree::{service::Service, service_instance::ServiceInstance};
+
+/// Host variable describing the service instances of a host and their endpoints
+pub const SERVICE_INSTANCES_VARIABLE: &str = "skytree_service_instances";
+const PROTOCOLS: [&str; 3] = ["tcp", "udp", "sctp"];
+
+pub fn default_protocol() -> String {
+    "tcp".to_string()
+}
+
+/// Network endpoint a service instance listens on
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Rest, Crud)]
+#[diesel(table_name = schema::service_endpoint, treat_none_as_null = true)]
+#[rest(post=false,pre=true,context=true,authorize=true,validate=true,audit=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
+#[crud(table_name=schema::service_endpoint, search_field=ip, connection=diesel::sqlite::SqliteConnection)]
+pub struct ServiceEndpoint {
+    pub id: i32,
+    pub instance: i32,
+    /// IPv4 or IPv6 address, stored in canonical form
+    pub ip: String,
+    pub port: i32,
+    /// tcp, udp or sctp
+    #[serde(default = "default_protocol")]
+    pub protocol: String,
+    #[serde(default)]
+    pub dns_name: Option<String>,
+}
+
+impl ServiceEndpoint {
+    pub fn of_instance(db: &mut SqliteConnection, instance: i32) -> anyhow::Result<Vec<ServiceEndpoint>> {
+        Ok(schema::service_endpoint::table
+            .filter(schema::service_endpoint::dsl::instance.eq(instance))
+            .order((schema::service_endpoint::dsl::port, schema::service_endpoint::dsl::id))
+            .load::<ServiceEndpoint>(db)?)
+    }
+    /// The endpoint as exposed to Ansible, with its `address` ready to connect to
+    pub fn variable(&self) -> serde_json::Value {
+        let address = self.ip.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, self.port as u16).to_string()).unwrap_or_else(|_| format!("{}:{}", self.ip, self.port));
+        serde_json::json!({
+            "ip": self.ip,
+            "port": self.port,
+            "protocol": self.protocol,
+            "dns_name": self.dns_name,
+            "address": address,
+        })
+    }
+}
+
+/// Service instances of a host with their endpoints, and the instances they are related to with theirs, the [SERVICE_INSTANCES_VARIABLE] of the host
+pub fn instances_variable(db: &mut SqliteConnection, host: i32) -> anyhow::Result<serde_json::Value> {
+    let mut instances = Vec::new();
+    for instance in ServiceInstance::of_host(db, host)? {
+        let service = Service::db_fetch(db, instance.service)?;
+        let endpoints = ServiceEndpoint::of_instance(db, instance.id)?.iter().map(ServiceEndpoint::variable).collect::<Vec<serde_json::Value>>();
+        let relations = topology::related(db, &instance)?;
+        instances.push(serde_json::json!({ "id": instance.id, "name": instance.name, "service": service.name, "endpoints": endpoints, "relations": relations }));
+    }
+    Ok(serde_json::Value::Array(instances))
+}
+
+/// Checks the fields of an endpoint. Returns the address in canonical form and the DNS name, lowercase without trailing dot. Blank names, as sent by forms, are no name
+fn checked(ip: &str, port: i32, protocol: &str, dns_name: Option<&str>) -> anyhow::Result<(String, Option<String>)> {
+    let ip = ip.trim().parse::<IpAddr>().map_err(|_| anyhow!("Invalid IP address {}, expected IPv4 or IPv6", ip.trim()))?;
+    if !(1..=65535).contains(&port) {
+        bail!("Invalid port {}, expected 1 to 65535", port);
+    }
+    if !PROTOCOLS.contains(&protocol) {
+        bail!("Unknown protocol {}, expected one of {}", protocol, PROTOCOLS.join(", "));
+    }
+    let dns_name = dns_name.map(str::trim).filter(|name| !name.is_empty()).map(|name| name.trim_end_matches('.').to_lowercase());
+    if let Some(name) = dns_name.as_deref().filter(|name| !names::is_dns_name(name)) {
+        bail!("Invalid DNS name {}", name);
+    }
+    Ok((ip.to_string(), dns_name))
+}
+
+/// Whether two addresses take the same port: equal addresses, or the unspecified address of the family of the other, as a socket bound to `0.0.0.0` takes the port on every IPv4 address
+fn overlaps(address: IpAddr, other: IpAddr) -> bool {
+    address == other || (address.is_ipv4() == other.is_ipv4() && (address.is_unspecified() || other.is_unspecified()))
+}
+
+/// Fails when another endpoint on `host` already claims the address, port and protocol
+pub fn check_unique(db: &mut SqliteConnection, id: Option<i32>, host: i32, ip: &str, port: i32, protocol: &str) -> anyhow::Result<()> {
+    let address = ip.parse::<IpAddr>()?;
+    let claimed = schema::service_endpoint::table
+        .inner_join(schema::service_instance::table)
+        .filter(schema::service_instance::host.eq(host))
+        .filter(schema::service_endpoint::port.eq(port))
+        .filter(schema::service_endpoint::protocol.eq(protocol))
+        .select((schema::service_endpoint::all_columns, schema::service_instance::name))
+        .load::<(ServiceEndpoint, String)>(db)?;
+    let clash = claimed
+        .into_iter()
+        .find(|(other, _)| Some(other.id) != id && other.ip.parse::<IpAddr>().is_ok_and(|other| overlaps(address, other)));
+    if let Some((other, name)) = clash {
+        bail!("{} port {}/{} is already claimed on this host by endpoint {} of service instance {}", other.ip, port, protocol, other.id, name);
+    }
+    Ok(())
+}
+
+impl rest::RestContext<ServiceEndpoint, SqliteConnection> for ServiceEndpoint {
+    fn context(db: &mut SqliteConnection, object: &ServiceEndpoint) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "instance": ServiceInstance::db_fetch(db, object.instance)?,
+            "variable": object.variable(),
+        }))
+    }
+}
+
+/// Endpoints are validated
+impl rest::RestPre<ServiceEndpoint, NewServiceEndpoint, crate::AppData<'static>> for ServiceEndpoint {
+    fn pre_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, new_object: &NewServiceEndpoint) -> anyhow::Result<NewServiceEndpoint> {
+        let (ip, dns_name) = checked(&new_object.ip, new_object.port, &new_object.protocol, new_object.dns_name.as_deref())?;
+        Ok(NewServiceEndpoint { ip, dns_name, ..new_object.clone() })
+    }
+    fn pre_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+    fn pre_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32, to_update: &ServiceEndpoint) -> anyhow::Result<(i32, ServiceEndpoint)> {
+        let (ip, dns_name) = checked(&to_update.ip, to_update.port, &to_update.protocol, to_update.dns_name.as_deref())?;
+        Ok((id, ServiceEndpoint { ip, dns_name, ..to_update.clone() }))
+    }
+    fn pre_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+}
+
+/// Endpoints are edited by the editors of the host of their service instance
+impl rest::RestAuthorize<ServiceEndpoint, NewServiceEndpoint, crate::AppData<'static>, SqliteConnection> for ServiceEndpoint {
+    fn authorize_list(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, new_object: &NewServiceEndpoint) -> anyhow::Result<()> {
+        let host = ServiceInstance::db_fetch(db, new_object.instance)?.host;
+        let scope = Scope::host(db, host)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+    fn authorize_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32, to_update: &ServiceEndpoint) -> anyhow::Result<()> {
+        let instance = ServiceEndpoint::db_fetch(db, id)?.instance;
+        for instance in [instance, to_update.instance] {
+            let host = ServiceInstance::db_fetch(db, instance)?.host;
+            let scope = Scope::host(db, host)?;
+            authorization::require(db, principal, Role::Editor, scope)?;
+        }
+        Ok(())
+    }
+    fn authorize_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<()> {
+        let instance = ServiceEndpoint::db_fetch(db, id)?.instance;
+        let host = ServiceInstance::db_fetch(db, instance)?.host;
+        let scope = Scope::host(db, host)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+}
+
+/// Endpoints must not claim an address and port already taken on their host
+impl rest::RestValidate<ServiceEndpoint, NewServiceEndpoint, SqliteConnection> for ServiceEndpoint {
+    fn validate_post(db: &mut SqliteConnection, new_object: &NewServiceEndpoint) -> anyhow::Result<()> {
+        let host = ServiceInstance::db_fetch(db, new_object.instance)?.host;
+        check_unique(db, None, host, &new_object.ip, new_object.port, &new_object.protocol)
+    }
+    fn validate_put(db: &mut SqliteConnection, id: i32, to_update: &ServiceEndpoint) -> anyhow::Result<()> {
+        let host = ServiceInstance::db_fetch(db, to_update.instance)?.host;
+        check_unique(db, Some(id), host, &to_update.ip, to_update.port, &to_update.protocol)
+    }
+    fn validate_post_batch(db: &mut SqliteConnection, new_object: &NewServiceEndpoint, batch: &[NewServiceEndpoint]) -> anyhow::Result<()> {
+        let host = ServiceInstance::db_fetch(db, new_object.instance)?.host;
+        let address = new_object.ip.parse::<IpAddr>()?;
+        for (index, other) in batch.iter().enumerate() {
+            if other.port != new_object.port || other.protocol != new_object.protocol || !other.ip.parse::<IpAddr>().is_ok_and(|other| overlaps(address, other)) {
+                continue;
+            }
+            if ServiceInstance::db_fetch(db, other.instance)?.host == host {
+                bail!("{} port {}/{} is already claimed on this host by item {} of the request", other.ip, other.port, other.protocol, index);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use async_trait::async_trait;
+use rest::{RestCollection, Rest, Crud};
+use rest_derive::{Rest, Crud};
+use serde::{Deserialize, Serialize};
+use diesel::{self, *};
+use crate::{authorization::{self, Role, Scope}, schema, secrets::Disclosure, variables::{self, Owner}};
+use crate::skytree::{host::Host, service::Service, service_endpoint::{self, ServiceEndpoint}, service_instance_relation::ServiceInstanceRelation};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Rest, Crud)]
+#[diesel(table_name = schema::service_instance)]
+#[rest(post=false,pre=false,context=true,authorize=true,validate=true,audit=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
+#[crud(table_name=schema::service_instance, connection=diesel::sqlite::SqliteConnection)]
+pub struct ServiceInstance {
+    pub id: i32,
+    pub service: i32,
+    pub host: i32,
+    pub ip: String,
+    pub name: String,
+}
+
+impl ServiceInstance {
+    pub fn of_host(db: &mut SqliteConnection, host: i32) -> anyhow::Result<Vec<ServiceInstance>> {
+        Ok(schema::service_instance::table
+            .filter(schema::service_instance::dsl::host.eq(host))
+            .order(schema::service_instance::dsl::name)
+            .load::<ServiceInstance>(db)?)
+    }
+    pub fn of_service(db: &mut SqliteConnection, service: i32) -> anyhow::Result<Vec<ServiceInstance>> {
+        Ok(schema::service_instance::table
+            .filter(schema::service_instance::dsl::service.eq(service))
+            .order(schema::service_instance::dsl::name)
+            .load::<ServiceInstance>(db)?)
+    }
+}
+
+impl rest::RestContext<ServiceInstance, SqliteConnection> for ServiceInstance {
+    fn context(db: &mut SqliteConnection, object: &ServiceInstance) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "host": Host::db_fetch(db, object.host)?,
+            "service": Service::db_fetch(db, object.service)?,
+            "endpoints": ServiceEndpoint::of_instance(db, object.id)?,
+            "relations_from": ServiceInstanceRelation::from_instance(db, object.id)?,
+            "relations_to": ServiceInstanceRelation::to_instance(db, object.id)?,
+            "variables": variables::values(db, Owner::ServiceInstance(object.id), &Disclosure::Redacted)?,
+        }))
+    }
+}
+
+/// Service instances are edited by the editors of their host
+impl rest::RestAuthorize<ServiceInstance, NewServiceInstance, crate::AppData<'static>, SqliteConnection> for ServiceInstance {
+    fn authorize_list(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, new_object: &NewServiceInstance) -> anyhow::Result<()> {
+        let scope = Scope::host(db, new_object.host)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+    fn authorize_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32, to_update: &ServiceInstance) -> anyhow::Result<()> {
+        let current = ServiceInstance::db_fetch(db, id)?;
+        let scope = Scope::host(db, current.host)?;
+        authorization::require(db, principal, Role::Editor, scope)?;
+        let scope = Scope::host(db, to_update.host)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+    fn authorize_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<()> {
+        let current = ServiceInstance::db_fetch(db, id)?;
+        let scope = Scope::host(db, current.host)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+}
+
+/// Moving an instance to another host takes its endpoints along, which must not clash with those already there
+impl rest::RestValidate<ServiceInstance, NewServiceInstance, SqliteConnection> for ServiceInstance {
+    fn validate_post(_db: &mut SqliteConnection, _new_object: &NewServiceInstance) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn validate_put(db: &mut SqliteConnection, id: i32, to_update: &ServiceInstance) -> anyhow::Result<()> {
+        if to_update.host != ServiceInstance::db_fetch(db, id)?.host {
+            for endpoint in ServiceEndpoint::of_instance(db, id)? {
+                service_endpoint::check_unique(db, Some(endpoint.id), to_update.host, &endpoint.ip, endpoint.port, &endpoint.protocol)?;
+            }
+        }
+        Ok(())
+    }
+    fn validate_post_batch(_db: &mut SqliteConnection, _new_object: &NewServiceInstance, _batch: &[NewServiceInstance]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
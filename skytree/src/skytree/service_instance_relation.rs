@@ -0,0 +1,100 @@
+use async_trait::async_trait;
+use anyhow::Context;
+use rest::{RestCollection, Rest, Crud, DbFactory};
+use rest_derive::{Rest, Crud};
+use serde::{Deserialize, Serialize};
+use diesel::{self, *};
+use crate::{authorization::{self, Role, Scope}, schema};
+use crate::skytree::{service_instance::ServiceInstance, service_relation::checked_kind};
+
+/// Relation between two service instances, such as a web server depending on one database instance
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Rest, Crud)]
+#[diesel(table_name = schema::service_instance_relation)]
+#[rest(post=false,pre=true,context=true,authorize=true,audit=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
+#[crud(table_name=schema::service_instance_relation, search_field=kind, connection=diesel::sqlite::SqliteConnection)]
+pub struct ServiceInstanceRelation {
+    pub id: i32,
+    /// depends_on, load_balances or replicates_to
+    pub kind: String,
+    pub source: i32,
+    pub target: i32,
+}
+
+impl ServiceInstanceRelation {
+    /// Relations from `instance`
+    pub fn from_instance(db: &mut SqliteConnection, instance: i32) -> anyhow::Result<Vec<ServiceInstanceRelation>> {
+        Ok(schema::service_instance_relation::table
+            .filter(schema::service_instance_relation::dsl::source.eq(instance))
+            .order((schema::service_instance_relation::dsl::kind, schema::service_instance_relation::dsl::target))
+            .load::<ServiceInstanceRelation>(db)?)
+    }
+    /// Relations to `instance`
+    pub fn to_instance(db: &mut SqliteConnection, instance: i32) -> anyhow::Result<Vec<ServiceInstanceRelation>> {
+        Ok(schema::service_instance_relation::table
+            .filter(schema::service_instance_relation::dsl::target.eq(instance))
+            .order((schema::service_instance_relation::dsl::kind, schema::service_instance_relation::dsl::source))
+            .load::<ServiceInstanceRelation>(db)?)
+    }
+}
+
+impl rest::RestContext<ServiceInstanceRelation, SqliteConnection> for ServiceInstanceRelation {
+    fn context(db: &mut SqliteConnection, object: &ServiceInstanceRelation) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "source": ServiceInstance::db_fetch(db, object.source)?,
+            "target": ServiceInstance::db_fetch(db, object.target)?,
+        }))
+    }
+}
+
+/// Relations link two existing, distinct service instances
+fn check(app_data: &crate::AppData<'static>, kind: &str, source: i32, target: i32) -> anyhow::Result<String> {
+    let kind = checked_kind(kind, source, target)?;
+    let mut db = app_data.db()?;
+    ServiceInstance::db_fetch(&mut db, source).with_context(|| format!("Unknown service instance {}", source))?;
+    ServiceInstance::db_fetch(&mut db, target).with_context(|| format!("Unknown service instance {}", target))?;
+    Ok(kind)
+}
+
+impl rest::RestPre<ServiceInstanceRelation, NewServiceInstanceRelation, crate::AppData<'static>> for ServiceInstanceRelation {
+    fn pre_post(app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, new_object: &NewServiceInstanceRelation) -> anyhow::Result<NewServiceInstanceRelation> {
+        Ok(NewServiceInstanceRelation { kind: check(app_data, &new_object.kind, new_object.source, new_object.target)?, ..new_object.clone() })
+    }
+    fn pre_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+    fn pre_put(app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32, to_update: &ServiceInstanceRelation) -> anyhow::Result<(i32, ServiceInstanceRelation)> {
+        Ok((id, ServiceInstanceRelation { kind: check(app_data, &to_update.kind, to_update.source, to_update.target)?, ..to_update.clone() }))
+    }
+    fn pre_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+}
+
+/// Relations are edited by the editors of the host of their source instance, the instance whose configuration they affect
+impl rest::RestAuthorize<ServiceInstanceRelation, NewServiceInstanceRelation, crate::AppData<'static>, SqliteConnection> for ServiceInstanceRelation {
+    fn authorize_list(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, new_object: &NewServiceInstanceRelation) -> anyhow::Result<()> {
+        let host = ServiceInstance::db_fetch(db, new_object.source)?.host;
+        let scope = Scope::host(db, host)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+    fn authorize_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32, to_update: &ServiceInstanceRelation) -> anyhow::Result<()> {
+        for source in [ServiceInstanceRelation::db_fetch(db, id)?.source, to_update.source] {
+            let host = ServiceInstance::db_fetch(db, source)?.host;
+            let scope = Scope::host(db, host)?;
+            authorization::require(db, principal, Role::Editor, scope)?;
+        }
+        Ok(())
+    }
+    fn authorize_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<()> {
+        let source = ServiceInstanceRelation::db_fetch(db, id)?.source;
+        let host = ServiceInstance::db_fetch(db, source)?.host;
+        let scope = Scope::host(db, host)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+}
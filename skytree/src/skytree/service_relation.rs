@@ -0,0 +1,136 @@
+use std::fmt;
+use async_trait::async_trait;
+use anyhow::{bail, Context};
+use rest::{RestCollection, Rest, Crud, DbFactory};
+use rest_derive::{Rest, Crud};
+use serde::{Deserialize, Serialize};
+use diesel::{self, *};
+use crate::{authorization::{self, Role, Scope}, schema};
+use crate::skytree::service::Service;
+
+/// Kind of a relation between services, or between service instances, from the source to the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationKind {
+    /// The source uses the target, such as a web service its database
+    DependsOn,
+    /// The source distributes requests over the target
+    LoadBalances,
+    /// The source copies its data to the target
+    ReplicatesTo,
+}
+impl RelationKind {
+    pub const ALL: [RelationKind; 3] = [RelationKind::DependsOn, RelationKind::LoadBalances, RelationKind::ReplicatesTo];
+}
+impl std::str::FromStr for RelationKind {
+    type Err = anyhow::Error;
+    /// Parses `depends_on`, or `depends-on` as written in prose
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind.trim().replace('-', "_").as_str() {
+            "depends_on" => Ok(RelationKind::DependsOn),
+            "load_balances" => Ok(RelationKind::LoadBalances),
+            "replicates_to" => Ok(RelationKind::ReplicatesTo),
+            _ => bail!("Unknown relation kind {}, expected depends_on, load_balances or replicates_to", kind),
+        }
+    }
+}
+impl fmt::Display for RelationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RelationKind::DependsOn => "depends_on",
+            RelationKind::LoadBalances => "load_balances",
+            RelationKind::ReplicatesTo => "replicates_to",
+        })
+    }
+}
+
+/// Checks the kind and ends of a relation, returning the kind in its stored form
+pub fn checked_kind(kind: &str, source: i32, target: i32) -> anyhow::Result<String> {
+    let kind = kind.parse::<RelationKind>()?;
+    if source == target {
+        bail!("A relation cannot link {} to itself", source);
+    }
+    Ok(kind.to_string())
+}
+
+/// Relation between two services. It applies to every instance of the services
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Rest, Crud)]
+#[diesel(table_name = schema::service_relation)]
+#[rest(post=false,pre=true,context=true,authorize=true,audit=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
+#[crud(table_name=schema::service_relation, search_field=kind, connection=diesel::sqlite::SqliteConnection)]
+pub struct ServiceRelation {
+    pub id: i32,
+    /// depends_on, load_balances or replicates_to
+    pub kind: String,
+    pub source: i32,
+    pub target: i32,
+}
+
+impl ServiceRelation {
+    /// Relations from `service`
+    pub fn from_service(db: &mut SqliteConnection, service: i32) -> anyhow::Result<Vec<ServiceRelation>> {
+        Ok(schema::service_relation::table
+            .filter(schema::service_relation::dsl::source.eq(service))
+            .order((schema::service_relation::dsl::kind, schema::service_relation::dsl::target))
+            .load::<ServiceRelation>(db)?)
+    }
+    /// Relations to `service`
+    pub fn to_service(db: &mut SqliteConnection, service: i32) -> anyhow::Result<Vec<ServiceRelation>> {
+        Ok(schema::service_relation::table
+            .filter(schema::service_relation::dsl::target.eq(service))
+            .order((schema::service_relation::dsl::kind, schema::service_relation::dsl::source))
+            .load::<ServiceRelation>(db)?)
+    }
+}
+
+impl rest::RestContext<ServiceRelation, SqliteConnection> for ServiceRelation {
+    fn context(db: &mut SqliteConnection, object: &ServiceRelation) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "source": Service::db_fetch(db, object.source)?,
+            "target": Service::db_fetch(db, object.target)?,
+        }))
+    }
+}
+
+/// Relations link two existing, distinct services
+fn check(app_data: &crate::AppData<'static>, kind: &str, source: i32, target: i32) -> anyhow::Result<String> {
+    let kind = checked_kind(kind, source, target)?;
+    let mut db = app_data.db()?;
+    Service::db_fetch(&mut db, source).with_context(|| format!("Unknown service {}", source))?;
+    Service::db_fetch(&mut db, target).with_context(|| format!("Unknown service {}", target))?;
+    Ok(kind)
+}
+
+impl rest::RestPre<ServiceRelation, NewServiceRelation, crate::AppData<'static>> for ServiceRelation {
+    fn pre_post(app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, new_object: &NewServiceRelation) -> anyhow::Result<NewServiceRelation> {
+        Ok(NewServiceRelation { kind: check(app_data, &new_object.kind, new_object.source, new_object.target)?, ..new_object.clone() })
+    }
+    fn pre_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+    fn pre_put(app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32, to_update: &ServiceRelation) -> anyhow::Result<(i32, ServiceRelation)> {
+        Ok((id, ServiceRelation { kind: check(app_data, &to_update.kind, to_update.source, to_update.target)?, ..to_update.clone() }))
+    }
+    fn pre_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+}
+
+/// Services are outside the group hierarchy, so relating them takes a global role
+impl rest::RestAuthorize<ServiceRelation, NewServiceRelation, crate::AppData<'static>, SqliteConnection> for ServiceRelation {
+    fn authorize_list(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _new_object: &NewServiceRelation) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)
+    }
+    fn authorize_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32, _to_update: &ServiceRelation) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)
+    }
+    fn authorize_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)
+    }
+}
@@ -0,0 +1,157 @@
+use async_trait::async_trait;
+use rest::{RestCollection, Rest, Crud};
+use rest_derive::{Rest, Crud};
+use serde::{Deserialize, Serialize};
+use diesel::{self, *};
+use crate::{authorization::{self, Role, Scope}, schema, secrets::SecretKey, value_schema::{self, FieldError}, variables};
+
+/// How values of a variable set at several levels combine, like Ansible's `hash_behaviour`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashBehaviour {
+    /// The most specific value wins
+    Replace,
+    /// Dictionaries are merged recursively, the most specific value winning on each key. Other values are replaced
+    Merge,
+}
+impl std::str::FromStr for HashBehaviour {
+    type Err = anyhow::Error;
+    fn from_str(hash_behaviour: &str) -> Result<Self, Self::Err> {
+        match hash_behaviour {
+            "replace" => Ok(HashBehaviour::Replace),
+            "merge" => Ok(HashBehaviour::Merge),
+            _ => anyhow::bail!("Unknown hash_behaviour {}, expected replace or merge", hash_behaviour)
+        }
+    }
+}
+impl HashBehaviour {
+    /// Combines `value`, set at a more specific level, into `base`
+    pub fn combine(self, base: &mut serde_json::Value, value: serde_json::Value) {
+        match (self, base, value) {
+            (HashBehaviour::Merge, serde_json::Value::Object(base), serde_json::Value::Object(value)) => {
+                for (key, value) in value {
+                    match base.get_mut(&key) {
+                        Some(base) => self.combine(base, value),
+                        None => { base.insert(key, value); }
+                    }
+                }
+            },
+            (_, base, value) => *base = value,
+        }
+    }
+}
+
+pub fn default_hash_behaviour() -> String {
+    "replace".to_string()
+}
+
+/// (De)serializes a JSON document stored as text as the document itself. Blank strings, as sent by forms, are no document
+mod json_text {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_json::Value;
+
+    pub fn serialize<S: Serializer>(text: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+        text.as_ref()
+            .map(|text| serde_json::from_str::<Value>(text).unwrap_or_else(|_| Value::String(text.clone())))
+            .serialize(serializer)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+        Ok(match Value::deserialize(deserializer)? {
+            Value::Null => None,
+            Value::String(text) if text.trim().is_empty() => None,
+            Value::String(text) => Some(text),
+            value => Some(value.to_string()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Rest, Crud)]
+#[diesel(table_name = schema::variable, treat_none_as_null = true)]
+#[rest(post=false,pre=true,authorize=true,cascade=true,audit=true,app_data=crate::AppData<'static>,connection=diesel::sqlite::SqliteConnection)]
+#[crud(table_name=schema::variable, connection=diesel::sqlite::SqliteConnection)]
+pub struct Variable {
+    pub id: i32,
+    pub name: Option<String>,
+    #[serde(default = "default_hash_behaviour")]
+    pub hash_behaviour: String,
+    /// JSON Schema values must match, see [crate::value_schema]
+    #[serde(default, with = "json_text")]
+    pub value_schema: Option<String>,
+    /// Values are encrypted at rest and redacted in output
+    #[serde(default)]
+    pub secret: bool,
+}
+impl Variable {
+    pub fn hash_behaviour(&self) -> HashBehaviour {
+        self.hash_behaviour.parse().unwrap_or(HashBehaviour::Replace)
+    }
+    /// Violations of the schema of the variable by `value`
+    pub fn validate(&self, value: &serde_json::Value) -> anyhow::Result<Vec<FieldError>> {
+        Ok(match &self.value_schema {
+            Some(schema) => value_schema::validate(&serde_json::from_str(schema)?, value),
+            None => Vec::new(),
+        })
+    }
+}
+
+/// Checks the hash behaviour and schema of a variable
+fn check(hash_behaviour: &str, schema: Option<&String>) -> anyhow::Result<()> {
+    hash_behaviour.parse::<HashBehaviour>()?;
+    if let Some(schema) = schema {
+        let schema = serde_json::from_str(schema).map_err(|err| anyhow::anyhow!("Invalid value_schema: {}", err))?;
+        value_schema::check(&schema)?;
+    }
+    Ok(())
+}
+
+impl rest::RestPre<Variable, NewVariable, crate::AppData<'static>> for Variable {
+    fn pre_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, new_object: &NewVariable) -> anyhow::Result<NewVariable> {
+        check(&new_object.hash_behaviour, new_object.value_schema.as_ref())?;
+        Ok(new_object.clone())
+    }
+    fn pre_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+    fn pre_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32, to_update: &Variable) -> anyhow::Result<(i32, Variable)> {
+        check(&to_update.hash_behaviour, to_update.value_schema.as_ref())?;
+        Ok((id, to_update.clone()))
+    }
+    fn pre_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, _principal: Option<&rest::Principal>, id: i32) -> anyhow::Result<i32> {
+        Ok(id)
+    }
+}
+
+/// Variables are outside the group hierarchy, so editing them takes a global role
+impl rest::RestAuthorize<Variable, NewVariable, crate::AppData<'static>, SqliteConnection> for Variable {
+    fn authorize_list(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    fn authorize_post(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _new_object: &NewVariable) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)
+    }
+    fn authorize_get(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require_viewer(db, principal)
+    }
+    /// Making a variable no longer secret reveals its values, which takes a global admin
+    fn authorize_put(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, id: i32, to_update: &Variable) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)?;
+        if !to_update.secret && Variable::db_fetch(db, id)?.secret {
+            authorization::require(db, principal, Role::Admin, Scope::Global)?;
+        }
+        Ok(())
+    }
+    fn authorize_delete(_app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, _id: i32) -> anyhow::Result<()> {
+        authorization::require(db, principal, Role::Editor, Scope::Global)
+    }
+}
+
+/// Changing `secret` reseals the stored values, each change audited like a value set through the API
+impl rest::RestCascade<Variable, crate::AppData<'static>, SqliteConnection> for Variable {
+    fn cascade_put(app_data: &actix_web::web::Data<crate::AppData<'static>>, db: &mut SqliteConnection, principal: Option<&rest::Principal>, request_id: &rest::RequestId, before: &Variable, after: &Variable) -> anyhow::Result<()> {
+        if before.secret != after.secret {
+            let key = SecretKey::load(&app_data.config())?;
+            variables::reseal_variable(app_data.get_ref(), db, principal, request_id, &key, after.id, after.secret)?;
+        }
+        Ok(())
+    }
+}
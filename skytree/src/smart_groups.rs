@@ -0,0 +1,394 @@
+use actix_web::{http::StatusCode, web};
+use anyhow::{anyhow, bail};
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use regex::Regex;
+use rest::{Crud, DbFactory, RequestPrincipal};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use crate::{AppData, authorization, facts, schema, secrets::Disclosure, skytree::{host::Host, host_group::HostGroup}, variables};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// `==`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `~`, the value is a regular expression searched for
+    Match,
+    /// `!~`
+    NotMatch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Operator(Operator),
+    Word(String),
+    Quoted(String),
+}
+
+/// Splits a rule into tokens. Quoted strings keep their backslashes, except before their own quote
+fn tokens(rule: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = rule.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => { chars.next(); },
+            '(' => { chars.next(); tokens.push(Token::Open) },
+            ')' => { chars.next(); tokens.push(Token::Close) },
+            '\'' | '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(escaped) if escaped == c => text.push(escaped),
+                            Some(escaped) => { text.push('\\'); text.push(escaped) },
+                            None => bail!("Unterminated string in rule"),
+                        },
+                        Some(end) if end == c => break,
+                        Some(other) => text.push(other),
+                        None => bail!("Unterminated string in rule"),
+                    }
+                }
+                tokens.push(Token::Quoted(text));
+            },
+            '=' | '!' | '~' => {
+                let mut operator = String::new();
+                while let Some(c) = chars.next_if(|c| matches!(c, '=' | '!' | '~')) {
+                    operator.push(c);
+                }
+                tokens.push(Token::Operator(match operator.as_str() {
+                    "==" => Operator::Equal,
+                    "!=" => Operator::NotEqual,
+                    "~" => Operator::Match,
+                    "!~" => Operator::NotMatch,
+                    operator => bail!("Unknown operator {} in rule, expected ==, !=, ~ or !~", operator),
+                }));
+            },
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | '\'' | '"' | '=' | '!' | '~')) {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+/// Rule computing the members of a smart group, such as `name ~ '^db-' and env == prod`.
+///
+/// Comparisons are combined with `and`, `or`, `not` and parentheses. Their left side is `name`, the host name, or the dotted path of
+/// a variable of the host. String values are compared as they are and other values as JSON text, so `vcpus == 4` works as expected
+#[derive(Debug, Clone)]
+pub enum Rule {
+    And(Box<Rule>, Box<Rule>),
+    Or(Box<Rule>, Box<Rule>),
+    Not(Box<Rule>),
+    Compare { path: String, operator: Operator, value: String, regex: Option<Regex> },
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.tokens.get(self.position), Some(Token::Word(word)) if word == keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+    fn or(&mut self) -> anyhow::Result<Rule> {
+        let mut rule = self.and()?;
+        while self.keyword("or") {
+            rule = Rule::Or(Box::new(rule), Box::new(self.and()?));
+        }
+        Ok(rule)
+    }
+    fn and(&mut self) -> anyhow::Result<Rule> {
+        let mut rule = self.not()?;
+        while self.keyword("and") {
+            rule = Rule::And(Box::new(rule), Box::new(self.not()?));
+        }
+        Ok(rule)
+    }
+    fn not(&mut self) -> anyhow::Result<Rule> {
+        match self.keyword("not") {
+            true => Ok(Rule::Not(Box::new(self.not()?))),
+            false => self.comparison(),
+        }
+    }
+    fn comparison(&mut self) -> anyhow::Result<Rule> {
+        match self.next() {
+            Some(Token::Open) => {
+                let rule = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(rule),
+                    _ => bail!("Missing ) in rule"),
+                }
+            },
+            Some(Token::Word(path)) => {
+                let Some(Token::Operator(operator)) = self.next() else { bail!("Expected ==, !=, ~ or !~ after {} in rule", path) };
+                let value = match self.next() {
+                    Some(Token::Word(value)) | Some(Token::Quoted(value)) => value,
+                    _ => bail!("Expected a value after {} in rule", path),
+                };
+                let regex = match operator {
+                    Operator::Match | Operator::NotMatch => Some(Regex::new(&value).map_err(|err| anyhow!("Invalid regular expression {} in rule: {}", value, err))?),
+                    Operator::Equal | Operator::NotEqual => None,
+                };
+                Ok(Rule::Compare { path, operator, value, regex })
+            },
+            Some(token) => bail!("Unexpected {:?} in rule, expected a comparison", token),
+            None => bail!("Incomplete rule, expected a comparison"),
+        }
+    }
+}
+
+impl std::str::FromStr for Rule {
+    type Err = anyhow::Error;
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokens(rule)?, position: 0 };
+        let parsed = parser.or()?;
+        if let Some(token) = parser.next() {
+            bail!("Unexpected {:?} in rule", token);
+        }
+        Ok(parsed)
+    }
+}
+
+/// Variable at a dotted path, such as `ansible_default_ipv4.address`
+fn lookup<'a>(variables: &'a Map<String, Value>, path: &str) -> Option<&'a Value> {
+    if let Some(value) = variables.get(path) {
+        return Some(value);
+    }
+    let mut parts = path.split('.');
+    let first = variables.get(parts.next()?);
+    parts.fold(first, |value, part| value.and_then(|value| value.get(part)))
+}
+
+impl Rule {
+    /// Whether a host, with its variables, matches the rule
+    pub fn matches(&self, host: &Host, variables: &Map<String, Value>) -> bool {
+        match self {
+            Rule::And(left, right) => left.matches(host, variables) && right.matches(host, variables),
+            Rule::Or(left, right) => left.matches(host, variables) || right.matches(host, variables),
+            Rule::Not(rule) => !rule.matches(host, variables),
+            Rule::Compare { path, operator, value, regex } => {
+                let actual = match path.as_str() {
+                    "name" => Some(host.name.clone()),
+                    path => lookup(variables, path).map(|actual| match actual {
+                        Value::String(text) => text.clone(),
+                        actual => actual.to_string(),
+                    }),
+                };
+                let matched = || actual.as_deref().is_some_and(|actual| regex.as_ref().is_some_and(|regex| regex.is_match(actual)));
+                match operator {
+                    Operator::Equal => actual.as_deref() == Some(value.as_str()),
+                    Operator::NotEqual => actual.as_deref() != Some(value.as_str()),
+                    Operator::Match => matched(),
+                    Operator::NotMatch => !matched(),
+                }
+            },
+        }
+    }
+}
+
+/// Groups with a rule, with their parsed rule
+fn smart_groups(db: &mut SqliteConnection) -> anyhow::Result<Vec<(HostGroup, Rule)>> {
+    schema::host_group::table
+        .filter(schema::host_group::rule.is_not_null())
+        .order(schema::host_group::id)
+        .load::<HostGroup>(db)?
+        .into_iter()
+        .map(|group| {
+            let rule = group.rule.as_deref().unwrap_or_default().parse::<Rule>().map_err(|err| anyhow!("Host group {}: {}", group.id, err))?;
+            Ok((group, rule))
+        })
+        .collect()
+}
+
+/// Variables rules are matched against: those of the host through its explicit groups, and its facts. Secret values are redacted
+fn rule_variables(db: &mut SqliteConnection, host: i32) -> anyhow::Result<Map<String, Value>> {
+    let mut variables = variables::explicit(db, host, &Disclosure::Redacted)?;
+    facts::overlay(&mut variables, facts::stored(db, host)?.facts);
+    Ok(variables)
+}
+
+/// Smart groups whose rule matches `host`
+pub fn groups_of(db: &mut SqliteConnection, host: i32) -> anyhow::Result<Vec<HostGroup>> {
+    let groups = smart_groups(db)?;
+    if groups.is_empty() {
+        return Ok(Vec::new());
+    }
+    let host = Host::db_fetch(db, host)?;
+    let variables = rule_variables(db, host.id)?;
+    Ok(groups.into_iter().filter(|(_, rule)| rule.matches(&host, &variables)).map(|(group, _)| group).collect())
+}
+
+/// Hosts matching `rule`, ordered by name
+pub fn members(db: &mut SqliteConnection, rule: &Rule) -> anyhow::Result<Vec<Host>> {
+    let mut members = Vec::new();
+    for host in schema::host::table.order((schema::host::name, schema::host::id)).load::<Host>(db)? {
+        if rule.matches(&host, &rule_variables(db, host.id)?) {
+            members.push(host);
+        }
+    }
+    Ok(members)
+}
+
+/// (host, group) pairs of the members of every smart group, computed from their rules
+pub fn memberships(db: &mut SqliteConnection) -> anyhow::Result<Vec<(i32, i32)>> {
+    let groups = smart_groups(db)?;
+    if groups.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut memberships = Vec::new();
+    for host in schema::host::table.load::<Host>(db)? {
+        let variables = rule_variables(db, host.id)?;
+        memberships.extend(groups.iter().filter(|(_, rule)| rule.matches(&host, &variables)).map(|(group, _)| (host.id, group.id)));
+    }
+    Ok(memberships)
+}
+
+/// Checks the syntax of a rule
+pub fn check(rule: &str) -> anyhow::Result<()> {
+    rule.parse::<Rule>().map(|_| ())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewQuery {
+    pub rule: String,
+}
+
+/// `GET /host_groups/preview?rule=`: hosts currently matching a rule, before saving it on a group
+pub async fn preview(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, web::Query(query): web::Query<PreviewQuery>) -> Responder<AppData<'static>> {
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        match query.rule.parse::<Rule>() {
+            Ok(rule) => members(&mut db, &rule).into(),
+            Err(err) => Responder::<AppData<'static>>::from(err).with_status_code(StatusCode::BAD_REQUEST),
+        }
+    }).await)
+        .with_template("host/list")
+        .with_error_template("host_group/error")
+}
+
+/// `GET /host_group/{id}/preview`: hosts currently matching the rule of a smart group
+pub async fn group_preview(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, id: web::Path<i32>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        HostGroup::db_fetch(&mut db, id)
+            .and_then(|group| group.rule.ok_or_else(|| anyhow!("Host group {} has no rule", id)))
+            .and_then(|rule| rule.parse::<Rule>())
+            .and_then(|rule| members(&mut db, &rule))
+            .into()
+    }).await)
+        .with_template("host/list")
+        .with_error_template("host_group/error")
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/host_groups/preview", web::get().to(preview))
+        .route("/host_group/{id}/preview", web::get().to(group_preview));
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::connection::SimpleConnection;
+    use super::*;
+    use crate::testing;
+
+    fn host(name: &str) -> Host {
+        Host { id: 1, name: name.to_string() }
+    }
+
+    #[test]
+    fn tokenizes_rules() {
+        assert_eq!(tokens(r#"(env=="prod" or name !~ '^db\.\'x') and not vcpus!=4"#).unwrap(), vec![
+            Token::Open,
+            Token::Word("env".to_string()),
+            Token::Operator(Operator::Equal),
+            Token::Quoted("prod".to_string()),
+            Token::Word("or".to_string()),
+            Token::Word("name".to_string()),
+            Token::Operator(Operator::NotMatch),
+            Token::Quoted(r"^db\.'x".to_string()),
+            Token::Close,
+            Token::Word("and".to_string()),
+            Token::Word("not".to_string()),
+            Token::Word("vcpus".to_string()),
+            Token::Operator(Operator::NotEqual),
+            Token::Word("4".to_string()),
+        ]);
+        assert!(tokens("env = prod").is_err());
+        assert!(tokens("env == 'prod").is_err());
+    }
+
+    #[test]
+    fn parses_precedence() {
+        let rule = "env == prod or env == staging and not name ~ '^db'".parse::<Rule>().unwrap();
+        let Rule::Or(left, right) = &rule else { panic!("expected or at the top, got {:?}", rule) };
+        assert!(matches!(left.as_ref(), Rule::Compare { path, operator: Operator::Equal, value, .. } if path == "env" && value == "prod"));
+        assert!(matches!(right.as_ref(), Rule::And(_, not) if matches!(not.as_ref(), Rule::Not(_))));
+
+        let variables = serde_json::json!({ "env": "staging", "vcpus": 4, "ansible_lsb": { "codename": "bookworm" } });
+        let variables = variables.as_object().unwrap();
+        assert!(rule.matches(&host("web1"), variables));
+        assert!(!rule.matches(&host("db1"), variables));
+        let matches = |rule: &str, name: &str| rule.parse::<Rule>().unwrap().matches(&host(name), variables);
+        assert!(matches("(env == prod or env == staging) and vcpus == 4", "db1"));
+        assert!(matches("ansible_lsb.codename == bookworm", "db1"));
+        assert!(matches("missing != x", "db1"));
+        assert!(!matches("missing ~ '.*'", "db1"));
+    }
+
+    #[test]
+    fn refuses_invalid_rules() {
+        for rule in ["", "env", "env ==", "(env == prod", "env == prod)", "env == prod and", "name ~ '('", "== prod"] {
+            assert!(rule.parse::<Rule>().is_err(), "{:?} was accepted", rule);
+        }
+    }
+
+    #[test]
+    fn rules_match_facts() {
+        let mut db = testing::db();
+        db.batch_execute(r#"
+            INSERT INTO host(id, name) VALUES (1, 'db1'), (2, 'db2');
+            INSERT INTO host_group(id, name, rule) VALUES (1, 'bookworm', 'ansible_distribution == Debian and ansible_lsb.codename == bookworm');
+            INSERT INTO host_fact(host, path, value, gathered) VALUES (1, 'ansible_distribution', '"Debian"', 0), (1, 'ansible_lsb.codename', '"bookworm"', 0), (2, 'ansible_distribution', '"Debian"', 0), (2, 'ansible_lsb.codename', '"trixie"', 0);
+        "#).unwrap();
+        let rule = "ansible_distribution == Debian and ansible_lsb.codename == bookworm".parse::<Rule>().unwrap();
+        assert_eq!(members(&mut db, &rule).unwrap().into_iter().map(|host| host.name).collect::<Vec<String>>(), vec!["db1"]);
+        assert_eq!(memberships(&mut db).unwrap(), vec![(1, 1)]);
+        assert_eq!(groups_of(&mut db, 1).unwrap().len(), 1);
+        assert!(groups_of(&mut db, 2).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, HashSet};
+use actix_web::web;
+use anyhow::{anyhow, bail, Context};
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use rest::{Crud, DbFactory, IfMatch, JsonOrForm, OperationError, Principal, RequestId, RequestPrincipal, RestOperation};
+use serde::Deserialize;
+use crate::{AppData, authorization::{self, Role, Scope}, memberships, schema, variables::{self, Owner}};
+use crate::skytree::host_group::{HostGroup, NewHostGroup};
+
+#[derive(Debug, Deserialize)]
+pub struct MoveForm {
+    /// New parent of the group, the root when `None`
+    #[serde(default)]
+    pub parent: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CopyForm {
+    /// Prepended to the names of the copied groups
+    pub prefix: String,
+    /// Parent of the copy, the root when `None`
+    #[serde(default)]
+    pub parent: Option<i32>,
+}
+
+/// Groups of the subtree rooted at `root`, parents before their children
+fn subtree(db: &mut SqliteConnection, root: HostGroup) -> anyhow::Result<Vec<HostGroup>> {
+    let mut visited = HashSet::new();
+    let mut groups = vec![root];
+    let mut next = 0;
+    while let Some(group) = groups.get(next) {
+        let id = group.id;
+        if !visited.insert(id) {
+            bail!("Cycle detected in host group hierarchy at group {}", id);
+        }
+        groups.extend(HostGroup::children(db, id)?);
+        next += 1;
+    }
+    Ok(groups)
+}
+
+/// Those of `names` already taken, by existing groups or by other names in the list
+fn collisions(db: &mut SqliteConnection, names: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut taken = schema::host_group::table
+        .filter(schema::host_group::name.eq_any(names))
+        .select(schema::host_group::name)
+        .load::<String>(db)?;
+    let mut seen = HashSet::new();
+    taken.extend(names.iter().filter(|name| !seen.insert(*name)).cloned());
+    taken.sort();
+    taken.dedup();
+    Ok(taken)
+}
+
+/// Moves group `id`, with its descendants, memberships and variables, under `parent`
+fn move_group(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, if_match: &IfMatch, id: i32, parent: Option<i32>) -> Result<HostGroup, OperationError> {
+    let group = HostGroup::db_fetch(db, id).with_context(|| format!("Unknown host group {}", id))?;
+    HostGroup::update_if_match(app_data, db, principal, request_id, if_match, id, HostGroup { parent, ..group })
+}
+
+/// Copies group `id` and its descendants under `parent`, with their memberships and variables, prefixing their names
+fn copy_group(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, id: i32, form: &CopyForm) -> Result<HostGroup, OperationError> {
+    let prefix = form.prefix.trim();
+    if prefix.is_empty() {
+        return Err(OperationError::Failed(anyhow!("A prefix is required to copy host group {}", id)));
+    }
+    let root = HostGroup::db_fetch(db, id).with_context(|| format!("Unknown host group {}", id))?;
+    authorization::require(db, principal, Role::Editor, Scope::group(Some(id))).map_err(OperationError::Forbidden)?;
+    if let Some(parent) = form.parent {
+        HostGroup::db_fetch(db, parent).with_context(|| format!("Unknown host group {}", parent))?;
+    }
+    let groups = subtree(db, root)?;
+    let names = groups.iter().map(|group| format!("{}{}", prefix, group.name)).collect::<Vec<String>>();
+    let taken = collisions(db, &names)?;
+    if !taken.is_empty() {
+        return Err(OperationError::Failed(anyhow!("Host group names already taken: {}", taken.join(", "))));
+    }
+    let mut copies = HashMap::<i32, i32>::new();
+    let mut copied_root = None;
+    for (group, name) in groups.into_iter().zip(names) {
+        let parent = match copies.is_empty() {
+            true => form.parent,
+            false => group.parent.and_then(|parent| copies.get(&parent).copied()),
+        };
+        let copy = HostGroup::create(app_data, db, principal, request_id, NewHostGroup { parent, name, rule: group.rule.clone() })?;
+        copies.insert(group.id, copy.id);
+        for host in HostGroup::hosts(db, group.id)? {
+            memberships::add(app_data.get_ref(), db, principal, request_id, host.id, copy.id)?;
+        }
+        // values are copied as stored, so secrets are never unsealed
+        for value in variables::stored(db, Owner::HostGroup(group.id))? {
+            variables::assign(app_data.get_ref(), db, principal, request_id, Owner::HostGroup(copy.id), value.variable, &value.value)?;
+        }
+        copied_root.get_or_insert(copy);
+    }
+    copied_root.ok_or_else(|| OperationError::Failed(anyhow!("Nothing copied from host group {}", id)))
+}
+
+fn done(result: Result<HostGroup, OperationError>, flash: &str) -> Responder<AppData<'static>> {
+    match result {
+        Ok(group) => {
+            let location = format!("/host_group/{}", group.id);
+            Responder::<AppData<'static>>::from(anyhow::Result::<HostGroup>::Ok(group)).with_redirect(&location, flash)
+        },
+        Err(err) => err.into(),
+    }
+}
+
+/// `GET /host_group/{id}/move`: page with the forms moving and copying a group
+pub async fn form(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, id: web::Path<i32>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        let groups = schema::host_group::table.order(schema::host_group::name).load::<HostGroup>(&mut db).map_err(anyhow::Error::from);
+        match (HostGroup::db_fetch(&mut db, id), groups) {
+            (Ok(group), Ok(groups)) => Responder::<AppData<'static>>::from(anyhow::Result::<HostGroup>::Ok(group))
+                .with_context(serde_json::json!({ "host_groups": groups })),
+            (Err(err), _) | (_, Err(err)) => err.into(),
+        }
+    }).await)
+        .with_template("host_group/move")
+        .with_error_template("host_group/error")
+}
+
+/// `POST /host_group/{id}/move`: moves a group, with everything below and attached to it, under the `parent` group, or to the root. Refuses to move a group below itself
+pub async fn move_subtree(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, if_match: IfMatch, id: web::Path<i32>, JsonOrForm(form): JsonOrForm<MoveForm>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        let result = db.transaction(|db| move_group(&app_data, db, principal.as_ref(), &request_id, &if_match, id, form.parent));
+        done(result, "Host group moved")
+    }).await)
+        .with_template("host_group/show")
+        .with_error_template("host_group/error")
+}
+
+/// `POST /host_group/{id}/copy`: copies a group and its descendants, with their memberships, rules and variables, under the `parent` group or the root, prefixing their names with `prefix`.
+/// Refuses names already taken. Role grants are not copied
+pub async fn copy_subtree(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, id: web::Path<i32>, JsonOrForm(form): JsonOrForm<CopyForm>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        let result = db.transaction(|db| copy_group(&app_data, db, principal.as_ref(), &request_id, id, &form));
+        done(result, "Host group copied")
+    }).await)
+        .with_template("host_group/show")
+        .with_error_template("host_group/error")
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/host_group/{id}/move", web::get().to(form))
+        .route("/host_group/{id}/move", web::post().to(move_subtree))
+        .route("/host_group/{id}/copy", web::post().to(copy_subtree));
+}
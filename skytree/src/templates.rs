@@ -0,0 +1,65 @@
+use std::path::Path;
+use handlebars::Handlebars;
+use rust_embed::RustEmbed;
+use crate::Config;
+
+pub const TEMPLATE_EXTENSION: &str = ".hbs";
+
+/// Default templates, compiled into the binary
+#[derive(RustEmbed)]
+#[folder = "templates/"]
+pub struct DefaultTemplates;
+
+/// Builds the template registry for `config`: strict mode, helpers, built-in templates and `template_dir` overrides
+pub fn handlebars(config: &Config) -> anyhow::Result<Handlebars<'static>> {
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(true);
+    handlebars.set_dev_mode(config.dev_mode);
+    negotiated::helpers::register_helpers(&mut handlebars);
+    register_templates(&mut handlebars, &config.template_dir)?;
+    Ok(handlebars)
+}
+
+/// Registers the built-in templates, then overrides them with any template found in `template_dir`
+pub fn register_templates(handlebars: &mut Handlebars, template_dir: &str) -> anyhow::Result<()> {
+    for file_name in DefaultTemplates::iter() {
+        if let (Some(name), Some(file)) = (file_name.strip_suffix(TEMPLATE_EXTENSION), DefaultTemplates::get(&file_name)) {
+            handlebars.register_template_string(name, String::from_utf8_lossy(file.data.as_ref()))?;
+        }
+    }
+    if Path::new(template_dir).is_dir() {
+        handlebars.register_templates_directory(TEMPLATE_EXTENSION, template_dir)?;
+    } else {
+        log::warn!("Template directory {} not found, using built-in templates only", template_dir);
+    }
+    Ok(())
+}
+
+/// Source of a template, from `template_dir` if overridden there, otherwise from the built-in templates
+pub fn template_source(template_dir: &str, name: &str) -> Option<String> {
+    let file_name = format!("{}{}", name, TEMPLATE_EXTENSION);
+    std::fs::read_to_string(Path::new(template_dir).join(&file_name))
+        .ok()
+        .or_else(|| DefaultTemplates::get(&file_name).map(|file| String::from_utf8_lossy(file.data.as_ref()).to_string()))
+}
+
+/// Writes the built-in templates to `target_dir`, refusing to overwrite existing files unless `overwrite` is set
+pub fn dump_templates(target_dir: &str, overwrite: bool) -> anyhow::Result<Vec<String>> {
+    if !overwrite {
+        if let Some(existing) = DefaultTemplates::iter().map(|file_name| Path::new(target_dir).join(file_name.as_ref())).find(|target| target.exists()) {
+            anyhow::bail!("{} already exists, use --force to overwrite", existing.display());
+        }
+    }
+    let mut written = Vec::new();
+    for file_name in DefaultTemplates::iter() {
+        let target = Path::new(target_dir).join(file_name.as_ref());
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if let Some(file) = DefaultTemplates::get(&file_name) {
+            std::fs::write(&target, file.data.as_ref())?;
+            written.push(target.display().to_string());
+        }
+    }
+    Ok(written)
+}
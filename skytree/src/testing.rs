@@ -0,0 +1,24 @@
+//! Helpers of the unit tests
+use std::path::Path;
+use diesel::{prelude::*, connection::SimpleConnection, SqliteConnection};
+use crate::{AppData, config::Config};
+
+/// In-memory database with every migration applied
+pub fn db() -> SqliteConnection {
+    let mut db = SqliteConnection::establish(":memory:").unwrap();
+    let mut migrations = std::fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    migrations.sort();
+    for migration in migrations {
+        db.batch_execute(&std::fs::read_to_string(migration.join("up.sql")).unwrap()).unwrap();
+    }
+    db
+}
+
+/// Application data with the default configuration and the built-in templates
+pub fn app_data() -> AppData<'static> {
+    AppData::new(Config::default()).unwrap()
+}
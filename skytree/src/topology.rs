@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use actix_web::{web, Either, HttpResponse};
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use rest::{Crud, DbFactory, OperationError, RequestPrincipal};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use crate::{AppData, authorization, schema, skytree::{host::Host, service::Service, service_endpoint::ServiceEndpoint, service_instance::ServiceInstance, service_instance_relation::ServiceInstanceRelation, service_relation::{RelationKind, ServiceRelation}}};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Nodes and edges
+    #[default]
+    Json,
+    /// Graphviz digraph
+    Dot,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphQuery {
+    #[serde(default)]
+    pub format: Format,
+}
+
+/// Service instances `instance` is related to, by relation kind, with their endpoints.
+///
+/// Relations between services relate every instance of the source service to every instance of the target service.
+/// Every kind is present, so roles can iterate over e.g. `relations.depends_on` without checking for it
+pub fn related(db: &mut SqliteConnection, instance: &ServiceInstance) -> anyhow::Result<Map<String, Value>> {
+    let mut targets = RelationKind::ALL.iter().map(|kind| (kind.to_string(), Vec::<i32>::new())).collect::<BTreeMap<String, Vec<i32>>>();
+    for relation in ServiceInstanceRelation::from_instance(db, instance.id)? {
+        targets.entry(relation.kind).or_default().push(relation.target);
+    }
+    for relation in ServiceRelation::from_service(db, instance.service)? {
+        let instances = ServiceInstance::of_service(db, relation.target)?;
+        targets.entry(relation.kind).or_default().extend(instances.iter().map(|instance| instance.id));
+    }
+    let mut related = Map::new();
+    for (kind, ids) in targets {
+        let mut instances = Vec::new();
+        let targets = schema::service_instance::table
+            .filter(schema::service_instance::id.eq_any(&ids))
+            .order(schema::service_instance::id)
+            .load::<ServiceInstance>(db)?;
+        for target in targets {
+            let service = Service::db_fetch(db, target.service)?;
+            let host = Host::db_fetch(db, target.host)?;
+            let endpoints = ServiceEndpoint::of_instance(db, target.id)?.iter().map(ServiceEndpoint::variable).collect::<Vec<Value>>();
+            instances.push(json!({ "id": target.id, "name": target.name, "service": service.name, "host": host.name, "endpoints": endpoints }));
+        }
+        related.insert(kind, Value::Array(instances));
+    }
+    Ok(related)
+}
+
+fn service_node(id: i32) -> String {
+    format!("service_{}", id)
+}
+
+fn instance_node(id: i32) -> String {
+    format!("service_instance_{}", id)
+}
+
+/// The whole service topology: services and service instances as nodes, relations and `instance_of` links from instances to their service as edges
+pub fn graph(db: &mut SqliteConnection) -> anyhow::Result<Value> {
+    let services = schema::service::table.order(schema::service::id).load::<Service>(db)?;
+    let instances = schema::service_instance::table.order(schema::service_instance::id).load::<ServiceInstance>(db)?;
+    let service_relations = schema::service_relation::table.order(schema::service_relation::id).load::<ServiceRelation>(db)?;
+    let instance_relations = schema::service_instance_relation::table.order(schema::service_instance_relation::id).load::<ServiceInstanceRelation>(db)?;
+    let mut nodes = Vec::new();
+    for service in &services {
+        nodes.push(json!({ "id": service_node(service.id), "type": "service", "name": service.name }));
+    }
+    for instance in &instances {
+        nodes.push(json!({ "id": instance_node(instance.id), "type": "service_instance", "name": instance.name, "host": instance.host }));
+    }
+    let mut edges = Vec::new();
+    for instance in &instances {
+        edges.push(json!({ "source": instance_node(instance.id), "target": service_node(instance.service), "kind": "instance_of" }));
+    }
+    for relation in &service_relations {
+        edges.push(json!({ "source": service_node(relation.source), "target": service_node(relation.target), "kind": relation.kind }));
+    }
+    for relation in &instance_relations {
+        edges.push(json!({ "source": instance_node(relation.source), "target": instance_node(relation.target), "kind": relation.kind }));
+    }
+    Ok(json!({ "nodes": nodes, "edges": edges }))
+}
+
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The [graph] as a Graphviz digraph, with a cluster per service holding the service and its instances
+pub fn dot(graph: &Value) -> String {
+    let nodes = graph["nodes"].as_array().cloned().unwrap_or_default();
+    let edges = graph["edges"].as_array().cloned().unwrap_or_default();
+    let label = |node: &Value| node["name"].as_str().map(str::to_string).unwrap_or_else(|| node["id"].as_str().unwrap_or_default().to_string());
+    let mut dot = String::from("digraph services {\n");
+    for service in nodes.iter().filter(|node| node["type"] == "service") {
+        let id = service["id"].as_str().unwrap_or_default();
+        let _ = writeln!(dot, "  subgraph {} {{\n    label={};", quoted(&format!("cluster_{}", id)), quoted(&label(service)));
+        let _ = writeln!(dot, "    {} [label={}, shape=box];", quoted(id), quoted(&label(service)));
+        for edge in edges.iter().filter(|edge| edge["kind"] == "instance_of" && edge["target"] == id) {
+            let instance = nodes.iter().find(|node| node["id"] == edge["source"]).unwrap_or(&Value::Null);
+            let _ = writeln!(dot, "    {} [label={}];", quoted(edge["source"].as_str().unwrap_or_default()), quoted(&label(instance)));
+        }
+        dot.push_str("  }\n");
+    }
+    for edge in &edges {
+        let source = quoted(edge["source"].as_str().unwrap_or_default());
+        let target = quoted(edge["target"].as_str().unwrap_or_default());
+        let _ = match edge["kind"].as_str().unwrap_or_default() {
+            "instance_of" => writeln!(dot, "  {} -> {} [style=dashed, arrowhead=none];", source, target),
+            kind => writeln!(dot, "  {} -> {} [label={}];", source, target, quoted(kind)),
+        };
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// `GET /service_graph`: the service topology, as JSON or with `?format=dot` for Graphviz
+pub async fn get(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, query: web::Query<GraphQuery>) -> Either<HttpResponse, Responder<AppData<'static>>> {
+    let principal = principal.map(|principal| principal.into_inner());
+    let format = query.format;
+    let result = web::block(move || -> Result<String, OperationError> {
+        let mut db = app_data.db()?;
+        authorization::require_viewer(&mut db, principal.as_ref()).map_err(OperationError::Forbidden)?;
+        let graph = graph(&mut db)?;
+        Ok(match format {
+            Format::Json => graph.to_string(),
+            Format::Dot => dot(&graph),
+        })
+    }).await;
+    match result {
+        Ok(Ok(body)) => Either::Left(HttpResponse::Ok()
+            .content_type(match format {
+                Format::Json => "application/json",
+                Format::Dot => "text/vnd.graphviz",
+            })
+            .body(body)),
+        Ok(Err(err)) => Either::Right(err.into()),
+        Err(err) => Either::Right(Responder::<AppData<'static>>::from(anyhow::Error::from(err))),
+    }
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.route("/service_graph", web::get().to(get));
+}
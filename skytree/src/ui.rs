@@ -0,0 +1,162 @@
+use actix_web::web;
+use diesel::{SqliteConnection, RunQueryDsl, QueryDsl};
+use negotiated::Responder;
+use rest::{Crud, DbFactory, RequestPrincipal};
+use serde::Serialize;
+use crate::{AppData, auth::User, authorization::Role, schema, skytree::{host::Host, host_group::HostGroup, role_grant::RoleGrant, service::Service, service_endpoint::ServiceEndpoint, service_instance::ServiceInstance, service_instance_relation::ServiceInstanceRelation, service_relation::{RelationKind, ServiceRelation}, variable::Variable}};
+
+/// HTML form pages of a resource. Forms are rendered with the `<resource>/new` and `<resource>/edit` templates
+pub trait Form<NewT>: Crud<Self, NewT, SqliteConnection> + rest::RestAuthorize<Self, NewT, AppData<'static>, SqliteConnection> + Serialize + serde::de::DeserializeOwned + Send + Sized + 'static {
+    const RESOURCE: &'static str;
+    /// Additional template context for the forms, such as the options of select inputs
+    fn form_context(_db: &mut SqliteConnection) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::Value::Null)
+    }
+}
+
+fn templated(responder: Responder<AppData<'static>>, resource: &str, action: &str) -> Responder<AppData<'static>> {
+    responder
+        .with_template(&format!("{}/{}", resource, action))
+        .with_error_template(&format!("{}/error", resource))
+}
+
+pub async fn index() -> Responder<AppData<'static>> {
+    Responder::<AppData<'static>>::default().with_template("index")
+}
+
+pub async fn new_form<T, NewT>(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal) -> Responder<AppData<'static>>
+where T: Form<NewT>
+{
+    let principal = principal.map(|principal| principal.into_inner());
+    let responder = Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = T::authorize_list(&app_data, &mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        match T::form_context(&mut db) {
+            Ok(context) => Responder::<AppData<'static>>::default().with_context(context),
+            Err(err) => err.into()
+        }
+    }).await);
+    templated(responder, T::RESOURCE, "new")
+}
+
+pub async fn edit_form<T, NewT>(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, id: web::Path<i32>) -> Responder<AppData<'static>>
+where T: Form<NewT>
+{
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    let responder = Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = T::authorize_get(&app_data, &mut db, principal.as_ref(), id) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        match (T::db_fetch(&mut db, id), T::form_context(&mut db)) {
+            (Ok(object), Ok(context)) => Responder::<AppData<'static>>::from(anyhow::Result::<T>::Ok(object)).with_context(context),
+            (Err(err), _) | (_, Err(err)) => err.into()
+        }
+    }).await);
+    templated(responder, T::RESOURCE, "edit")
+}
+
+impl Form<crate::skytree::host_group::NewHostGroup> for HostGroup {
+    const RESOURCE: &'static str = "host_group";
+    fn form_context(db: &mut SqliteConnection) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "host_groups": schema::host_group::table.order(schema::host_group::dsl::name).load::<HostGroup>(db)?,
+        }))
+    }
+}
+impl Form<crate::skytree::role_grant::NewRoleGrant> for RoleGrant {
+    const RESOURCE: &'static str = "role_grant";
+    fn form_context(db: &mut SqliteConnection) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "users": schema::user::table.order(schema::user::dsl::name).load::<User>(db)?,
+            "host_groups": schema::host_group::table.order(schema::host_group::dsl::name).load::<HostGroup>(db)?,
+            "roles": [Role::Viewer, Role::Editor, Role::Admin],
+        }))
+    }
+}
+impl Form<crate::skytree::host::NewHost> for Host {
+    const RESOURCE: &'static str = "host";
+}
+impl Form<crate::skytree::service::NewService> for Service {
+    const RESOURCE: &'static str = "service";
+    fn form_context(db: &mut SqliteConnection) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "services": schema::service::table.order(schema::service::dsl::name).load::<Service>(db)?,
+        }))
+    }
+}
+impl Form<crate::skytree::service_instance::NewServiceInstance> for ServiceInstance {
+    const RESOURCE: &'static str = "service_instance";
+    fn form_context(db: &mut SqliteConnection) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "hosts": schema::host::table.order(schema::host::dsl::name).load::<Host>(db)?,
+            "services": schema::service::table.order(schema::service::dsl::name).load::<Service>(db)?,
+        }))
+    }
+}
+impl Form<crate::skytree::service_endpoint::NewServiceEndpoint> for ServiceEndpoint {
+    const RESOURCE: &'static str = "service_endpoint";
+    fn form_context(db: &mut SqliteConnection) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "service_instances": schema::service_instance::table.order(schema::service_instance::dsl::name).load::<ServiceInstance>(db)?,
+        }))
+    }
+}
+impl Form<crate::skytree::service_relation::NewServiceRelation> for ServiceRelation {
+    const RESOURCE: &'static str = "service_relation";
+    fn form_context(db: &mut SqliteConnection) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "services": schema::service::table.order(schema::service::dsl::name).load::<Service>(db)?,
+            "kinds": RelationKind::ALL,
+        }))
+    }
+}
+impl Form<crate::skytree::service_instance_relation::NewServiceInstanceRelation> for ServiceInstanceRelation {
+    const RESOURCE: &'static str = "service_instance_relation";
+    fn form_context(db: &mut SqliteConnection) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "service_instances": schema::service_instance::table.order(schema::service_instance::dsl::name).load::<ServiceInstance>(db)?,
+            "kinds": RelationKind::ALL,
+        }))
+    }
+}
+impl Form<crate::skytree::variable::NewVariable> for Variable {
+    const RESOURCE: &'static str = "variable";
+}
+
+/// Registers the REST routes of a resource, along with its HTML form pages.
+///
+/// Collection paths take bulk requests: `POST /<resource>s` with an array of new objects, `PATCH /<resource>s` with an array of objects,
+/// and `DELETE /<resource>s?id=1&id=2`.
+///
+/// HTML forms can only POST, so updates are also accepted on `POST /<resource>/{id}` and deletes on `POST /<resource>/{id}/delete`
+pub fn resource<T, NewT>(config: &mut web::ServiceConfig)
+where T: Form<NewT> 
+        + rest::Rest<T, NewT, AppData<'static>, SqliteConnection> 
+        + rest::RestCollection<rest::RestCollectionGetParameters, AppData<'static>, SqliteConnection>,
+      NewT: serde::de::DeserializeOwned + 'static
+{
+    let resource = T::RESOURCE;
+    config
+        .route(&format!("/{}s", resource), web::get().to(<T as rest::RestCollection<rest::RestCollectionGetParameters, AppData<'static>, SqliteConnection>>::get))
+        .route(&format!("/{}s", resource), web::post().to(<T as rest::Rest<T, NewT, AppData<'static>, SqliteConnection>>::bulk_post))
+        .route(&format!("/{}s", resource), web::patch().to(<T as rest::Rest<T, NewT, AppData<'static>, SqliteConnection>>::bulk_patch))
+        .route(&format!("/{}s", resource), web::delete().to(<T as rest::Rest<T, NewT, AppData<'static>, SqliteConnection>>::bulk_delete))
+        .route(&format!("/{}/new", resource), web::get().to(new_form::<T, NewT>))
+        .route(&format!("/{}/{{id}}/edit", resource), web::get().to(edit_form::<T, NewT>))
+        .route(&format!("/{}", resource), web::post().to(<T as rest::Rest<T, NewT, AppData<'static>, SqliteConnection>>::post))
+        .route(&format!("/{}/{{id}}", resource), web::get().to(<T as rest::Rest<T, NewT, AppData<'static>, SqliteConnection>>::get))
+        .route(&format!("/{}/{{id}}", resource), web::put().to(<T as rest::Rest<T, NewT, AppData<'static>, SqliteConnection>>::put))
+        .route(&format!("/{}/{{id}}", resource), web::post().to(<T as rest::Rest<T, NewT, AppData<'static>, SqliteConnection>>::put))
+        .route(&format!("/{}/{{id}}", resource), web::delete().to(<T as rest::Rest<T, NewT, AppData<'static>, SqliteConnection>>::delete))
+        .route(&format!("/{}/{{id}}/delete", resource), web::post().to(<T as rest::Rest<T, NewT, AppData<'static>, SqliteConnection>>::delete));
+}
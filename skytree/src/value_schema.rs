@@ -0,0 +1,223 @@
+use std::fmt;
+use anyhow::{anyhow, bail};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Keywords of the JSON Schema subset supported for variable values
+const KEYWORDS: &[&str] = &["type", "enum", "const", "pattern", "minLength", "maxLength", "minimum", "maximum", "minItems", "maxItems", "items", "properties", "required", "additionalProperties", "description", "title"];
+const TYPES: &[&str] = &["null", "boolean", "integer", "number", "string", "array", "object"];
+
+/// Violation of a schema, at `path` in the value, as a JSON pointer
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "value{} {}", self.path, self.message)
+    }
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_i64() || number.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn types(schema: &serde_json::Map<String, Value>) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(|name| name.as_str()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Checks that `schema` only uses the supported keywords, with values of the right type
+pub fn check(schema: &Value) -> anyhow::Result<()> {
+    check_at(schema, "")
+}
+
+fn check_at(schema: &Value, path: &str) -> anyhow::Result<()> {
+    let Value::Object(schema) = schema else { bail!("Schema{} must be an object", at(path)) };
+    for (keyword, value) in schema {
+        let valid = match keyword.as_str() {
+            "type" => match value {
+                Value::String(name) => TYPES.contains(&name.as_str()),
+                Value::Array(names) => names.iter().all(|name| name.as_str().is_some_and(|name| TYPES.contains(&name))),
+                _ => false,
+            },
+            "enum" => value.is_array(),
+            "pattern" => {
+                let pattern = value.as_str().ok_or_else(|| anyhow!("pattern{} must be a string", at(path)))?;
+                Regex::new(pattern).map_err(|err| anyhow!("Invalid pattern{}: {}", at(path), err))?;
+                true
+            },
+            "minLength" | "maxLength" | "minItems" | "maxItems" => value.is_u64(),
+            "minimum" | "maximum" => value.is_number(),
+            "items" => { check_at(value, &format!("{}/items", path))?; true },
+            "properties" => match value {
+                Value::Object(properties) => {
+                    for (name, property) in properties {
+                        check_at(property, &format!("{}/properties/{}", path, name))?;
+                    }
+                    true
+                },
+                _ => false,
+            },
+            "required" => value.as_array().is_some_and(|names| names.iter().all(Value::is_string)),
+            "additionalProperties" => match value {
+                Value::Bool(_) => true,
+                schema => { check_at(schema, &format!("{}/additionalProperties", path))?; true },
+            },
+            "const" | "description" | "title" => true,
+            keyword => bail!("Unsupported schema keyword {}{}, expected one of {}", keyword, at(path), KEYWORDS.join(", ")),
+        };
+        if !valid {
+            bail!("Invalid value for schema keyword {}{}", keyword, at(path));
+        }
+    }
+    Ok(())
+}
+
+fn at(path: &str) -> String {
+    if path.is_empty() { String::new() } else { format!(" at {}", path) }
+}
+
+/// Violations of `schema` by `value`. The schema is expected to have passed [check]
+pub fn validate(schema: &Value, value: &Value) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "", &mut errors);
+    errors
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str, errors: &mut Vec<FieldError>) {
+    let Value::Object(schema) = schema else { return };
+    let mut fail = |message: String| errors.push(FieldError { path: path.to_string(), message });
+    let expected = types(schema);
+    let actual = type_of(value);
+    if !expected.is_empty() && !expected.iter().any(|expected| *expected == actual || (*expected == "number" && actual == "integer")) {
+        fail(format!("expected {}, got {}", expected.join(" or "), actual));
+        return;
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            fail(format!("must be one of {}", allowed.iter().map(Value::to_string).collect::<Vec<String>>().join(", ")));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if constant != value {
+            fail(format!("must be {}", constant));
+        }
+    }
+    match value {
+        Value::String(string) => {
+            let length = string.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64).filter(|min| length < *min) {
+                fail(format!("must be at least {} characters long", min));
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64).filter(|max| length > *max) {
+                fail(format!("must be at most {} characters long", max));
+            }
+            if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+                if !Regex::new(pattern).is_ok_and(|regex| regex.is_match(string)) {
+                    fail(format!("must match {}", pattern));
+                }
+            }
+        },
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").filter(|min| min.as_f64().is_some_and(|min| number < min)) {
+                fail(format!("must be at least {}", min));
+            }
+            if let Some(max) = schema.get("maximum").filter(|max| max.as_f64().is_some_and(|max| number > max)) {
+                fail(format!("must be at most {}", max));
+            }
+        },
+        Value::Array(items) => {
+            let count = items.len() as u64;
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64).filter(|min| count < *min) {
+                fail(format!("must have at least {} items", min));
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64).filter(|max| count > *max) {
+                fail(format!("must have at most {} items", max));
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{}/{}", path, index), errors);
+                }
+            }
+        },
+        Value::Object(object) => {
+            for name in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+                if !object.contains_key(name) {
+                    errors.push(FieldError { path: format!("{}/{}", path, name), message: "is required".to_string() });
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, property) in object {
+                let property_path = format!("{}/{}", path, name.replace('~', "~0").replace('/', "~1"));
+                match (properties.and_then(|properties| properties.get(name)), schema.get("additionalProperties")) {
+                    (Some(property_schema), _) => validate_at(property_schema, property, &property_path, errors),
+                    (None, Some(Value::Bool(false))) => errors.push(FieldError { path: property_path, message: "is not allowed".to_string() }),
+                    (None, Some(additional)) => validate_at(additional, property, &property_path, errors),
+                    (None, None) => (),
+                }
+            }
+        },
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn violations(schema: Value, value: Value) -> Vec<String> {
+        check(&schema).unwrap();
+        validate(&schema, &value).iter().map(FieldError::to_string).collect()
+    }
+
+    #[test]
+    fn checks_keywords() {
+        assert!(check(&json!({ "type": ["string", "null"], "pattern": "^[a-z]+$", "items": { "type": "integer" } })).is_ok());
+        assert!(check(&json!({ "format": "ipv4" })).is_err());
+        assert!(check(&json!({ "type": "text" })).is_err());
+        assert!(check(&json!({ "pattern": "(" })).is_err());
+        assert!(check(&json!({ "properties": { "port": { "minimum": "1" } } })).is_err());
+        assert!(check(&json!([])).is_err());
+    }
+
+    #[test]
+    fn validates_scalars() {
+        assert!(violations(json!({ "type": "number" }), json!(8080)).is_empty());
+        assert_eq!(violations(json!({ "type": "integer" }), json!(1.5)), vec!["value expected integer, got number"]);
+        assert_eq!(violations(json!({ "type": "integer", "minimum": 1, "maximum": 65535 }), json!(70000)), vec!["value must be at most 65535"]);
+        assert_eq!(violations(json!({ "type": "string", "minLength": 2, "pattern": "^[a-z]+$" }), json!("A")), vec!["value must be at least 2 characters long", "value must match ^[a-z]+$"]);
+        assert_eq!(violations(json!({ "enum": ["tcp", "udp"] }), json!("sctp")), vec![r#"value must be one of "tcp", "udp""#]);
+        assert_eq!(violations(json!({ "const": true }), json!(false)), vec!["value must be true"]);
+    }
+
+    #[test]
+    fn reports_nested_paths() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name"],
+            "properties": { "name": { "type": "string" }, "ports": { "type": "array", "maxItems": 2, "items": { "type": "integer" } } },
+            "additionalProperties": false,
+        });
+        let paths = |value: Value| validate(&schema, &value).into_iter().map(|error| error.path).collect::<Vec<String>>();
+        assert!(paths(json!({ "name": "web", "ports": [80, 443] })).is_empty());
+        assert_eq!(paths(json!({ "ports": [80, "443"], "a/b": 1 })), vec!["/name", "/a~1b", "/ports/1"]);
+        assert_eq!(paths(json!({ "name": "web", "ports": [1, 2, 3] })), vec!["/ports"]);
+    }
+}
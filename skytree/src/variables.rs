@@ -0,0 +1,650 @@
+use std::collections::{HashMap, HashSet};
+use actix_web::{http::StatusCode, web};
+use anyhow::anyhow;
+use diesel::{prelude::*, SqliteConnection};
+use negotiated::Responder;
+use rest::{Audit, AuditAction, AuditEntry, Crud, DbFactory, JsonOrForm, OperationError, Principal, RequestId, RequestPrincipal};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::{AppData, audit, facts, history, smart_groups, secrets::{self, Disclosure, SecretKey}, value_schema::FieldError, authorization::{self, Role, Scope}, schema, skytree::{host::Host, host_group::HostGroup, service_instance::ServiceInstance, variable::{HashBehaviour, Variable}}};
+
+/// Entry a variable value is set on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    HostGroup(i32),
+    Host(i32),
+    Service(i32),
+    ServiceInstance(i32),
+}
+impl Owner {
+    /// Owner of resource `resource`, named as in its REST path
+    pub fn of(resource: &str, id: i32) -> anyhow::Result<Owner> {
+        match resource {
+            "host_group" => Ok(Owner::HostGroup(id)),
+            "host" => Ok(Owner::Host(id)),
+            "service" => Ok(Owner::Service(id)),
+            "service_instance" => Ok(Owner::ServiceInstance(id)),
+            _ => Err(anyhow!("{} entries have no variables", resource)),
+        }
+    }
+    pub fn resource(&self) -> &'static str {
+        match self {
+            Owner::HostGroup(_) => "host_group",
+            Owner::Host(_) => "host",
+            Owner::Service(_) => "service",
+            Owner::ServiceInstance(_) => "service_instance",
+        }
+    }
+    pub fn id(&self) -> i32 {
+        match self {
+            Owner::HostGroup(id) | Owner::Host(id) | Owner::Service(id) | Owner::ServiceInstance(id) => *id,
+        }
+    }
+    /// Whether the entry exists
+    fn exists(&self, db: &mut SqliteConnection) -> anyhow::Result<bool> {
+        Ok(match *self {
+            Owner::HostGroup(id) => diesel::select(diesel::dsl::exists(schema::host_group::table.find(id))).get_result(db)?,
+            Owner::Host(id) => diesel::select(diesel::dsl::exists(schema::host::table.find(id))).get_result(db)?,
+            Owner::Service(id) => diesel::select(diesel::dsl::exists(schema::service::table.find(id))).get_result(db)?,
+            Owner::ServiceInstance(id) => diesel::select(diesel::dsl::exists(schema::service_instance::table.find(id))).get_result(db)?,
+        })
+    }
+    fn scope(&self, db: &mut SqliteConnection) -> anyhow::Result<Scope> {
+        Ok(match self {
+            Owner::HostGroup(group) => Scope::group(Some(*group)),
+            Owner::Host(host) => Scope::host(db, *host)?,
+            Owner::Service(_) => Scope::Global,
+            Owner::ServiceInstance(instance) => {
+                let host = ServiceInstance::db_fetch(db, *instance)?.host;
+                Scope::host(db, host)?
+            },
+        })
+    }
+    /// Values are changed by the editors of their owner
+    fn authorize_write(&self, db: &mut SqliteConnection, principal: Option<&Principal>) -> anyhow::Result<()> {
+        let scope = self.scope(db)?;
+        authorization::require(db, principal, Role::Editor, scope)
+    }
+    /// Secret values are revealed to the admins of their owner
+    fn authorize_reveal(&self, db: &mut SqliteConnection, principal: Option<&Principal>) -> anyhow::Result<()> {
+        let scope = self.scope(db)?;
+        authorization::require(db, principal, Role::Admin, scope)
+    }
+}
+
+/// Value of a variable set on an entry
+#[derive(Debug, Clone, Serialize)]
+pub struct VariableValue {
+    pub variable: i32,
+    pub name: Option<String>,
+    pub secret: bool,
+    pub value: Value,
+}
+
+fn parsed(rows: Vec<(i32, Option<String>, bool, String)>) -> anyhow::Result<Vec<VariableValue>> {
+    rows.into_iter()
+        .map(|(variable, name, secret, value)| Ok(VariableValue { variable, name, secret, value: serde_json::from_str(&value)? }))
+        .collect()
+}
+
+/// Values set on `owner`, by variable name, values of secret variables output according to `disclosure`
+pub fn values(db: &mut SqliteConnection, owner: Owner, disclosure: &Disclosure) -> anyhow::Result<Vec<VariableValue>> {
+    stored(db, owner)?
+        .into_iter()
+        .map(|value| match value.secret {
+            true => Ok(VariableValue { value: disclosure.disclose_stored(&value.value)?, ..value }),
+            false => Ok(value),
+        })
+        .collect()
+}
+
+/// Values set on `owner` as stored, values of secret variables sealed
+pub fn stored(db: &mut SqliteConnection, owner: Owner) -> anyhow::Result<Vec<VariableValue>> {
+    let rows = match owner {
+        Owner::HostGroup(group) => schema::host_group_variable::table
+            .inner_join(schema::variable::table)
+            .filter(schema::host_group_variable::group.eq(group))
+            .select((schema::variable::id, schema::variable::name, schema::variable::secret, schema::host_group_variable::value))
+            .order(schema::variable::name)
+            .load::<(i32, Option<String>, bool, String)>(db)?,
+        Owner::Host(host) => schema::host_variable::table
+            .inner_join(schema::variable::table)
+            .filter(schema::host_variable::host.eq(host))
+            .select((schema::variable::id, schema::variable::name, schema::variable::secret, schema::host_variable::value))
+            .order(schema::variable::name)
+            .load::<(i32, Option<String>, bool, String)>(db)?,
+        Owner::Service(service) => schema::service_variable::table
+            .inner_join(schema::variable::table)
+            .filter(schema::service_variable::service.eq(service))
+            .select((schema::variable::id, schema::variable::name, schema::variable::secret, schema::service_variable::value))
+            .order(schema::variable::name)
+            .load::<(i32, Option<String>, bool, String)>(db)?,
+        Owner::ServiceInstance(instance) => schema::service_instance_variable::table
+            .inner_join(schema::variable::table)
+            .filter(schema::service_instance_variable::instance.eq(instance))
+            .select((schema::variable::id, schema::variable::name, schema::variable::secret, schema::service_instance_variable::value))
+            .order(schema::variable::name)
+            .load::<(i32, Option<String>, bool, String)>(db)?,
+    };
+    parsed(rows)
+}
+
+/// Value of `variable` set on `owner` as stored, if any
+pub fn value(db: &mut SqliteConnection, owner: Owner, variable: i32) -> anyhow::Result<Option<Value>> {
+    Ok(stored(db, owner)?.into_iter().find(|value| value.variable == variable).map(|value| value.value))
+}
+
+/// Sets the value of `variable` on `owner`, replacing the current one. Values of secret variables are expected sealed
+pub fn set_value(db: &mut SqliteConnection, owner: Owner, variable: i32, value: &Value) -> anyhow::Result<()> {
+    let value = value.to_string();
+    match owner {
+        Owner::HostGroup(group) => diesel::replace_into(schema::host_group_variable::table)
+            .values((schema::host_group_variable::variable.eq(variable), schema::host_group_variable::group.eq(group), schema::host_group_variable::value.eq(value)))
+            .execute(db)?,
+        Owner::Host(host) => diesel::replace_into(schema::host_variable::table)
+            .values((schema::host_variable::variable.eq(variable), schema::host_variable::host.eq(host), schema::host_variable::value.eq(value)))
+            .execute(db)?,
+        Owner::Service(service) => diesel::replace_into(schema::service_variable::table)
+            .values((schema::service_variable::variable.eq(variable), schema::service_variable::service.eq(service), schema::service_variable::value.eq(value)))
+            .execute(db)?,
+        Owner::ServiceInstance(instance) => diesel::replace_into(schema::service_instance_variable::table)
+            .values((schema::service_instance_variable::variable.eq(variable), schema::service_instance_variable::instance.eq(instance), schema::service_instance_variable::value.eq(value)))
+            .execute(db)?,
+    };
+    Ok(())
+}
+
+/// Removes the value of `variable` from `owner`
+pub fn unset_value(db: &mut SqliteConnection, owner: Owner, variable: i32) -> anyhow::Result<()> {
+    match owner {
+        Owner::HostGroup(group) => diesel::delete(schema::host_group_variable::table
+            .filter(schema::host_group_variable::variable.eq(variable))
+            .filter(schema::host_group_variable::group.eq(group)))
+            .execute(db)?,
+        Owner::Host(host) => diesel::delete(schema::host_variable::table
+            .filter(schema::host_variable::variable.eq(variable))
+            .filter(schema::host_variable::host.eq(host)))
+            .execute(db)?,
+        Owner::Service(service) => diesel::delete(schema::service_variable::table
+            .filter(schema::service_variable::variable.eq(variable))
+            .filter(schema::service_variable::service.eq(service)))
+            .execute(db)?,
+        Owner::ServiceInstance(instance) => diesel::delete(schema::service_instance_variable::table
+            .filter(schema::service_instance_variable::variable.eq(variable))
+            .filter(schema::service_instance_variable::instance.eq(instance)))
+            .execute(db)?,
+    };
+    Ok(())
+}
+
+/// Variable given by id or by name
+pub fn find_variable(db: &mut SqliteConnection, variable: &str) -> anyhow::Result<Variable> {
+    let found = match variable.parse::<i32>() {
+        Ok(id) => schema::variable::table.find(id).first::<Variable>(db).optional()?,
+        Err(_) => schema::variable::table.filter(schema::variable::name.eq(variable)).first::<Variable>(db).optional()?,
+    };
+    found.ok_or_else(|| anyhow!("Unknown variable {}", variable))
+}
+
+/// Groups whose variables apply to the members of `member_of`, in increasing order of precedence: less deep groups first, then by name, like Ansible
+fn precedence(db: &mut SqliteConnection, member_of: Vec<HostGroup>) -> anyhow::Result<Vec<HostGroup>> {
+    let mut groups: Vec<(usize, HostGroup)> = Vec::new();
+    for group in member_of {
+        let mut lineage = HostGroup::ancestors(db, &group)?;
+        lineage.push(group);
+        for (depth, group) in lineage.into_iter().enumerate() {
+            if !groups.iter().any(|(_, known)| known.id == group.id) {
+                groups.push((depth, group));
+            }
+        }
+    }
+    groups.sort_by(|(depth, group), (other_depth, other)| (depth, &group.name, group.id).cmp(&(other_depth, &other.name, other.id)));
+    Ok(groups.into_iter().map(|(_, group)| group).collect())
+}
+
+/// Variables of a host, resolved from its groups, smart groups included, and their ancestors down to the host itself.
+///
+/// More specific values replace less specific ones, or are deep merged into them for variables with the `merge` hash behaviour.
+/// Values of secret variables are resolved decrypted, then output according to `disclosure`. The facts of the host come last
+pub fn effective(db: &mut SqliteConnection, host: i32, disclosure: &Disclosure) -> anyhow::Result<serde_json::Map<String, Value>> {
+    let mut groups = Host::groups(db, host)?;
+    groups.extend(smart_groups::groups_of(db, host)?);
+    let mut resolved = resolve(db, host, groups, disclosure)?;
+    facts::overlay(&mut resolved, facts::stored(db, host)?.facts);
+    Ok(resolved)
+}
+
+/// Variables of a host resolved from the groups it is explicitly a member of, without its facts
+pub fn explicit(db: &mut SqliteConnection, host: i32, disclosure: &Disclosure) -> anyhow::Result<serde_json::Map<String, Value>> {
+    let groups = Host::groups(db, host)?;
+    resolve(db, host, groups, disclosure)
+}
+
+fn resolve(db: &mut SqliteConnection, host: i32, member_of: Vec<HostGroup>, disclosure: &Disclosure) -> anyhow::Result<serde_json::Map<String, Value>> {
+    let behaviours = schema::variable::table
+        .load::<Variable>(db)?
+        .into_iter()
+        .map(|variable| (variable.id, variable.hash_behaviour()))
+        .collect::<HashMap<i32, HashBehaviour>>();
+    let mut owners = precedence(db, member_of)?.into_iter().map(|group| Owner::HostGroup(group.id)).collect::<Vec<Owner>>();
+    owners.push(Owner::Host(host));
+    let mut resolved = serde_json::Map::new();
+    let mut secrets = HashSet::new();
+    for owner in owners {
+        for VariableValue { variable, name, secret, value } in stored(db, owner)? {
+            let Some(name) = name else { continue };
+            let value = match (secret, disclosure.key()) {
+                (false, _) => value,
+                (true, Some(key)) => key.unseal(&value)?,
+                (true, None) => Value::Null,
+            };
+            if secret {
+                secrets.insert(name.clone());
+            }
+            match resolved.get_mut(&name) {
+                Some(current) => behaviours.get(&variable).copied().unwrap_or(HashBehaviour::Replace).combine(current, value),
+                None => { resolved.insert(name, value); },
+            }
+        }
+    }
+    for name in secrets {
+        if let Some(value) = resolved.get_mut(&name) {
+            *value = disclosure.disclose(value.take());
+        }
+    }
+    Ok(resolved)
+}
+
+/// Table of variable values, with its owner column and owner
+type ValueTable = (&'static str, &'static str, fn(i32) -> Owner);
+
+const VALUE_TABLES: [ValueTable; 4] = [
+    ("host_group_variable", "group", Owner::HostGroup),
+    ("host_variable", "host", Owner::Host),
+    ("service_variable", "service", Owner::Service),
+    ("service_instance_variable", "instance", Owner::ServiceInstance),
+];
+
+#[derive(QueryableByName)]
+struct StoredValue {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    variable: i32,
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    owner: i32,
+    #[diesel(sql_type = diesel::sql_types::Bool)]
+    secret: bool,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    value: String,
+}
+
+/// Seals the values of secret variables stored in clear, and decrypts the values of variables no longer secret, recording each change in the audit log. Returns the number of values changed
+pub fn reseal<A: Audit<SqliteConnection>>(audit: &A, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, key: &SecretKey) -> anyhow::Result<usize> {
+    reseal_values(audit, db, principal, request_id, key, None)
+}
+
+/// Seals the values of `variable` stored in clear when `secret`, and decrypts them otherwise, as its `secret` flag changes, recording each change in the audit log. Returns the number of values changed
+pub fn reseal_variable<A: Audit<SqliteConnection>>(audit: &A, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, key: &SecretKey, variable: i32, secret: bool) -> anyhow::Result<usize> {
+    reseal_values(audit, db, principal, request_id, key, Some((variable, secret)))
+}
+
+/// Reseals the values of every variable as its `secret` flag says, or only those of a variable as the given flag says
+fn reseal_values<A: Audit<SqliteConnection>>(audit: &A, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, key: &SecretKey, only: Option<(i32, bool)>) -> anyhow::Result<usize> {
+    let mut changed = 0;
+    for (table, column, owner) in VALUE_TABLES {
+        let filter = only.map(|(variable, _)| format!(" WHERE v.variable = {}", variable)).unwrap_or_default();
+        let rows = diesel::sql_query(format!(r#"SELECT v.variable AS variable, v."{}" AS owner, variable.secret AS secret, v.value AS value FROM {} v INNER JOIN variable ON variable.id = v.variable{}"#, column, table, filter))
+            .load::<StoredValue>(db)?;
+        for row in rows {
+            let value = serde_json::from_str::<Value>(&row.value)?;
+            let resealed = match (only.map_or(row.secret, |(_, secret)| secret), secrets::is_sealed(&value)) {
+                (true, false) => key.seal(&value)?,
+                (false, true) => key.unseal(&value)?,
+                _ => continue,
+            };
+            assign(audit, db, principal, request_id, owner(row.owner), row.variable, &resealed)?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+/// Sets the value of `variable` on `owner`, as stored, sealed for secret variables, recording it in the audit log. Callers authorize the change
+pub fn assign<A: Audit<SqliteConnection>>(audit: &A, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, owner: Owner, variable: i32, stored: &Value) -> anyhow::Result<()> {
+    let variable = Variable::db_fetch(db, variable)?;
+    let before = value(db, owner, variable.id)?;
+    set_value(db, owner, variable.id, stored)?;
+    audit.audit(db, audit_entry(principal, request_id, owner, &variable, before, Some(stored.clone())))
+}
+
+/// Removes the value of `variable` from `owner`, if set, recording it in the audit log. Callers authorize the change
+pub fn remove<A: Audit<SqliteConnection>>(audit: &A, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, owner: Owner, variable: i32) -> anyhow::Result<()> {
+    let variable = Variable::db_fetch(db, variable)?;
+    if let Some(before) = value(db, owner, variable.id)? {
+        unset_value(db, owner, variable.id)?;
+        audit.audit(db, audit_entry(principal, request_id, owner, &variable, Some(before), None))?;
+    }
+    Ok(())
+}
+
+/// Audit log entry of a change of the value of `variable` on `owner`
+fn audit_entry(principal: Option<&Principal>, request_id: &RequestId, owner: Owner, variable: &Variable, before: Option<Value>, after: Option<Value>) -> AuditEntry {
+    let action = match (&before, &after) {
+        (None, _) => AuditAction::Create,
+        (_, None) => AuditAction::Delete,
+        _ => AuditAction::Update,
+    };
+    let state = |value: Option<Value>| value.map(|value| serde_json::json!({ "variable": variable.id, "name": variable.name, "value": value }));
+    AuditEntry {
+        entity: format!("{}_variable", owner.resource()),
+        entity_id: owner.id(),
+        entity_key: Some(variable.id),
+        action,
+        before: state(before),
+        after: state(after),
+        principal: principal.cloned(),
+        request_id: request_id.0.clone(),
+    }
+}
+
+/// Value refused as not matching the schema of its variable
+#[derive(Debug)]
+pub struct InvalidValue {
+    pub variable: Option<String>,
+    pub errors: Vec<FieldError>,
+}
+impl std::fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let summary = self.errors.iter().map(FieldError::to_string).collect::<Vec<String>>().join(", ");
+        write!(f, "Value does not match the schema of variable {}: {}", self.variable.as_deref().unwrap_or_default(), summary)
+    }
+}
+impl std::error::Error for InvalidValue {}
+
+/// `422 Unprocessable Entity` listing the violations of the schema of the variable in its payload
+fn invalid(refused: InvalidValue) -> Responder<AppData<'static>> {
+    let mut responder = Responder::<AppData<'static>>::from(anyhow!("{}", refused))
+        .with_status_code(StatusCode::UNPROCESSABLE_ENTITY);
+    responder.payload = Some(Box::new(refused.errors));
+    responder
+}
+
+/// Sets the value of `variable`, by id or name, on `owner`, as an editor of the owner. Values not matching the schema of the variable fail with [InvalidValue].
+/// Values of secret variables are sealed, and redacted in the result
+pub fn set(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, owner: Owner, variable: &str, value: Value) -> Result<VariableValue, OperationError> {
+    if !owner.exists(db)? {
+        return Err(not_found(owner).into());
+    }
+    owner.authorize_write(db, principal).map_err(OperationError::Forbidden)?;
+    let variable = find_variable(db, variable)?;
+    let errors = variable.validate(&value)?;
+    if !errors.is_empty() {
+        return Err(anyhow::Error::new(InvalidValue { variable: variable.name, errors }).into());
+    }
+    let (stored, value) = match variable.secret {
+        true => (SecretKey::load(&app_data.config())?.seal(&value)?, Disclosure::Redacted.disclose(value)),
+        false => (value.clone(), value),
+    };
+    assign(app_data.get_ref(), db, principal, request_id, owner, variable.id, &stored)?;
+    Ok(VariableValue { variable: variable.id, name: variable.name, secret: variable.secret, value })
+}
+
+/// Removes the value of `variable`, by id or name, from `owner`, as an editor of the owner. Fails when it is not set. Values of secret variables are redacted in the result
+pub fn unset(app_data: &web::Data<AppData<'static>>, db: &mut SqliteConnection, principal: Option<&Principal>, request_id: &RequestId, owner: Owner, variable: &str) -> Result<VariableValue, OperationError> {
+    if !owner.exists(db)? {
+        return Err(not_found(owner).into());
+    }
+    owner.authorize_write(db, principal).map_err(OperationError::Forbidden)?;
+    let variable = find_variable(db, variable)?;
+    let before = value(db, owner, variable.id)?.ok_or_else(|| anyhow!("Variable {} is not set on {} {}", variable.id, owner.resource(), owner.id()))?;
+    remove(app_data.get_ref(), db, principal, request_id, owner, variable.id)?;
+    let value = match variable.secret {
+        true => Disclosure::Redacted.disclose(before),
+        false => before,
+    };
+    Ok(VariableValue { variable: variable.id, name: variable.name, secret: variable.secret, value })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevealQuery {
+    /// Output secret values in clear
+    #[serde(default)]
+    pub reveal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EffectiveQuery {
+    /// Output secret values in clear
+    #[serde(default)]
+    pub reveal: bool,
+    /// Variables as they were at that time, replayed from the audit log
+    pub as_of: Option<String>,
+}
+
+/// How `principal` gets the secret values of `owner`: redacted, unless revealing them was asked for and allowed
+fn disclosure(app_data: &AppData<'static>, db: &mut SqliteConnection, principal: Option<&Principal>, owner: Owner, reveal: bool) -> Result<Disclosure, OperationError> {
+    if !reveal {
+        return Ok(Disclosure::Redacted);
+    }
+    owner.authorize_reveal(db, principal).map_err(OperationError::Forbidden)?;
+    Ok(Disclosure::Revealed(SecretKey::load(&app_data.config())?))
+}
+
+/// `GET /{resource}/{id}/variables`: values set on an entry. Secret values are redacted, unless `?reveal=true` is given by an admin of the entry
+pub async fn list(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, path: web::Path<(String, i32)>, query: web::Query<RevealQuery>) -> Responder<AppData<'static>> {
+    let (resource, id) = path.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        let owner = match Owner::of(&resource, id) {
+            Ok(owner) => owner,
+            Err(err) => return err.into(),
+        };
+        match disclosure(&app_data, &mut db, principal.as_ref(), owner, query.reveal) {
+            Ok(disclosure) => values(&mut db, owner, &disclosure).into(),
+            Err(err) => err.into(),
+        }
+    }).await)
+}
+
+/// `404 Not Found` when `owner` is missing
+fn missing(db: &mut SqliteConnection, owner: Owner) -> Option<Responder<AppData<'static>>> {
+    match owner.exists(db) {
+        Ok(true) => None,
+        Ok(false) => Some(Responder::<AppData<'static>>::from(not_found(owner)).with_status_code(StatusCode::NOT_FOUND)),
+        Err(err) => Some(err.into()),
+    }
+}
+
+fn not_found(owner: Owner) -> anyhow::Error {
+    anyhow!("No {} {}", owner.resource().replace('_', " "), owner.id())
+}
+
+/// Response to a change of a value, refused values answered by [invalid]
+fn value_change(result: Result<VariableValue, OperationError>) -> Responder<AppData<'static>> {
+    match result {
+        Ok(value) => Responder::<AppData<'static>>::from(anyhow::Result::<VariableValue>::Ok(value)),
+        Err(OperationError::Failed(err)) => match err.downcast::<InvalidValue>() {
+            Ok(refused) => invalid(refused),
+            Err(err) => OperationError::Failed(err).into(),
+        },
+        Err(err) => err.into(),
+    }
+}
+
+/// `PUT /{resource}/{id}/variables/{variable}`: sets a value, given as the JSON request body. The variable is given by id or name.
+///
+/// Values not matching the schema of the variable are refused with `422 Unprocessable Entity`, the violations in the payload.
+/// Values of secret variables are sealed, and redacted in the response
+pub async fn put(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, path: web::Path<(String, i32, String)>, web::Json(value): web::Json<Value>) -> Responder<AppData<'static>> {
+    let (resource, id, variable) = path.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        let owner = match Owner::of(&resource, id) {
+            Ok(owner) => owner,
+            Err(err) => return err.into(),
+        };
+        if let Some(responder) = missing(&mut db, owner) {
+            return responder;
+        }
+        value_change(db.transaction(|db| set(&app_data, db, principal.as_ref(), &request_id, owner, &variable, value)))
+    }).await)
+}
+
+/// `DELETE /{resource}/{id}/variables/{variable}`: removes a value
+pub async fn delete(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, path: web::Path<(String, i32, String)>) -> Responder<AppData<'static>> {
+    let (resource, id, variable) = path.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        let owner = match Owner::of(&resource, id) {
+            Ok(owner) => owner,
+            Err(err) => return err.into(),
+        };
+        if let Some(responder) = missing(&mut db, owner) {
+            return responder;
+        }
+        value_change(db.transaction(|db| unset(&app_data, db, principal.as_ref(), &request_id, owner, &variable)))
+    }).await)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValueForm {
+    /// Variable, by id or name
+    pub variable: String,
+    /// Value as JSON. Text that is not JSON is taken as a string
+    pub value: String,
+}
+
+/// `POST /{resource}/{id}/variables`: sets a value from the form of the page of the entry
+pub async fn post_form(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, path: web::Path<(String, i32)>, JsonOrForm(form): JsonOrForm<ValueForm>) -> Responder<AppData<'static>> {
+    let (resource, id) = path.into_inner();
+    let owner = match Owner::of(&resource, id) {
+        Ok(owner) => owner,
+        Err(err) => return err.into(),
+    };
+    let value = serde_json::from_str(&form.value).unwrap_or(Value::String(form.value));
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Some(responder) = missing(&mut db, owner) {
+            return responder;
+        }
+        value_change(db.transaction(|db| set(&app_data, db, principal.as_ref(), &request_id, owner, &form.variable, value)))
+    }).await)
+        .with_redirect(&format!("/{}/{}", resource, id), "Variable set")
+        .with_error_template(&format!("{}/error", resource))
+}
+
+/// `POST /{resource}/{id}/variables/{variable}/delete`: removes a value from the page of the entry
+pub async fn delete_form(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, request_id: RequestId, path: web::Path<(String, i32, String)>) -> Responder<AppData<'static>> {
+    let (resource, id, variable) = path.into_inner();
+    let owner = match Owner::of(&resource, id) {
+        Ok(owner) => owner,
+        Err(err) => return err.into(),
+    };
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Some(responder) = missing(&mut db, owner) {
+            return responder;
+        }
+        value_change(db.transaction(|db| unset(&app_data, db, principal.as_ref(), &request_id, owner, &variable)))
+    }).await)
+        .with_redirect(&format!("/{}/{}", resource, id), "Variable removed")
+        .with_error_template(&format!("{}/error", resource))
+}
+
+/// `GET /host/{id}/effective_variables`: variables of a host, resolved through its groups. Secret values are redacted, unless `?reveal=true` is given by an admin of the host.
+/// `?as_of=` gives them at a point in time
+pub async fn effective_variables(app_data: web::Data<AppData<'static>>, principal: RequestPrincipal, id: web::Path<i32>, query: web::Query<EffectiveQuery>) -> Responder<AppData<'static>> {
+    let id = id.into_inner();
+    let principal = principal.map(|principal| principal.into_inner());
+    Responder::<AppData<'static>>::from(web::block(move || -> Responder<AppData<'static>> {
+        let mut db = match app_data.db() {
+            Ok(db) => db,
+            Err(err) => return err.into(),
+        };
+        if let Err(err) = authorization::require_viewer(&mut db, principal.as_ref()) {
+            return Responder::<AppData<'static>>::forbidden(err);
+        }
+        let disclosure = match disclosure(&app_data, &mut db, principal.as_ref(), Owner::Host(id), query.reveal) {
+            Ok(disclosure) => disclosure,
+            Err(err) => return err.into(),
+        };
+        if let Some(as_of) = query.as_of.as_deref().filter(|as_of| !as_of.is_empty()) {
+            let as_of = match audit::parse_time(as_of) {
+                Ok(as_of) => as_of,
+                Err(err) => return Responder::<AppData<'static>>::from(err).with_status_code(StatusCode::BAD_REQUEST),
+            };
+            db = match history::snapshot(&app_data, as_of) {
+                Ok(db) => db,
+                Err(err) => return err.into(),
+            };
+        }
+        match Host::db_fetch(&mut db, id) {
+            Ok(host) => effective(&mut db, host.id, &disclosure).into(),
+            Err(err) => err.into(),
+        }
+    }).await)
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/host/{id}/effective_variables", web::get().to(effective_variables))
+        .route("/{resource}/{id}/variables", web::get().to(list))
+        .route("/{resource}/{id}/variables", web::post().to(post_form))
+        .route("/{resource}/{id}/variables/{variable}/delete", web::post().to(delete_form))
+        .route("/{resource}/{id}/variables/{variable}", web::put().to(put))
+        .route("/{resource}/{id}/variables/{variable}", web::delete().to(delete));
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::connection::SimpleConnection;
+    use serde_json::json;
+    use super::*;
+    use crate::{audit, testing};
+
+    #[test]
+    fn values_of_one_owner_replay_separately() {
+        let mut db = testing::db();
+        let app_data = testing::app_data();
+        let request_id = RequestId("test".to_string());
+        db.batch_execute("INSERT INTO host(id, name) VALUES (1, 'web1'); INSERT INTO variable(id, name) VALUES (1, 'http_port'), (2, 'ntp_server');").unwrap();
+        assign(&app_data, &mut db, None, &request_id, Owner::Host(1), 1, &json!(80)).unwrap();
+        assign(&app_data, &mut db, None, &request_id, Owner::Host(1), 2, &json!("ntp1")).unwrap();
+        db.batch_execute("UPDATE audit_log SET timestamp = 100;").unwrap();
+        assign(&app_data, &mut db, None, &request_id, Owner::Host(1), 1, &json!(8080)).unwrap();
+        db.batch_execute("UPDATE audit_log SET timestamp = 200 WHERE timestamp != 100;").unwrap();
+
+        let replayed = |db: &mut SqliteConnection, variable: i32, as_of: i64| audit::state_at(db, "host_variable", 1, Some(variable), as_of).unwrap()["value"].clone();
+        assert_eq!(replayed(&mut db, 1, 100), json!(80));
+        assert_eq!(replayed(&mut db, 2, 100), json!("ntp1"));
+        assert_eq!(replayed(&mut db, 1, 200), json!(8080));
+        assert_eq!(replayed(&mut db, 2, 200), json!("ntp1"));
+        assert_eq!(audit::state_at(&mut db, "host_variable", 1, Some(1), 50).unwrap(), Value::Null);
+
+        let differences = audit::diff(&mut db, Some("host_variable"), 100, 200).unwrap();
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].entity_key, Some(1));
+    }
+}